@@ -0,0 +1,74 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use noteexplorer::{FileFilter, NoteCollection, NoteParser};
+use std::env::temp_dir;
+use std::{fs, path};
+
+const NOTE_COUNT: usize = 100_000;
+const NOTES_PER_DIR: usize = 1000;
+
+/// Generate a vault with `NOTE_COUNT` notes that link to each other, unless
+/// it has already been generated
+fn generate_vault() -> path::PathBuf {
+	let mut dir = temp_dir();
+	dir.push(format!("noteexplorer-bench-{}", NOTE_COUNT));
+	let done = dir.join(".generated");
+	if done.exists() {
+		return dir;
+	}
+	if dir.exists() {
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	for i in 0..NOTE_COUNT {
+		let sub_dir = dir.join(format!("{:03}", i / NOTES_PER_DIR));
+		if i % NOTES_PER_DIR == 0 {
+			fs::create_dir_all(&sub_dir).unwrap();
+		}
+		let id = 20200000000000u64 + i as u64;
+		// Links to a few pseudo-random notes, and one to a missing note
+		let links: Vec<String> = [7, 31, 1009]
+			.iter()
+			.map(|n| format!("- [[{}]]", 20200000000000u64 + ((i * n + 1) % NOTE_COUNT) as u64))
+			.collect();
+		let contents = format!(
+			"---\ntags: [bench]\n---\n\n# Note number {}\n\nSome text with a [link](https://example.com) and a #tag.\n\n## Links\n\n{}\n- [[Missing {}]]\n\n- [ ] A task\n\n## Backlinks\n\n- [[{}]]\n",
+			i,
+			links.join("\n"),
+			i % 100,
+			id + 1
+		);
+		fs::write(sub_dir.join(format!("{} Note number {}.md", id, i)), contents).unwrap();
+	}
+	fs::write(done, "").unwrap();
+	dir
+}
+
+fn collect(dir: &path::Path) -> NoteCollection {
+	let filter = FileFilter::new("md", &[]).unwrap();
+	let parser = NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+	NoteCollection::collect_files(dir, &filter, parser, None)
+}
+
+fn collect_files(c: &mut Criterion) {
+	let dir = generate_vault();
+	assert_eq!(collect(&dir).count(), NOTE_COUNT);
+
+	let mut group = c.benchmark_group("collect_files");
+	group.sample_size(10);
+	let mut thread_counts = vec![1, num_cpus()];
+	thread_counts.dedup();
+	for threads in thread_counts {
+		let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+		group.bench_with_input(BenchmarkId::new("threads", threads), &dir, |b, dir| {
+			b.iter(|| pool.install(|| collect(dir)))
+		});
+	}
+	group.finish();
+}
+
+fn num_cpus() -> usize {
+	std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+criterion_group!(benches, collect_files);
+criterion_main!(benches);
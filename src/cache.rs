@@ -0,0 +1,179 @@
+use crate::mdparse::{NoteData, NoteParser};
+use debug_print::debug_eprintln;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::UNIX_EPOCH;
+use std::{fs, io, path};
+
+pub const CACHE_FILE_NAME: &str = ".noteexplorer-cache.json";
+
+/// Changed when the cached data is parsed or stored differently
+const CACHE_VERSION: &str = concat!("2-", env!("CARGO_PKG_VERSION"));
+
+/// Modification time and size of a file, to tell if it has changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+	/// Nanoseconds since the Unix epoch
+	modified: u64,
+	size: u64,
+}
+
+impl FileStamp {
+	pub fn of(path: &path::Path) -> io::Result<FileStamp> {
+		let metadata = fs::metadata(path)?;
+		let modified = metadata
+			.modified()?
+			.duration_since(UNIX_EPOCH)
+			.map_or(0, |duration| duration.as_nanos() as u64);
+		Ok(FileStamp {
+			modified,
+			size: metadata.len(),
+		})
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+	stamp: FileStamp,
+	data: NoteData,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheContents {
+	version: String,
+	/// Parser settings that the notes were parsed with
+	settings: String,
+	/// Parsed notes, by path
+	notes: HashMap<String, CacheEntry>,
+}
+
+/// Parsed notes that are saved between runs, in a file in the notes
+/// directory. Notes are only parsed again if their files have changed.
+pub struct ParseCache {
+	path: path::PathBuf,
+	settings: String,
+	notes: HashMap<String, CacheEntry>,
+	changed: bool,
+}
+
+impl ParseCache {
+	/// Load the cache file in `root`. The cache is empty if the file is
+	/// missing or unreadable, or if the notes were parsed with other settings.
+	pub fn load(root: &path::Path, parser: &NoteParser) -> ParseCache {
+		let path = root.join(CACHE_FILE_NAME);
+		let settings = parser.settings_key();
+		let notes = match fs::read(&path) {
+			Ok(bytes) => match serde_json::from_slice::<CacheContents>(&bytes) {
+				Ok(contents) if contents.version == CACHE_VERSION && contents.settings == settings => {
+					contents.notes
+				}
+				Ok(_) => {
+					debug_eprintln!("Parse cache was made with other settings");
+					HashMap::new()
+				}
+				Err(_err) => {
+					debug_eprintln!("Couldn't read parse cache: {}", _err);
+					HashMap::new()
+				}
+			},
+			Err(_) => HashMap::new(),
+		};
+
+		ParseCache {
+			path,
+			settings,
+			notes,
+			changed: false,
+		}
+	}
+
+	/// Get the parsed data for a note, if the file hasn't changed
+	pub fn get(&self, path: &str, stamp: FileStamp) -> Option<NoteData> {
+		self.notes
+			.get(path)
+			.filter(|entry| entry.stamp == stamp)
+			.map(|entry| entry.data.clone())
+	}
+
+	pub fn insert(&mut self, path: &str, stamp: FileStamp, data: NoteData) {
+		self.notes.insert(path.to_owned(), CacheEntry { stamp, data });
+		self.changed = true;
+	}
+
+	/// Remove the notes whose paths don't match `keep`, like deleted notes
+	pub fn retain<F>(&mut self, keep: F)
+	where
+		F: Fn(&str) -> bool,
+	{
+		let count = self.notes.len();
+		self.notes.retain(|path, _| keep(path));
+		self.changed |= self.notes.len() != count;
+	}
+
+	/// Write the cache file, if any note was parsed or removed
+	pub fn save(self) -> io::Result<()> {
+		if !self.changed {
+			return Ok(());
+		}
+
+		let contents = CacheContents {
+			version: CACHE_VERSION.to_owned(),
+			settings: self.settings,
+			notes: self.notes,
+		};
+		// Write to another file first, so that the cache is never half-written
+		let tmp_path = self.path.with_extension("tmp");
+		fs::write(&tmp_path, serde_json::to_vec(&contents)?)?;
+		fs::rename(&tmp_path, &self.path)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::cache::*;
+	use std::env::temp_dir;
+
+	#[test]
+	fn parse_cache() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-cache");
+		if dir.exists() {
+			fs::remove_dir_all(&dir).unwrap();
+		}
+		fs::create_dir_all(&dir).unwrap();
+		let file = dir.join("a.md");
+		fs::write(&file, "# A\n\n[[b]]").unwrap();
+		let stamp = FileStamp::of(&file).unwrap();
+
+		let parser = NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let mut cache = ParseCache::load(&dir, &parser);
+		assert!(cache.get("a.md", stamp).is_none());
+		cache.insert("a.md", stamp, parser.parse("# A\n\n[[b]]"));
+		cache.save().unwrap();
+
+		let mut cache = ParseCache::load(&dir, &parser);
+		let data = cache.get("a.md", stamp).unwrap();
+		assert_eq!(data.titles, ["A"]);
+		assert_eq!(data.links[0].line, 3);
+		// Nothing changed, so the file isn't written again
+		cache.retain(|path| path == "a.md");
+		assert!(!cache.changed);
+
+		// Changed files are parsed again
+		let changed = FileStamp {
+			size: stamp.size + 1,
+			..stamp
+		};
+		assert!(cache.get("a.md", changed).is_none());
+
+		// Deleted files are removed
+		cache.retain(|_| false);
+		assert!(cache.changed);
+
+		// Other settings give other results
+		let parser = NoteParser::new(r"\d{8}", "## Backlinks").unwrap();
+		assert!(ParseCache::load(&dir, &parser).get("a.md", stamp).is_none());
+		let parser = NoteParser::new(r"\d{14}", "## Links").unwrap();
+		assert!(ParseCache::load(&dir, &parser).get("a.md", stamp).is_none());
+	}
+}
@@ -0,0 +1,179 @@
+use crate::note::{NoteCollection, NoteMeta};
+use serde::Serialize;
+use std::error::Error;
+use std::{fmt, str};
+
+/// Rules that can be checked with the `check` subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+	BrokenLinks,
+	BrokenAnchors,
+	MissingAttachments,
+	DuplicateIds,
+	StaleBacklinks,
+}
+
+impl Rule {
+	pub const ALL: [Rule; 5] = [
+		Rule::BrokenLinks,
+		Rule::BrokenAnchors,
+		Rule::MissingAttachments,
+		Rule::DuplicateIds,
+		Rule::StaleBacklinks,
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Rule::BrokenLinks => "broken-links",
+			Rule::BrokenAnchors => "broken-anchors",
+			Rule::MissingAttachments => "missing-attachments",
+			Rule::DuplicateIds => "duplicate-ids",
+			Rule::StaleBacklinks => "stale-backlinks",
+		}
+	}
+}
+
+impl fmt::Display for Rule {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+impl str::FromStr for Rule {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Rule::ALL
+			.iter()
+			.find(|rule| rule.name() == s)
+			.copied()
+			.ok_or_else(|| {
+				let names: Vec<&str> = Rule::ALL.iter().map(|r| r.name()).collect();
+				format!("Unknown rule \"{}\", expected one of {}", s, names.join(", "))
+			})
+	}
+}
+
+/// A problem found by a rule, in a note
+#[derive(Debug, Serialize, Default)]
+pub struct Violation {
+	pub rule: &'static str,
+	pub path: String,
+	pub line: Option<usize>,
+	pub message: String,
+}
+
+impl Violation {
+	fn new(rule: Rule, note: &NoteMeta, line: Option<usize>, message: String) -> Violation {
+		Violation {
+			rule: rule.name(),
+			path: note.path.clone(),
+			line,
+			message,
+		}
+	}
+}
+
+impl fmt::Display for Violation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.line {
+			Some(line) => write!(f, "{}:{}: {}: {}", self.path, line, self.rule, self.message),
+			None => write!(f, "{}: {}: {}", self.path, self.rule, self.message),
+		}
+	}
+}
+
+/// Returned from `run` when a rule has failed, making the program exit
+/// with status code 3
+#[derive(Debug)]
+pub struct CheckFailed {
+	pub problems: usize,
+}
+
+impl fmt::Display for CheckFailed {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} problems found", self.problems)
+	}
+}
+
+impl Error for CheckFailed {}
+
+/// Parse rule names, or get all rules if there are no names
+pub fn parse_rules(names: &[String]) -> Result<Vec<Rule>, String> {
+	if names.is_empty() {
+		Ok(Rule::ALL.to_vec())
+	} else {
+		names.iter().map(|name| name.trim().parse()).collect()
+	}
+}
+
+/// Run the rules on the notes. The backlinks sections are expected to be
+/// written with `update-backlinks`, with or without `aliased`.
+pub fn check(notes: &NoteCollection, rules: &[Rule], aliased: bool) -> Vec<Violation> {
+	let mut violations = Vec::new();
+	for rule in rules {
+		match rule {
+			Rule::BrokenLinks => {
+				for (note, link) in notes.get_broken_link_occurrences() {
+					let message = format!("{} links to an unknown note", link);
+					violations.push(Violation::new(*rule, &note, Some(link.line), message));
+				}
+			}
+			Rule::BrokenAnchors => {
+				for (note, link) in notes.get_broken_anchors() {
+					let message = format!("{} links to an unknown section", link);
+					violations.push(Violation::new(*rule, &note, Some(link.line), message));
+				}
+			}
+			Rule::MissingAttachments => {
+				for (note, link) in notes.get_broken_attachment_occurrences() {
+					let message = format!("{} links to an unknown attachment", link);
+					violations.push(Violation::new(*rule, &note, Some(link.line), message));
+				}
+			}
+			Rule::DuplicateIds => {
+				for (id, duplicates) in notes.get_duplicate_ids() {
+					for note in &duplicates {
+						let others: Vec<String> = duplicates
+							.iter()
+							.filter(|other| *other != note)
+							.map(|other| format!("\"{}\"", other.stem))
+							.collect();
+						let message = format!("The ID {} is also used in {}", id, others.join(" and "));
+						violations.push(Violation::new(*rule, note, None, message));
+					}
+				}
+			}
+			Rule::StaleBacklinks => {
+				for (note, line) in notes.get_stale_backlinks(aliased) {
+					let message = match line {
+						Some(_) => "The backlinks section is out of date".to_owned(),
+						None => "The backlinks section is missing".to_owned(),
+					};
+					violations.push(Violation::new(*rule, &note, line, message));
+				}
+			}
+		}
+	}
+	violations
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::check::*;
+
+	#[test]
+	fn rule_names() {
+		for rule in Rule::ALL {
+			assert_eq!(rule.name().parse(), Ok(rule));
+		}
+		assert!("broken_links".parse::<Rule>().is_err());
+
+		assert_eq!(parse_rules(&[]).unwrap().len(), Rule::ALL.len());
+		assert_eq!(
+			parse_rules(&["duplicate-ids".to_owned(), " broken-links".to_owned()]),
+			Ok(vec![Rule::DuplicateIds, Rule::BrokenLinks])
+		);
+		assert!(parse_rules(&["nope".to_owned()]).is_err());
+	}
+}
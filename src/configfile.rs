@@ -0,0 +1,189 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::{fs, path};
+
+pub const CONFIG_FILE_NAME: &str = ".noteexplorer.toml";
+
+/// Options that can be set in the config file, either at the top level
+/// or in a section named after a subcommand
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigOptions {
+	pub id_format: Option<String>,
+	pub backlinks_heading: Option<String>,
+	pub extension: Option<String>,
+	pub markdown_links: Option<bool>,
+	pub format: Option<String>,
+	/// Files and directories to skip
+	pub ignore: Vec<String>,
+	/// Files to use, skipping all others
+	pub include: Vec<String>,
+	pub aliased: Option<bool>,
+	pub force: Option<bool>,
+	/// Directory for `list-unused-attachments --quarantine` without a value
+	pub quarantine: Option<String>,
+	/// Rules for the check subcommand
+	pub rules: Option<Vec<String>>,
+	/// Keep parsed notes in a cache file
+	pub cache: Option<bool>,
+	/// Encoding to save changed notes in, instead of the encoding they had
+	pub normalize_encoding: Option<String>,
+	/// Parser profiles for file extensions, like `txt = "text"`
+	pub profiles: HashMap<String, String>,
+}
+
+impl ConfigOptions {
+	/// Combine with `other`, letting the options set here win.
+	/// Ignore and include patterns are added together.
+	fn or(self, other: ConfigOptions) -> ConfigOptions {
+		let mut ignore = other.ignore;
+		ignore.extend(self.ignore);
+		let mut include = other.include;
+		include.extend(self.include);
+		let mut profiles = other.profiles;
+		profiles.extend(self.profiles);
+
+		ConfigOptions {
+			id_format: self.id_format.or(other.id_format),
+			backlinks_heading: self.backlinks_heading.or(other.backlinks_heading),
+			extension: self.extension.or(other.extension),
+			markdown_links: self.markdown_links.or(other.markdown_links),
+			format: self.format.or(other.format),
+			ignore,
+			include,
+			aliased: self.aliased.or(other.aliased),
+			force: self.force.or(other.force),
+			quarantine: self.quarantine.or(other.quarantine),
+			rules: self.rules.or(other.rules),
+			cache: self.cache.or(other.cache),
+			normalize_encoding: self.normalize_encoding.or(other.normalize_encoding),
+			profiles,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct ConfigFile {
+	pub path: path::PathBuf,
+	options: ConfigOptions,
+	/// Options for each subcommand, by subcommand name
+	sections: HashMap<String, ConfigOptions>,
+}
+
+impl ConfigFile {
+	/// Look for a config file in `dir`, and then in its parent directories
+	pub fn find(dir: &path::Path) -> Result<Option<ConfigFile>, Box<dyn Error>> {
+		let dir = fs::canonicalize(dir)?;
+		for ancestor in dir.ancestors() {
+			let path = ancestor.join(CONFIG_FILE_NAME);
+			if path.is_file() {
+				return Ok(Some(ConfigFile::load(&path)?));
+			}
+		}
+		Ok(None)
+	}
+
+	/// The directory of the config file, that ignore and include patterns
+	/// containing a slash are relative to
+	pub fn dir(&self) -> &path::Path {
+		self.path.parent().unwrap_or(&self.path)
+	}
+
+	pub fn load(path: &path::Path) -> Result<ConfigFile, Box<dyn Error>> {
+		let contents = fs::read_to_string(path)?;
+		ConfigFile::parse(path, &contents)
+			.map_err(|err| format!("Couldn't read {}: {}", path.display(), err).into())
+	}
+
+	fn parse(path: &path::Path, contents: &str) -> Result<ConfigFile, Box<dyn Error>> {
+		let mut top_level = toml::value::Table::new();
+		let mut sections = HashMap::new();
+
+		// Tables are subcommand sections, everything else is a top-level
+		// option, except for the table of parser profiles
+		for (key, value) in contents.parse::<toml::Value>()?.try_into::<toml::value::Table>()? {
+			match value {
+				toml::Value::Table(table) if key != "profiles" => {
+					let options = toml::Value::Table(table)
+						.try_into()
+						.map_err(|err| format!("in section [{}]: {}", key, err))?;
+					sections.insert(key, options);
+				}
+				value => {
+					top_level.insert(key, value);
+				}
+			}
+		}
+
+		Ok(ConfigFile {
+			path: path.to_owned(),
+			options: toml::Value::Table(top_level).try_into()?,
+			sections,
+		})
+	}
+
+	/// Get the options for `command`, where the subcommand's section
+	/// overrides the top-level options
+	pub fn options(&self, command: &str) -> ConfigOptions {
+		match self.sections.get(command) {
+			Some(section) => section.clone().or(self.options.clone()),
+			None => self.options.clone(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::configfile::*;
+
+	#[test]
+	fn parse_config_file() {
+		let config = ConfigFile::parse(
+			path::Path::new(CONFIG_FILE_NAME),
+			r###"
+id-format = "\\d{8}"
+backlinks-heading = "## Backlinks"
+ignore = ["templates"]
+
+[update-backlinks]
+aliased = true
+backlinks-heading = "## Incoming"
+
+[list-tasks]
+ignore = ["archive"]
+
+[profiles]
+txt = "text"
+"###,
+		)
+		.unwrap();
+
+		let options = config.options("list-broken-links");
+		assert_eq!(options.id_format.as_deref(), Some(r"\d{8}"));
+		assert_eq!(options.backlinks_heading.as_deref(), Some("## Backlinks"));
+		assert_eq!(options.extension, None);
+		assert_eq!(options.aliased, None);
+		assert_eq!(options.ignore, ["templates"]);
+
+		let options = config.options("update-backlinks");
+		assert_eq!(options.id_format.as_deref(), Some(r"\d{8}"));
+		assert_eq!(options.backlinks_heading.as_deref(), Some("## Incoming"));
+		assert_eq!(options.aliased, Some(true));
+
+		let options = config.options("list-tasks");
+		assert_eq!(options.ignore, ["templates", "archive"]);
+		assert_eq!(options.profiles.get("txt").map(String::as_str), Some("text"));
+		assert!(!config.sections.contains_key("profiles"));
+	}
+
+	#[test]
+	fn invalid_config_file() {
+		let parse = |contents| ConfigFile::parse(path::Path::new(CONFIG_FILE_NAME), contents);
+
+		assert!(parse("unknown-option = 1").is_err());
+		assert!(parse("extension = 1").is_err());
+		assert!(parse("[update-backlinks]\naliased = \"yes\"").is_err());
+		assert!(parse("ignore = [").is_err());
+	}
+}
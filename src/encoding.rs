@@ -0,0 +1,156 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::{fmt, fs, io, path};
+
+/// Text encoding of a note file, that is used again when the file is saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileEncoding {
+	encoding: &'static Encoding,
+	/// The file starts with a UTF-16 byte order mark. A UTF-8 byte order
+	/// mark is kept in the text instead.
+	bom: bool,
+}
+
+impl FileEncoding {
+	pub const UTF8: FileEncoding = FileEncoding {
+		encoding: UTF_8,
+		bom: false,
+	};
+
+	/// Decode the contents of a file, detecting the encoding from the byte
+	/// order mark, or else by looking at the bytes
+	pub fn decode(bytes: Vec<u8>) -> io::Result<(String, FileEncoding)> {
+		let (encoding, bom) = match Encoding::for_bom(&bytes) {
+			Some((encoding, _)) if encoding != UTF_8 => (encoding, true),
+			_ => (detect(&bytes), false),
+		};
+		let text = if encoding == UTF_8 {
+			String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+		} else {
+			let skip = if bom { 2 } else { 0 };
+			encoding
+				.decode_without_bom_handling_and_without_replacement(&bytes[skip..])
+				.ok_or_else(|| {
+					io::Error::new(
+						io::ErrorKind::InvalidData,
+						format!("invalid {} text", encoding.name()),
+					)
+				})?
+				.into_owned()
+		};
+		Ok((text, FileEncoding { encoding, bom }))
+	}
+
+	/// Encode text to be saved, failing if some characters can't be written
+	/// in this encoding
+	pub fn encode(&self, text: &str) -> io::Result<Vec<u8>> {
+		// encoding_rs only writes UTF-8 for UTF-16 encodings
+		if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+			let bom = if self.bom { "\u{feff}" } else { "" };
+			let mut bytes = Vec::with_capacity(text.len() * 2 + 2);
+			for unit in bom.encode_utf16().chain(text.encode_utf16()) {
+				if self.encoding == UTF_16LE {
+					bytes.extend(unit.to_le_bytes());
+				} else {
+					bytes.extend(unit.to_be_bytes());
+				}
+			}
+			return Ok(bytes);
+		}
+
+		let (bytes, _, unmappable) = self.encoding.encode(text);
+		if unmappable {
+			return Err(io::Error::other(format!(
+				"the text contains characters that can't be written in {}",
+				self
+			)));
+		}
+		Ok(bytes.into_owned())
+	}
+}
+
+impl fmt::Display for FileEncoding {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.encoding.name())
+	}
+}
+
+/// Read a text file in any encoding
+pub fn read_text(path: &path::Path) -> io::Result<(String, FileEncoding)> {
+	FileEncoding::decode(fs::read(path)?)
+}
+
+/// Guess the encoding of text without a byte order mark
+fn detect(bytes: &[u8]) -> &'static Encoding {
+	if let Some(encoding) = detect_utf16(bytes) {
+		return encoding;
+	}
+	if std::str::from_utf8(bytes).is_ok() {
+		return UTF_8;
+	}
+	let mut detector = EncodingDetector::new();
+	detector.feed(bytes, true);
+	detector.guess(None, false)
+}
+
+/// Text in UTF-16 has a zero byte in most characters in the Latin alphabet,
+/// while other encodings almost never contain zero bytes
+fn detect_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+	if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+		return None;
+	}
+	let pairs = bytes.len() / 2;
+	let zeros_first = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+	let zeros_second = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+	if zeros_second * 2 > pairs && zeros_first == 0 {
+		Some(UTF_16LE)
+	} else if zeros_first * 2 > pairs && zeros_second == 0 {
+		Some(UTF_16BE)
+	} else {
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::encoding::*;
+
+	#[test]
+	fn detect_encodings() {
+		let (text, encoding) = read_text(path::Path::new("testdata/Win-1252.md")).unwrap();
+		assert!(text.starts_with("Yxmördaren Julia Blomqvist på fäktning i Schweiz"));
+		assert_eq!(encoding.to_string(), "windows-1252");
+
+		let (text, encoding) = read_text(path::Path::new("testdata/BOM.md")).unwrap();
+		assert!(text.starts_with('\u{feff}'));
+		assert_eq!(encoding, FileEncoding::UTF8);
+
+		let (text, encoding) = FileEncoding::decode(b"\xff\xfe#\x00 \x00\xc5\x00".to_vec()).unwrap();
+		assert_eq!(text, "# Å");
+		assert_eq!(encoding.to_string(), "UTF-16LE");
+
+		let (text, encoding) = FileEncoding::decode(b"\x00#\x00 \x00A".to_vec()).unwrap();
+		assert_eq!(text, "# A");
+		assert_eq!(encoding.to_string(), "UTF-16BE");
+
+		let (text, encoding) = FileEncoding::decode(b"# A\n".to_vec()).unwrap();
+		assert_eq!(text, "# A\n");
+		assert_eq!(encoding, FileEncoding::UTF8);
+	}
+
+	#[test]
+	fn encode_as_detected() {
+		for bytes in [
+			b"\xff\xfe#\x00 \x00\xc5\x00".to_vec(),
+			b"\xfe\xff\x00#\x00 \x00\xc5".to_vec(),
+			b"#\x00 \x00A\x00".to_vec(),
+			b"# \xc5ngstr\xf6m \xe4r h\xe4r".to_vec(),
+		] {
+			let (text, encoding) = FileEncoding::decode(bytes.clone()).unwrap();
+			assert_eq!(encoding.encode(&text).unwrap(), bytes, "{}", encoding);
+		}
+
+		let (_, encoding) = FileEncoding::decode(b"# \xc5ngstr\xf6m \xe4r h\xe4r".to_vec()).unwrap();
+		assert!(encoding.encode("# Ω").is_err());
+	}
+}
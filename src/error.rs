@@ -0,0 +1,188 @@
+use ansi_term::Colour;
+use std::{error, fmt, io, path};
+
+/// Errors from reading, parsing and changing notes
+#[derive(Debug)]
+pub enum Error {
+	/// Reading or writing a file failed
+	Io { path: path::PathBuf, source: io::Error },
+	/// A note isn't text in any encoding that could be detected
+	UnknownEncoding { path: path::PathBuf },
+	/// The ID format isn't a valid regular expression
+	InvalidIdFormat(regex::Error),
+	/// An ignore pattern isn't a valid glob pattern
+	InvalidIgnorePattern(globset::Error),
+	/// A .gitignore or .noteexplorerignore file couldn't be read, or has
+	/// invalid patterns
+	InvalidIgnoreFile { path: path::PathBuf, source: ignore::Error },
+	/// Several notes have the same ID, so links to it are ambiguous
+	DuplicateId { id: String, paths: Vec<path::PathBuf> },
+	/// A note can't be renamed, since another file has the new name
+	RenameConflict { from: path::PathBuf, to: path::PathBuf },
+	/// There is no note with the name
+	UnknownNote { stem: String },
+	/// A change can't be undone, since the file has changed again
+	UndoConflict { path: path::PathBuf },
+}
+
+impl Error {
+	/// Error from reading or writing the file at `path`
+	pub fn io(path: &path::Path, source: io::Error) -> Error {
+		if source.kind() == io::ErrorKind::InvalidData {
+			return Error::UnknownEncoding {
+				path: path.to_path_buf(),
+			};
+		}
+		Error::Io {
+			path: path.to_path_buf(),
+			source,
+		}
+	}
+
+	/// Warnings are reported, but don't make the program fail
+	pub fn is_warning(&self) -> bool {
+		matches!(self, Error::DuplicateId { .. } | Error::InvalidIgnoreFile { .. })
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Io { path, source } => write!(f, "Couldn't read or write {}: {}", path.display(), source),
+			Error::UnknownEncoding { path } => write!(f, "Couldn't read {}: the text encoding couldn't be detected", path.display()),
+			Error::InvalidIdFormat(err) => {
+				write!(f, "Cannot parse ID format as regular expression: {}", err)
+			}
+			Error::InvalidIgnorePattern(err) => write!(f, "Invalid ignore pattern: {}", err),
+			Error::InvalidIgnoreFile { path, source } => write!(f, "Couldn't read {}: {}", path.display(), source),
+			Error::DuplicateId { id, paths } => {
+				let stems: Vec<String> = paths
+					.iter()
+					.map(|path| format!("\"{}\"", path.file_stem().unwrap_or_default().to_string_lossy()))
+					.collect();
+				write!(f, "The id {} was used in {}", id, stems.join(" and "))
+			}
+			Error::RenameConflict { from, to } => write!(
+				f,
+				"Couldn't rename {} to {}, since the file already exists",
+				from.display(),
+				to.display()
+			),
+			Error::UnknownNote { stem } => write!(f, "There is no note named {}", stem),
+			Error::UndoConflict { path } => write!(
+				f,
+				"Couldn't undo the change to {}, since it has changed again after the last run",
+				path.display()
+			),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::Io { source, .. } => Some(source),
+			Error::InvalidIdFormat(err) => Some(err),
+			Error::InvalidIgnorePattern(err) => Some(err),
+			Error::InvalidIgnoreFile { source, .. } => Some(source),
+			_ => None,
+		}
+	}
+}
+
+impl From<regex::Error> for Error {
+	fn from(err: regex::Error) -> Error {
+		Error::InvalidIdFormat(err)
+	}
+}
+
+impl From<globset::Error> for Error {
+	fn from(err: globset::Error) -> Error {
+		Error::InvalidIgnorePattern(err)
+	}
+}
+
+/// Failures for single files, that didn't stop the rest of the work
+#[derive(Debug, Default)]
+pub struct Report {
+	errors: Vec<Error>,
+}
+
+impl Report {
+	pub fn push(&mut self, error: Error) {
+		self.errors.push(error);
+	}
+
+	pub fn append(&mut self, other: Report) {
+		self.errors.extend(other.errors);
+	}
+
+	/// Keep only the failures for which `f` returns true
+	pub fn retain(&mut self, f: impl FnMut(&Error) -> bool) {
+		self.errors.retain(f);
+	}
+
+	pub fn errors(&self) -> &[Error] {
+		&self.errors
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.errors.is_empty()
+	}
+
+	/// Some failures were errors, and not only warnings
+	pub fn has_errors(&self) -> bool {
+		self.errors.iter().any(|error| !error.is_warning())
+	}
+
+	/// Print warnings and errors to stderr
+	pub fn print(&self) {
+		for error in &self.errors {
+			if error.is_warning() {
+				eprintln!("{} {}", Colour::Yellow.paint("Warning:"), error);
+			} else {
+				eprintln!("{} {}", Colour::Red.paint("Error:"), error);
+			}
+		}
+	}
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let count = self.errors.iter().filter(|error| !error.is_warning()).count();
+		if count == 1 {
+			write!(f, "1 file failed")
+		} else {
+			write!(f, "{} files failed", count)
+		}
+	}
+}
+
+// Returned by `run` when files failed, after the report has been printed
+impl error::Error for Report {}
+
+#[cfg(test)]
+mod tests {
+	use crate::error::*;
+
+	#[test]
+	fn report() {
+		let mut report = Report::default();
+		report.push(Error::DuplicateId {
+			id: "123".to_owned(),
+			paths: vec![path::PathBuf::from("/a/x.md"), path::PathBuf::from("/b/y.md")],
+		});
+		assert!(!report.is_empty());
+		assert!(!report.has_errors());
+		assert_eq!(report.errors()[0].to_string(), "The id 123 was used in \"x\" and \"y\"");
+
+		let path = path::Path::new("/a/x.md");
+		let unknown_encoding = Error::io(path, io::Error::new(io::ErrorKind::InvalidData, "invalid"));
+		assert!(matches!(unknown_encoding, Error::UnknownEncoding { .. }));
+		report.push(unknown_encoding);
+		assert!(report.has_errors());
+		assert_eq!(report.to_string(), "1 file failed");
+		report.push(Error::io(path, io::Error::new(io::ErrorKind::NotFound, "missing")));
+		assert_eq!(report.to_string(), "2 files failed");
+	}
+}
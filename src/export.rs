@@ -0,0 +1,294 @@
+use crate::graph::{Node, NodeIndex, NoteGraph};
+use std::collections::HashSet;
+use std::{io, str};
+
+/// File formats for the link graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+	Dot,
+	GraphMl,
+	Gexf,
+	Mermaid,
+}
+
+impl str::FromStr for GraphFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"dot" | "graphviz" => Ok(GraphFormat::Dot),
+			"graphml" => Ok(GraphFormat::GraphMl),
+			"gexf" => Ok(GraphFormat::Gexf),
+			"mermaid" => Ok(GraphFormat::Mermaid),
+			_ => Err(format!("Unknown graph format \"{}\"", s)),
+		}
+	}
+}
+
+/// Attributes of a node, with the degree in the whole graph
+struct NodeAttributes<'a> {
+	key: String,
+	label: &'a str,
+	id: Option<&'a str>,
+	path: Option<&'a str>,
+	in_degree: usize,
+	out_degree: usize,
+	broken: bool,
+}
+
+/// Attribute names and types, in the order they are written
+const ATTRIBUTES: [(&str, &str); 6] = [
+	("note_id", "string"),
+	("path", "string"),
+	("degree", "int"),
+	("in_degree", "int"),
+	("out_degree", "int"),
+	("broken", "boolean"),
+];
+
+impl NodeAttributes<'_> {
+	/// Values in the order of `ATTRIBUTES`, leaving out missing ones
+	fn values(&self) -> Vec<(&'static str, String)> {
+		let values = [
+			self.id.map(str::to_owned),
+			self.path.map(str::to_owned),
+			Some((self.in_degree + self.out_degree).to_string()),
+			Some(self.in_degree.to_string()),
+			Some(self.out_degree.to_string()),
+			Some(self.broken.to_string()),
+		];
+		ATTRIBUTES
+			.iter()
+			.zip(values)
+			.filter_map(|((name, _), value)| value.map(|v| (*name, v)))
+			.collect()
+	}
+}
+
+/// Write the nodes in `nodes`, and the links between them. Nodes are named
+/// "n" and their index in the whole graph, so that the names stay the same
+/// in exports of different parts of the graph.
+pub fn write_graph<W: io::Write>(
+	mut writer: W,
+	graph: &NoteGraph,
+	nodes: &[NodeIndex],
+	format: GraphFormat,
+) -> io::Result<()> {
+	let included: HashSet<NodeIndex> = nodes.iter().copied().collect();
+	let mut sorted_nodes = nodes.to_vec();
+	sorted_nodes.sort_unstable();
+	sorted_nodes.dedup();
+	let attributes: Vec<NodeAttributes> = sorted_nodes
+		.iter()
+		.map(|&index| {
+			let node = &graph.nodes()[index];
+			NodeAttributes {
+				key: format!("n{}", index),
+				label: node.label(),
+				id: node.note().and_then(|note| note.id.as_deref()),
+				path: node.note().map(|note| note.path.as_str()),
+				in_degree: graph.incoming(index).len(),
+				out_degree: graph.outgoing(index).len(),
+				broken: matches!(node, Node::Missing(_)),
+			}
+		})
+		.collect();
+	let edges: Vec<(String, String)> = graph
+		.edges()
+		.filter(|(from, to)| included.contains(from) && included.contains(to))
+		.map(|(from, to)| (format!("n{}", from), format!("n{}", to)))
+		.collect();
+
+	match format {
+		GraphFormat::Dot => write_dot(&mut writer, &attributes, &edges),
+		GraphFormat::GraphMl => write_graphml(&mut writer, &attributes, &edges),
+		GraphFormat::Gexf => write_gexf(&mut writer, &attributes, &edges),
+		GraphFormat::Mermaid => write_mermaid(&mut writer, &attributes, &edges),
+	}
+}
+
+fn write_dot<W: io::Write>(w: &mut W, nodes: &[NodeAttributes], edges: &[(String, String)]) -> io::Result<()> {
+	writeln!(w, "digraph notes {{")?;
+	for node in nodes {
+		let mut attributes = vec![format!("label=\"{}\"", escape_dot(node.label))];
+		for (name, value) in node.values() {
+			match name {
+				"note_id" | "path" => attributes.push(format!("{}=\"{}\"", name, escape_dot(&value))),
+				_ => attributes.push(format!("{}={}", name, value)),
+			}
+		}
+		if node.broken {
+			attributes.push("style=dashed".to_owned());
+		}
+		writeln!(w, "\t{} [{}];", node.key, attributes.join(", "))?;
+	}
+	for (from, to) in edges {
+		writeln!(w, "\t{} -> {};", from, to)?;
+	}
+	writeln!(w, "}}")
+}
+
+fn write_graphml<W: io::Write>(w: &mut W, nodes: &[NodeAttributes], edges: &[(String, String)]) -> io::Result<()> {
+	writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+	writeln!(w, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">")?;
+	writeln!(w, "\t<key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>")?;
+	for (name, kind) in ATTRIBUTES {
+		writeln!(w, "\t<key id=\"{0}\" for=\"node\" attr.name=\"{0}\" attr.type=\"{1}\"/>", name, kind)?;
+	}
+	writeln!(w, "\t<graph id=\"notes\" edgedefault=\"directed\">")?;
+	for node in nodes {
+		writeln!(w, "\t\t<node id=\"{}\">", node.key)?;
+		writeln!(w, "\t\t\t<data key=\"label\">{}</data>", escape_xml(node.label))?;
+		for (name, value) in node.values() {
+			writeln!(w, "\t\t\t<data key=\"{}\">{}</data>", name, escape_xml(&value))?;
+		}
+		writeln!(w, "\t\t</node>")?;
+	}
+	for (from, to) in edges {
+		writeln!(w, "\t\t<edge source=\"{}\" target=\"{}\"/>", from, to)?;
+	}
+	writeln!(w, "\t</graph>")?;
+	writeln!(w, "</graphml>")
+}
+
+fn write_gexf<W: io::Write>(w: &mut W, nodes: &[NodeAttributes], edges: &[(String, String)]) -> io::Result<()> {
+	writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+	writeln!(w, "<gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">")?;
+	writeln!(w, "\t<graph defaultedgetype=\"directed\">")?;
+	writeln!(w, "\t\t<attributes class=\"node\">")?;
+	for (name, kind) in ATTRIBUTES {
+		writeln!(w, "\t\t\t<attribute id=\"{0}\" title=\"{0}\" type=\"{1}\"/>", name, kind)?;
+	}
+	writeln!(w, "\t\t</attributes>")?;
+	writeln!(w, "\t\t<nodes>")?;
+	for node in nodes {
+		writeln!(w, "\t\t\t<node id=\"{}\" label=\"{}\">", node.key, escape_xml(node.label))?;
+		writeln!(w, "\t\t\t\t<attvalues>")?;
+		for (name, value) in node.values() {
+			writeln!(w, "\t\t\t\t\t<attvalue for=\"{}\" value=\"{}\"/>", name, escape_xml(&value))?;
+		}
+		writeln!(w, "\t\t\t\t</attvalues>")?;
+		writeln!(w, "\t\t\t</node>")?;
+	}
+	writeln!(w, "\t\t</nodes>")?;
+	writeln!(w, "\t\t<edges>")?;
+	for (i, (from, to)) in edges.iter().enumerate() {
+		writeln!(w, "\t\t\t<edge id=\"e{}\" source=\"{}\" target=\"{}\"/>", i, from, to)?;
+	}
+	writeln!(w, "\t\t</edges>")?;
+	writeln!(w, "\t</graph>")?;
+	writeln!(w, "</gexf>")
+}
+
+/// Mermaid diagrams have no attributes, so only the labels are written, and
+/// broken link targets get a dashed border
+fn write_mermaid<W: io::Write>(w: &mut W, nodes: &[NodeAttributes], edges: &[(String, String)]) -> io::Result<()> {
+	writeln!(w, "flowchart LR")?;
+	for node in nodes {
+		writeln!(w, "\t{}[\"{}\"]", node.key, escape_mermaid(node.label))?;
+	}
+	for (from, to) in edges {
+		writeln!(w, "\t{} --> {}", from, to)?;
+	}
+	let broken: Vec<&str> = nodes.iter().filter(|n| n.broken).map(|n| n.key.as_str()).collect();
+	if !broken.is_empty() {
+		writeln!(w, "\tclassDef broken stroke-dasharray: 5 5")?;
+		writeln!(w, "\tclass {} broken", broken.join(","))?;
+	}
+	Ok(())
+}
+
+fn escape_dot(text: &str) -> String {
+	text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn escape_xml(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+		.replace('\'', "&apos;")
+}
+
+/// Mermaid labels can't contain quotes, but take HTML entity codes
+fn escape_mermaid(text: &str) -> String {
+	text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::export::*;
+	use crate::note::{NoteMeta, WikiLink};
+
+	/// "A & B" -> "C", and "A & B" -> "missing"
+	fn graph() -> NoteGraph {
+		let note = |stem: &str, title: &str, id: Option<&str>| {
+			Node::Note(NoteMeta {
+				path: format!("/notes/{}.md", stem),
+				stem: stem.to_owned(),
+				extension: "md".to_owned(),
+				title: title.to_owned(),
+				id: id.map(str::to_owned),
+				has_links: false,
+			})
+		};
+		NoteGraph::new(
+			vec![
+				note("a", "A & \"B\"", Some("20210101120000")),
+				note("c", "C", None),
+				Node::Missing(WikiLink::FileName("missing".to_owned())),
+			],
+			vec![vec![1, 2], vec![], vec![]],
+		)
+	}
+
+	fn export(nodes: &[NodeIndex], format: GraphFormat) -> String {
+		let mut out = Vec::new();
+		write_graph(&mut out, &graph(), nodes, format).unwrap();
+		String::from_utf8(out).unwrap()
+	}
+
+	#[test]
+	fn dot() {
+		assert_eq!(
+			export(&[0, 1, 2], GraphFormat::Dot),
+			"digraph notes {\n\
+			\tn0 [label=\"A & \\\"B\\\"\", note_id=\"20210101120000\", path=\"/notes/a.md\", degree=2, in_degree=0, out_degree=2, broken=false];\n\
+			\tn1 [label=\"C\", path=\"/notes/c.md\", degree=1, in_degree=1, out_degree=0, broken=false];\n\
+			\tn2 [label=\"missing\", degree=1, in_degree=1, out_degree=0, broken=true, style=dashed];\n\
+			\tn0 -> n1;\n\
+			\tn0 -> n2;\n\
+			}\n"
+		);
+	}
+
+	#[test]
+	fn mermaid_subgraph() {
+		// Only the links between the exported nodes are written
+		assert_eq!(
+			export(&[1, 0], GraphFormat::Mermaid),
+			"flowchart LR\n\tn0[\"A & #quot;B#quot;\"]\n\tn1[\"C\"]\n\tn0 --> n1\n"
+		);
+	}
+
+	#[test]
+	fn xml_formats() {
+		let graphml = export(&[0, 2], GraphFormat::GraphMl);
+		assert!(graphml.contains("\t\t\t<data key=\"label\">A &amp; &quot;B&quot;</data>\n"));
+		assert!(graphml.contains("\t\t\t<data key=\"broken\">true</data>\n"));
+		assert!(graphml.contains("\t\t<edge source=\"n0\" target=\"n2\"/>\n"));
+		assert!(!graphml.contains("n1"));
+
+		let gexf = export(&[0, 1, 2], GraphFormat::Gexf);
+		assert!(gexf.contains("\t\t\t<node id=\"n0\" label=\"A &amp; &quot;B&quot;\">\n"));
+		assert!(gexf.contains("\t\t\t\t\t<attvalue for=\"degree\" value=\"2\"/>\n"));
+		assert!(gexf.contains("\t\t\t<edge id=\"e1\" source=\"n0\" target=\"n2\"/>\n"));
+	}
+
+	#[test]
+	fn parse_format() {
+		assert_eq!("GraphML".parse(), Ok(GraphFormat::GraphMl));
+		assert_eq!("dot".parse(), Ok(GraphFormat::Dot));
+		assert!("svg".parse::<GraphFormat>().is_err());
+	}
+}
@@ -1,34 +1,322 @@
-use ansi_term::Colour;
-use std::path;
+use crate::error::{Error, Report};
+use crate::journal::{self, Journal};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::{io, path};
 use walkdir::{DirEntry, WalkDir};
 
-pub fn get_files(root: &path::Path, ext: &str) -> Vec<path::PathBuf> {
+/// Files with gitignore patterns, that are read in every directory. The
+/// patterns in the last file win.
+pub const IGNORE_FILE_NAMES: [&str; 2] = [".gitignore", ".noteexplorerignore"];
+
+/// Tells note files apart from other files, and which files to skip
+pub struct FileFilter {
+	/// Lowercase file extensions of note files
+	pub extensions: Vec<String>,
+	ignore: Vec<Patterns>,
+	/// Only files that match these patterns are used, if there are any
+	include: Vec<Patterns>,
+	/// Directories that are skipped, like the quarantine directory
+	excluded_dirs: Vec<path::PathBuf>,
+	/// Patterns from the ignore files, by the directory they are in
+	ignore_files: Mutex<HashMap<path::PathBuf, Option<Arc<Gitignore>>>>,
+	/// Problems with ignore files, that haven't been reported yet
+	errors: Mutex<Vec<Error>>,
+}
+
+impl FileFilter {
+	/// `extensions` are the file extensions of notes, separated by commas.
+	/// Patterns containing a slash are matched against the path relative
+	/// to the root, other patterns against file and directory names.
+	pub fn new(extensions: &str, ignore_patterns: &[String]) -> Result<FileFilter, Error> {
+		let mut filter = FileFilter {
+			extensions: parse_extensions(extensions),
+			ignore: Vec::new(),
+			include: Vec::new(),
+			excluded_dirs: Vec::new(),
+			ignore_files: Mutex::new(HashMap::new()),
+			errors: Mutex::new(Vec::new()),
+		};
+		if !ignore_patterns.is_empty() {
+			filter.ignore.push(Patterns::new(None, ignore_patterns)?);
+		}
+		Ok(filter)
+	}
+
+	/// Only use the files that match one of the patterns, which work like
+	/// the ignore patterns. Directories are always searched.
+	pub fn with_include(mut self, include_patterns: &[String]) -> Result<FileFilter, Error> {
+		if !include_patterns.is_empty() {
+			self.include.push(Patterns::new(None, include_patterns)?);
+		}
+		Ok(self)
+	}
+
+	/// Skip everything in `dir`, which should be below the root
+	pub fn with_excluded_dir(mut self, dir: &path::Path) -> FileFilter {
+		self.excluded_dirs.push(dir.to_path_buf());
+		self
+	}
+
+	/// Add ignore and include patterns from a config file in `dir`, where
+	/// patterns containing a slash are relative to `dir` instead of the root
+	pub fn with_patterns_in(
+		mut self,
+		dir: &path::Path,
+		ignore_patterns: &[String],
+		include_patterns: &[String],
+	) -> Result<FileFilter, Error> {
+		if !ignore_patterns.is_empty() {
+			self.ignore.push(Patterns::new(Some(dir), ignore_patterns)?);
+		}
+		if !include_patterns.is_empty() {
+			self.include.push(Patterns::new(Some(dir), include_patterns)?);
+		}
+		Ok(self)
+	}
+
+	/// File extensions are compared without case sensitivity
+	pub fn is_note(&self, path: &path::Path) -> bool {
+		let extension = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+		self.extensions.contains(&extension)
+	}
+
+	/// Check if a path below `root` is neither hidden nor ignored
+	pub fn includes(&self, root: &path::Path, path: &path::Path) -> bool {
+		let relative = match path.strip_prefix(root) {
+			Ok(relative) => relative,
+			Err(_) => return false,
+		};
+		let hidden = relative
+			.components()
+			.any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+		if hidden || self.is_excluded(path) {
+			return false;
+		}
+
+		// Ignored directories also exclude everything inside them
+		let is_dir = path.is_dir();
+		for ancestor in relative.ancestors().filter(|a| !a.as_os_str().is_empty()) {
+			let ancestor_is_dir = ancestor != relative || is_dir;
+			if self.is_ignored_by_patterns(root, ancestor)
+				|| self.is_ignored_by_files(root, &root.join(ancestor), ancestor_is_dir)
+			{
+				return false;
+			}
+		}
+		is_dir || self.is_included(root, relative)
+	}
+
+	fn is_ignored(&self, root: &path::Path, entry: &DirEntry) -> bool {
+		match entry.path().strip_prefix(root) {
+			Ok(relative) => {
+				entry.depth() > 0
+					&& (self.is_excluded(entry.path())
+						|| self.is_ignored_by_patterns(root, relative)
+						|| self.is_ignored_by_files(root, entry.path(), entry.file_type().is_dir()))
+			}
+			Err(_) => false,
+		}
+	}
+
+	fn is_excluded(&self, path: &path::Path) -> bool {
+		self.excluded_dirs.iter().any(|dir| path.starts_with(dir))
+	}
+
+	fn is_ignored_by_patterns(&self, root: &path::Path, relative: &path::Path) -> bool {
+		self.ignore.iter().any(|patterns| patterns.is_match(root, relative))
+	}
+
+	fn is_included(&self, root: &path::Path, relative: &path::Path) -> bool {
+		self.include.is_empty() || self.include.iter().any(|patterns| patterns.is_match(root, relative))
+	}
+
+	/// Check the ignore files in the directories from `root` to `path`,
+	/// where the deepest file with a matching pattern decides
+	fn is_ignored_by_files(&self, root: &path::Path, path: &path::Path, is_dir: bool) -> bool {
+		for dir in path.ancestors().skip(1) {
+			if !dir.starts_with(root) {
+				break;
+			}
+			if let Some(gitignore) = self.get_ignore_file(dir) {
+				match gitignore.matched(path, is_dir) {
+					Match::Ignore(_) => return true,
+					Match::Whitelist(_) => return false,
+					Match::None => {}
+				}
+			}
+		}
+		false
+	}
+
+	/// Get the patterns of the ignore files in a directory, reading them the
+	/// first time
+	fn get_ignore_file(&self, dir: &path::Path) -> Option<Arc<Gitignore>> {
+		let mut ignore_files = self.ignore_files.lock().unwrap();
+		ignore_files
+			.entry(dir.to_path_buf())
+			.or_insert_with(|| read_ignore_files(dir, &mut self.errors.lock().unwrap()))
+			.clone()
+	}
+
+	/// Read the ignore files in `dir` again the next time they are needed,
+	/// after they have been changed
+	pub fn forget_ignore_files(&self, dir: &path::Path) {
+		self.ignore_files.lock().unwrap().remove(dir);
+	}
+
+	/// Take the problems with ignore files found since the last call
+	pub fn take_errors(&self) -> Vec<Error> {
+		std::mem::take(&mut self.errors.lock().unwrap())
+	}
+}
+
+/// Get the lowercase file extensions in a list separated by commas, like
+/// "md, Markdown, .txt"
+pub fn parse_extensions(extensions: &str) -> Vec<String> {
+	extensions
+		.split(',')
+		.map(|e| e.trim().trim_start_matches('.').to_lowercase())
+		.filter(|e| !e.is_empty())
+		.collect()
+}
+
+/// Ignore or include patterns, where patterns containing a slash are
+/// relative to `dir`, or to the root if there is no `dir`
+struct Patterns {
+	dir: Option<path::PathBuf>,
+	globs: GlobSet,
+}
+
+impl Patterns {
+	fn new(dir: Option<&path::Path>, patterns: &[String]) -> Result<Patterns, Error> {
+		Ok(Patterns {
+			dir: dir.map(|dir| dir.to_path_buf()),
+			globs: build_glob_set(patterns)?,
+		})
+	}
+
+	/// Check a path relative to `root`
+	fn is_match(&self, root: &path::Path, relative: &path::Path) -> bool {
+		match &self.dir {
+			Some(dir) => match root.join(relative).strip_prefix(dir) {
+				Ok(path) => self.globs.is_match(path),
+				Err(_) => false,
+			},
+			None => self.globs.is_match(relative),
+		}
+	}
+}
+
+/// Build a glob set from ignore or include patterns
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+	let mut builder = GlobSetBuilder::new();
+	for pattern in patterns {
+		let pattern = pattern.trim_end_matches('/');
+		let pattern = if pattern.contains('/') {
+			pattern.trim_start_matches('/').to_owned()
+		} else {
+			format!("**/{}", pattern)
+		};
+		builder.add(GlobBuilder::new(&pattern).literal_separator(true).build()?);
+	}
+	Ok(builder.build()?)
+}
+
+/// Read the ignore files in a directory, if there are any
+fn read_ignore_files(dir: &path::Path, errors: &mut Vec<Error>) -> Option<Arc<Gitignore>> {
+	let mut builder = GitignoreBuilder::new(dir);
+	let mut found = false;
+	for name in IGNORE_FILE_NAMES {
+		let file = dir.join(name);
+		if !file.is_file() {
+			continue;
+		}
+		found = true;
+		if let Some(err) = builder.add(&file) {
+			errors.push(Error::InvalidIgnoreFile { path: file, source: err });
+		}
+	}
+	if !found {
+		return None;
+	}
+	match builder.build() {
+		Ok(gitignore) => Some(Arc::new(gitignore)),
+		Err(err) => {
+			errors.push(Error::InvalidIgnoreFile {
+				path: dir.to_path_buf(),
+				source: err,
+			});
+			None
+		}
+	}
+}
+
+/// Get all note files, and all other files (attachments), in the directory
+/// tree. Files and directories that can't be accessed are added to `report`.
+pub fn get_files(
+	root: &path::Path,
+	filter: &FileFilter,
+	report: &mut Report,
+) -> (Vec<path::PathBuf>, Vec<path::PathBuf>) {
 	let mut files = Vec::new();
+	let mut other_files = Vec::new();
 
 	if root.is_dir() {
 		let walker = WalkDir::new(root).into_iter();
-		for entry in walker.filter_entry(|e| !is_hidden(e)) {
+		for entry in walker.filter_entry(|e| !is_hidden(e) && !filter.is_ignored(root, e)) {
 			let entry = match entry {
 				Ok(e) => e,
 				Err(err) => {
-					let path = err.path().unwrap_or_else(|| path::Path::new("")).display();
-					eprintln!(
-						"{} Couldn't access {}",
-						Colour::Yellow.paint("Warning:"),
-						path
-					);
+					let path = err.path().unwrap_or(root).to_path_buf();
+					report.push(Error::io(&path, err.into()));
 					continue;
 				}
 			};
 
+			if entry.file_type().is_dir() {
+				continue;
+			}
+
 			let path = entry.into_path();
-			if ext == path.extension().unwrap_or_default().to_str().unwrap() {
+			if !filter.is_included(root, path.strip_prefix(root).unwrap_or(&path)) {
+				continue;
+			}
+			if filter.is_note(&path) {
 				files.push(path);
+			} else {
+				other_files.push(path);
 			}
 		}
 	}
 
-	files
+	(files, other_files)
+}
+
+/// Move `file` into `dir`, keeping its path relative to `root`.
+/// Returns the new path.
+pub fn move_to_dir(
+	root: &path::Path,
+	file: &path::Path,
+	dir: &path::Path,
+	journal: Option<&Journal>,
+) -> io::Result<path::PathBuf> {
+	let relative = file.strip_prefix(root).unwrap_or(file);
+	let new_path = dir.join(relative);
+	if new_path.exists() {
+		return Err(io::Error::new(
+			io::ErrorKind::AlreadyExists,
+			format!("{} already exists", new_path.display()),
+		));
+	}
+	if let Some(parent) = new_path.parent() {
+		journal::create_dir_all(journal, parent)?;
+	}
+	journal::rename(journal, file, &new_path)?;
+	Ok(new_path)
 }
 
 fn is_hidden(entry: &DirEntry) -> bool {
@@ -38,3 +326,101 @@ fn is_hidden(entry: &DirEntry) -> bool {
 		.map(|s| s.starts_with('.'))
 		.unwrap_or(false)
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::ftree::*;
+	use std::fs;
+
+	#[test]
+	fn ignore_patterns() {
+		let patterns: Vec<String> = ["templates", "archive/2019/", "*.tmp", "/drafts/*.md"]
+			.iter()
+			.map(|p| p.to_string())
+			.collect();
+		let filter = FileFilter::new("md", &patterns).unwrap();
+		let ignored = |p: &str| filter.is_ignored_by_patterns(path::Path::new("/notes"), path::Path::new(p));
+
+		assert!(ignored("templates"));
+		assert!(ignored("sub/templates"));
+		assert!(ignored("archive/2019"));
+		assert!(!ignored("sub/archive/2019"));
+		assert!(ignored("note.tmp"));
+		assert!(ignored("sub/note.tmp"));
+		assert!(ignored("drafts/note.md"));
+		assert!(!ignored("drafts/sub/note.md"));
+		assert!(!ignored("note.md"));
+
+		assert!(FileFilter::new("md", &["a[".to_owned()]).is_err());
+
+		// Patterns from a config file in a parent directory
+		let patterns = ["notes/drafts/*".to_owned(), "*.tmp".to_owned()];
+		let filter = FileFilter::new("md", &[])
+			.unwrap()
+			.with_patterns_in(path::Path::new("/base"), &patterns, &[])
+			.unwrap();
+		let ignored = |p: &str| filter.is_ignored_by_patterns(path::Path::new("/base/notes"), path::Path::new(p));
+		assert!(ignored("drafts/note.md"));
+		assert!(!ignored("notes/drafts/note.md"));
+		assert!(ignored("sub/note.tmp"));
+		assert!(!ignored("note.md"));
+	}
+
+	#[test]
+	fn ignore_files() {
+		let mut dir = std::env::temp_dir();
+		dir.push("noteexplorer-test-ignorefiles");
+		if dir.exists() {
+			fs::remove_dir_all(&dir).unwrap();
+		}
+		for sub in ["archive", "node_modules/pkg", "sub"] {
+			fs::create_dir_all(dir.join(sub)).unwrap();
+		}
+		fs::write(dir.join(".gitignore"), "node_modules/\n*.log\n").unwrap();
+		fs::write(dir.join(".noteexplorerignore"), "archive/\n!important.log\n").unwrap();
+		fs::write(dir.join("sub/.gitignore"), "*.png\n!keep.png\n").unwrap();
+		for file in [
+			"a.md",
+			"debug.log",
+			"important.log",
+			"archive/old.md",
+			"node_modules/pkg/readme.md",
+			"sub/b.md",
+			"sub/image.png",
+			"sub/keep.png",
+		] {
+			fs::write(dir.join(file), "").unwrap();
+		}
+
+		let files = |filter: &FileFilter| {
+			let (mut notes, mut others) = get_files(&dir, filter, &mut Report::default());
+			notes.sort();
+			others.sort();
+			(notes, others)
+		};
+		let filter = FileFilter::new("md", &[]).unwrap();
+		let (notes, others) = files(&filter);
+		assert_eq!(notes, [dir.join("a.md"), dir.join("sub/b.md")]);
+		assert_eq!(others, [dir.join("important.log"), dir.join("sub/keep.png")]);
+		assert!(!filter.includes(&dir, &dir.join("archive/new.md")));
+		assert!(!filter.includes(&dir, &dir.join("sub/new.png")));
+		assert!(filter.includes(&dir, &dir.join("sub/new.md")));
+
+		let filter = FileFilter::new("md", &[]).unwrap().with_include(&["sub/*".to_owned()]).unwrap();
+		let (notes, others) = files(&filter);
+		assert_eq!(notes, [dir.join("sub/b.md")]);
+		assert_eq!(others, [dir.join("sub/keep.png")]);
+		assert!(!filter.includes(&dir, &dir.join("new.md")));
+		assert!(filter.take_errors().is_empty());
+
+		// Invalid patterns are reported, and the other patterns still work
+		fs::write(dir.join("sub/.noteexplorerignore"), "b.md\nfoo[\n").unwrap();
+		let filter = FileFilter::new("md", &[]).unwrap();
+		let (notes, _) = files(&filter);
+		assert_eq!(notes, [dir.join("a.md")]);
+		let errors = filter.take_errors();
+		assert_eq!(errors.len(), 1);
+		assert!(errors[0].is_warning());
+		assert!(filter.take_errors().is_empty());
+	}
+}
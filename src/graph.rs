@@ -0,0 +1,606 @@
+use crate::note::{NoteMeta, WikiLink};
+use rayon::prelude::*;
+use std::collections::{HashSet, VecDeque};
+use std::str;
+
+/// Position of a node in `NoteGraph::nodes`
+pub type NodeIndex = usize;
+
+/// A node in the link graph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+	Note(NoteMeta),
+	/// The target of broken links, that no note matches
+	Missing(WikiLink),
+}
+
+impl Node {
+	/// Title of the note, or else its file name, or the broken link target
+	pub fn label(&self) -> &str {
+		match self {
+			Node::Note(note) if !note.title.is_empty() => &note.title,
+			Node::Note(note) => &note.stem,
+			Node::Missing(link) => link.value(),
+		}
+	}
+
+	pub fn note(&self) -> Option<&NoteMeta> {
+		match self {
+			Node::Note(note) => Some(note),
+			Node::Missing(_) => None,
+		}
+	}
+}
+
+/// Which links to follow from a note
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+	/// Links to the note
+	In,
+	/// Links from the note
+	Out,
+	Both,
+}
+
+impl str::FromStr for Direction {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"in" => Ok(Direction::In),
+			"out" => Ok(Direction::Out),
+			"both" => Ok(Direction::Both),
+			_ => Err(format!("Unknown direction \"{}\", expected in, out or both", s)),
+		}
+	}
+}
+
+/// Damping factor of PageRank, the chance of following a link instead of
+/// jumping to a random note
+const DAMPING: f64 = 0.85;
+const PAGERANK_ITERATIONS: usize = 100;
+const PAGERANK_TOLERANCE: f64 = 1e-10;
+
+/// Directed graph of the links between notes, where links to unknown notes
+/// point to dangling nodes. Links in backlinks sections and links to self
+/// are left out, and several links between the same notes are one edge.
+#[derive(Debug, Default)]
+pub struct NoteGraph {
+	/// Notes sorted by path, followed by the broken link targets
+	nodes: Vec<Node>,
+	/// Targets of the links from each node, sorted
+	outgoing: Vec<Vec<NodeIndex>>,
+	/// Sources of the links to each node, sorted
+	incoming: Vec<Vec<NodeIndex>>,
+}
+
+impl NoteGraph {
+	/// Build a graph from the nodes and the targets of each node's links
+	pub fn new(nodes: Vec<Node>, mut outgoing: Vec<Vec<NodeIndex>>) -> NoteGraph {
+		let mut incoming = vec![Vec::new(); nodes.len()];
+		for (from, targets) in outgoing.iter_mut().enumerate() {
+			targets.sort_unstable();
+			targets.dedup();
+			targets.retain(|&to| to != from);
+			for &to in targets.iter() {
+				incoming[to].push(from);
+			}
+		}
+		NoteGraph {
+			nodes,
+			outgoing,
+			incoming,
+		}
+	}
+
+	pub fn nodes(&self) -> &[Node] {
+		&self.nodes
+	}
+
+	pub fn len(&self) -> usize {
+		self.nodes.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.nodes.is_empty()
+	}
+
+	/// Get the notes that `node` links to
+	pub fn outgoing(&self, node: NodeIndex) -> &[NodeIndex] {
+		&self.outgoing[node]
+	}
+
+	/// Get the notes that link to `node`
+	pub fn incoming(&self, node: NodeIndex) -> &[NodeIndex] {
+		&self.incoming[node]
+	}
+
+	/// Get all links, as pairs of source and target, in node order
+	pub fn edges(&self) -> impl Iterator<Item = (NodeIndex, NodeIndex)> + '_ {
+		self.outgoing
+			.iter()
+			.enumerate()
+			.flat_map(|(from, targets)| targets.iter().map(move |&to| (from, to)))
+	}
+
+	/// Number of links to and from `node`
+	pub fn degree(&self, node: NodeIndex) -> usize {
+		self.incoming[node].len() + self.outgoing[node].len()
+	}
+
+	/// Get the node of the note with the path
+	pub fn find(&self, path: &str) -> Option<NodeIndex> {
+		self.nodes
+			.iter()
+			.position(|node| node.note().is_some_and(|note| note.path == path))
+	}
+
+	/// Get the graph without the targets of broken links
+	pub fn without_missing(&self) -> NoteGraph {
+		let mut new_index = vec![None; self.len()];
+		let mut nodes = Vec::new();
+		for (index, node) in self.nodes.iter().enumerate() {
+			if let Node::Note(_) = node {
+				new_index[index] = Some(nodes.len());
+				nodes.push(node.clone());
+			}
+		}
+		let outgoing = (0..self.len())
+			.filter(|&index| new_index[index].is_some())
+			.map(|index| self.outgoing[index].iter().filter_map(|&to| new_index[to]).collect())
+			.collect();
+		NoteGraph::new(nodes, outgoing)
+	}
+
+	/// Get the PageRank of each node, which is higher for nodes that many
+	/// nodes, or nodes with a high PageRank, link to. The sum is 1.
+	pub fn get_pagerank(&self) -> Vec<f64> {
+		let n = self.len();
+		if n == 0 {
+			return Vec::new();
+		}
+		let mut ranks = vec![1.0 / n as f64; n];
+		for _ in 0..PAGERANK_ITERATIONS {
+			// Nodes without links spread their rank over all nodes
+			let dangling: f64 = (0..n).filter(|&i| self.outgoing[i].is_empty()).map(|i| ranks[i]).sum();
+			let base = (1.0 - DAMPING) / n as f64 + DAMPING * dangling / n as f64;
+			let mut new_ranks = vec![base; n];
+			for (from, targets) in self.outgoing.iter().enumerate() {
+				for &to in targets {
+					new_ranks[to] += DAMPING * ranks[from] / targets.len() as f64;
+				}
+			}
+			let change: f64 = ranks.iter().zip(&new_ranks).map(|(a, b)| (a - b).abs()).sum();
+			ranks = new_ranks;
+			if change < PAGERANK_TOLERANCE {
+				break;
+			}
+		}
+		ranks
+	}
+
+	/// Get the betweenness centrality of each node: the share of the
+	/// shortest paths between all other pairs of nodes that pass through it,
+	/// from 0 to 1. Uses Brandes' algorithm, with the sources in parallel.
+	pub fn get_betweenness(&self) -> Vec<f64> {
+		let n = self.len();
+		let mut betweenness = (0..n)
+			.into_par_iter()
+			.map(|source| self.get_dependencies(source))
+			.reduce(
+				|| vec![0.0; n],
+				|mut sum, dependencies| {
+					sum.iter_mut().zip(dependencies).for_each(|(s, d)| *s += d);
+					sum
+				},
+			);
+		if n > 2 {
+			let pairs = ((n - 1) * (n - 2)) as f64;
+			betweenness.iter_mut().for_each(|b| *b /= pairs);
+		}
+		betweenness
+	}
+
+	/// Get how much each node is on the shortest paths from `source` to all
+	/// other nodes
+	fn get_dependencies(&self, source: NodeIndex) -> Vec<f64> {
+		let n = self.len();
+		// Breadth-first search, counting the shortest paths to each node
+		let mut order = Vec::with_capacity(n);
+		let mut predecessors: Vec<Vec<NodeIndex>> = vec![Vec::new(); n];
+		let mut paths = vec![0.0; n];
+		let mut distances: Vec<Option<usize>> = vec![None; n];
+		paths[source] = 1.0;
+		distances[source] = Some(0);
+		let mut queue = VecDeque::from([source]);
+		while let Some(node) = queue.pop_front() {
+			order.push(node);
+			let distance = distances[node].unwrap_or_default();
+			for &next in &self.outgoing[node] {
+				if distances[next].is_none() {
+					distances[next] = Some(distance + 1);
+					queue.push_back(next);
+				}
+				if distances[next] == Some(distance + 1) {
+					paths[next] += paths[node];
+					predecessors[next].push(node);
+				}
+			}
+		}
+
+		// Add up the dependencies, from the farthest nodes back
+		let mut dependency = vec![0.0; n];
+		for &node in order.iter().rev() {
+			for &previous in &predecessors[node] {
+				dependency[previous] += paths[previous] / paths[node] * (1.0 + dependency[node]);
+			}
+		}
+		dependency[source] = 0.0;
+		dependency
+	}
+
+	/// Get the groups of nodes that are connected by links in any direction,
+	/// largest first
+	pub fn get_weak_components(&self) -> Vec<Vec<NodeIndex>> {
+		let mut component = vec![None; self.len()];
+		let mut components = Vec::new();
+		for start in 0..self.len() {
+			if component[start].is_some() {
+				continue;
+			}
+			let mut members = Vec::new();
+			let mut stack = vec![start];
+			component[start] = Some(components.len());
+			while let Some(node) = stack.pop() {
+				members.push(node);
+				for neighbour in self.neighbours(node, Direction::Both) {
+					if component[neighbour].is_none() {
+						component[neighbour] = Some(components.len());
+						stack.push(neighbour);
+					}
+				}
+			}
+			components.push(members);
+		}
+		sort_components(components)
+	}
+
+	/// Get the groups of nodes where each node can be reached from all the
+	/// others by following links, largest first. Uses Kosaraju's algorithm.
+	pub fn get_strong_components(&self) -> Vec<Vec<NodeIndex>> {
+		// Order the nodes by when the depth-first search is done with them
+		let mut visited = vec![false; self.len()];
+		let mut finished = Vec::with_capacity(self.len());
+		for start in 0..self.len() {
+			if visited[start] {
+				continue;
+			}
+			visited[start] = true;
+			let mut stack = vec![(start, 0)];
+			while let Some((node, next)) = stack.pop() {
+				match self.outgoing[node].get(next) {
+					Some(&target) => {
+						stack.push((node, next + 1));
+						if !visited[target] {
+							visited[target] = true;
+							stack.push((target, 0));
+						}
+					}
+					None => finished.push(node),
+				}
+			}
+		}
+
+		// Follow the links backwards, starting with the last finished node
+		let mut component = vec![false; self.len()];
+		let mut components = Vec::new();
+		for &start in finished.iter().rev() {
+			if component[start] {
+				continue;
+			}
+			let mut members = Vec::new();
+			let mut stack = vec![start];
+			component[start] = true;
+			while let Some(node) = stack.pop() {
+				members.push(node);
+				for &source in &self.incoming[node] {
+					if !component[source] {
+						component[source] = true;
+						stack.push(source);
+					}
+				}
+			}
+			components.push(members);
+		}
+		sort_components(components)
+	}
+
+	/// Check if `from` links to `to`
+	pub fn has_link(&self, from: NodeIndex, to: NodeIndex) -> bool {
+		self.outgoing[from].binary_search(&to).is_ok()
+	}
+
+	/// Get up to `k` shortest paths from `from` to `to`, following links in
+	/// the direction, with the fewest links first. Paths never visit a node
+	/// twice, and with `k` 0 there are none. Uses Yen's algorithm.
+	pub fn get_shortest_paths(
+		&self,
+		from: NodeIndex,
+		to: NodeIndex,
+		k: usize,
+		direction: Direction,
+	) -> Vec<Vec<NodeIndex>> {
+		let mut removed_nodes = vec![false; self.len()];
+		let mut removed_steps = HashSet::new();
+		let mut paths: Vec<Vec<NodeIndex>> = Vec::new();
+		if k == 0 {
+			return paths;
+		}
+		match self.get_shortest_path(from, to, direction, &removed_nodes, &removed_steps) {
+			Some(path) => paths.push(path),
+			None => return paths,
+		}
+
+		let mut candidates: Vec<Vec<NodeIndex>> = Vec::new();
+		while paths.len() < k {
+			let previous = paths[paths.len() - 1].clone();
+			// Branch off from each node of the previous path, but not the
+			// way that any path found so far went
+			for i in 0..previous.len() - 1 {
+				let root = &previous[..=i];
+				removed_steps.clear();
+				for path in &paths {
+					if path.len() > i + 1 && path[..=i] == *root {
+						removed_steps.insert((path[i], path[i + 1]));
+					}
+				}
+				removed_nodes.iter_mut().for_each(|removed| *removed = false);
+				for &node in &root[..i] {
+					removed_nodes[node] = true;
+				}
+
+				if let Some(spur) = self.get_shortest_path(previous[i], to, direction, &removed_nodes, &removed_steps) {
+					let mut candidate = root[..i].to_vec();
+					candidate.extend(spur);
+					if !candidates.contains(&candidate) && !paths.contains(&candidate) {
+						candidates.push(candidate);
+					}
+				}
+			}
+			if candidates.is_empty() {
+				break;
+			}
+			candidates.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+			paths.push(candidates.remove(0));
+		}
+		paths
+	}
+
+	/// Breadth-first search for a shortest path, that avoids some nodes and
+	/// steps between nodes
+	fn get_shortest_path(
+		&self,
+		from: NodeIndex,
+		to: NodeIndex,
+		direction: Direction,
+		removed_nodes: &[bool],
+		removed_steps: &HashSet<(NodeIndex, NodeIndex)>,
+	) -> Option<Vec<NodeIndex>> {
+		let mut previous = vec![None; self.len()];
+		let mut visited = vec![false; self.len()];
+		visited[from] = true;
+		let mut queue = VecDeque::from([from]);
+		while let Some(node) = queue.pop_front() {
+			if node == to {
+				let mut path = vec![to];
+				let mut current = to;
+				while let Some(prev) = previous[current] {
+					path.push(prev);
+					current = prev;
+				}
+				path.reverse();
+				return Some(path);
+			}
+			for next in self.neighbours(node, direction) {
+				if !visited[next] && !removed_nodes[next] && !removed_steps.contains(&(node, next)) {
+					visited[next] = true;
+					previous[next] = Some(node);
+					queue.push_back(next);
+				}
+			}
+		}
+		None
+	}
+
+	/// Get the neighbours of `node` in the direction
+	fn neighbours(&self, node: NodeIndex, direction: Direction) -> impl Iterator<Item = NodeIndex> + '_ {
+		let outgoing = match direction {
+			Direction::Out | Direction::Both => self.outgoing[node].as_slice(),
+			Direction::In => &[],
+		};
+		let incoming = match direction {
+			Direction::In | Direction::Both => self.incoming[node].as_slice(),
+			Direction::Out => &[],
+		};
+		outgoing.iter().chain(incoming).copied()
+	}
+
+	/// Get the nodes that can be reached from `start` by following at most
+	/// `depth` links in the direction, with the number of links to each,
+	/// sorted by distance. `start` itself comes first, at distance 0. The
+	/// targets of broken links are reached, but not passed through.
+	pub fn get_neighbourhood(
+		&self,
+		start: NodeIndex,
+		depth: usize,
+		direction: Direction,
+	) -> Vec<(NodeIndex, usize)> {
+		let mut distances = vec![None; self.len()];
+		distances[start] = Some(0);
+		let mut reached = vec![(start, 0)];
+		let mut queue = VecDeque::from([start]);
+		while let Some(node) = queue.pop_front() {
+			let distance = distances[node].unwrap_or_default();
+			if distance == depth || (node != start && matches!(self.nodes[node], Node::Missing(_))) {
+				continue;
+			}
+			for neighbour in self.neighbours(node, direction) {
+				if distances[neighbour].is_none() {
+					distances[neighbour] = Some(distance + 1);
+					reached.push((neighbour, distance + 1));
+					queue.push_back(neighbour);
+				}
+			}
+		}
+		reached
+	}
+}
+
+/// Sort the members of each component, and the components by size, largest
+/// first
+fn sort_components(mut components: Vec<Vec<NodeIndex>>) -> Vec<Vec<NodeIndex>> {
+	for members in components.iter_mut() {
+		members.sort_unstable();
+	}
+	components.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a[0].cmp(&b[0])));
+	components
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::graph::*;
+
+	fn note(stem: &str) -> Node {
+		Node::Note(NoteMeta {
+			path: format!("/notes/{}.md", stem),
+			stem: stem.to_owned(),
+			extension: "md".to_owned(),
+			title: stem.to_uppercase(),
+			id: None,
+			has_links: false,
+		})
+	}
+
+	/// a -> b -> c -> d, a -> "missing", e -> a, and f on its own
+	fn graph() -> NoteGraph {
+		NoteGraph::new(
+			vec![
+				note("a"),
+				note("b"),
+				note("c"),
+				note("d"),
+				note("e"),
+				note("f"),
+				Node::Missing(WikiLink::FileName("missing".to_owned())),
+			],
+			vec![vec![1, 6, 1, 0], vec![2], vec![3], vec![], vec![0], vec![], vec![]],
+		)
+	}
+
+	#[test]
+	fn build_graph() {
+		let graph = graph();
+		let edges: Vec<(NodeIndex, NodeIndex)> = graph.edges().collect();
+		assert_eq!(edges, [(0, 1), (0, 6), (1, 2), (2, 3), (4, 0)]);
+		assert_eq!(graph.incoming(0), [4]);
+		assert_eq!(graph.degree(0), 3);
+		assert_eq!(graph.degree(5), 0);
+		assert_eq!(graph.find("/notes/c.md"), Some(2));
+		assert_eq!(graph.nodes()[6].label(), "missing");
+		assert_eq!(graph.nodes()[0].label(), "A");
+	}
+
+	#[test]
+	fn without_missing() {
+		let graph = graph().without_missing();
+		assert_eq!(graph.len(), 6);
+		let edges: Vec<(NodeIndex, NodeIndex)> = graph.edges().collect();
+		assert_eq!(edges, [(0, 1), (1, 2), (2, 3), (4, 0)]);
+	}
+
+	#[test]
+	fn pagerank() {
+		let ranks = graph().without_missing().get_pagerank();
+		assert!((ranks.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+		// Each note in the chain gets more than the one before
+		assert!(ranks[4] < ranks[0] && ranks[0] < ranks[1] && ranks[1] < ranks[2] && ranks[2] < ranks[3]);
+		assert!((ranks[4] - ranks[5]).abs() < 1e-12);
+
+		// All notes in a cycle are equal
+		let cycle = NoteGraph::new(vec![note("a"), note("b"), note("c")], vec![vec![1], vec![2], vec![0]]);
+		for rank in cycle.get_pagerank() {
+			assert!((rank - 1.0 / 3.0).abs() < 1e-9);
+		}
+	}
+
+	#[test]
+	fn betweenness() {
+		// e -> a -> b -> c -> d: a is on the paths e-b, e-c and e-d
+		let betweenness = graph().without_missing().get_betweenness();
+		let expected = [3.0, 4.0, 3.0, 0.0, 0.0, 0.0].map(|paths| paths / 20.0);
+		assert_eq!(betweenness, expected);
+
+		// Two shortest paths from a to d, each through half of the paths
+		let diamond = NoteGraph::new(
+			vec![note("a"), note("b"), note("c"), note("d")],
+			vec![vec![1, 2], vec![3], vec![3], vec![]],
+		);
+		assert_eq!(diamond.get_betweenness(), [0.0, 0.5 / 6.0, 0.5 / 6.0, 0.0]);
+	}
+
+	#[test]
+	fn components() {
+		let graph = graph();
+		assert_eq!(graph.get_weak_components(), [vec![0, 1, 2, 3, 4, 6], vec![5]]);
+		assert_eq!(graph.get_strong_components(), [[0], [1], [2], [3], [4], [5], [6]]);
+
+		// a <-> b -> c <-> d <-> e, and f
+		let graph = NoteGraph::new(
+			vec![note("a"), note("b"), note("c"), note("d"), note("e"), note("f")],
+			vec![vec![1], vec![0, 2], vec![3], vec![2, 4], vec![3], vec![]],
+		);
+		assert_eq!(graph.get_strong_components(), [vec![2, 3, 4], vec![0, 1], vec![5]]);
+		assert_eq!(graph.get_weak_components(), [vec![0, 1, 2, 3, 4], vec![5]]);
+	}
+
+	#[test]
+	fn shortest_paths() {
+		let graph = graph();
+		assert_eq!(graph.get_shortest_paths(4, 3, 1, Direction::Out), [[4, 0, 1, 2, 3]]);
+		assert!(graph.get_shortest_paths(3, 4, 1, Direction::Out).is_empty());
+		assert_eq!(graph.get_shortest_paths(3, 4, 3, Direction::Both), [[3, 2, 1, 0, 4]]);
+		assert_eq!(graph.get_shortest_paths(0, 0, 2, Direction::Out), [[0]]);
+		assert!(graph.get_shortest_paths(4, 3, 0, Direction::Out).is_empty());
+		assert!(graph.has_link(0, 6));
+		assert!(!graph.has_link(6, 0));
+
+		// a -> b -> d, a -> c -> d, a -> d, c -> e -> d
+		let graph = NoteGraph::new(
+			vec![note("a"), note("b"), note("c"), note("d"), note("e")],
+			vec![vec![1, 2, 3], vec![3], vec![3, 4], vec![], vec![3]],
+		);
+		assert_eq!(
+			graph.get_shortest_paths(0, 3, 10, Direction::Out),
+			[vec![0, 3], vec![0, 1, 3], vec![0, 2, 3], vec![0, 2, 4, 3]]
+		);
+		assert_eq!(graph.get_shortest_paths(0, 3, 2, Direction::Out), [vec![0, 3], vec![0, 1, 3]]);
+	}
+
+	#[test]
+	fn neighbourhood() {
+		let graph = graph();
+		assert_eq!(graph.get_neighbourhood(0, 1, Direction::Both), [(0, 0), (1, 1), (6, 1), (4, 1)]);
+		assert_eq!(graph.get_neighbourhood(0, 2, Direction::Out), [(0, 0), (1, 1), (6, 1), (2, 2)]);
+		assert_eq!(graph.get_neighbourhood(2, 5, Direction::In), [(2, 0), (1, 1), (0, 2), (4, 3)]);
+		assert_eq!(graph.get_neighbourhood(5, 3, Direction::Both), [(5, 0)]);
+
+		// Notes that link to the same unknown note aren't neighbours
+		let graph = NoteGraph::new(
+			vec![note("a"), note("b"), Node::Missing(WikiLink::FileName("missing".to_owned()))],
+			vec![vec![2], vec![2], vec![]],
+		);
+		assert_eq!(graph.get_neighbourhood(0, 3, Direction::Both), [(0, 0), (2, 1)]);
+		assert_eq!("IN".parse(), Ok(Direction::In));
+		assert!("up".parse::<Direction>().is_err());
+	}
+}
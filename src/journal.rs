@@ -0,0 +1,308 @@
+use crate::error::{Error, Report};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path;
+
+/// Directory in the notes root, where the changes of the last run are kept
+pub const JOURNAL_DIR_NAME: &str = ".noteexplorer-journal";
+const JOURNAL_FILE_NAME: &str = "journal.jsonl";
+
+/// A change to a file, recorded before it's made
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Entry {
+	/// The contents before and after are kept in the files "<backup>.old"
+	/// and "<backup>.new"
+	Write { path: path::PathBuf, backup: usize },
+	Rename { from: path::PathBuf, to: path::PathBuf },
+	/// A directory that didn't exist before
+	CreateDir { path: path::PathBuf },
+}
+
+/// Journal of the file changes in a run, that can be undone. The journal of
+/// the previous run is replaced when the first change is made.
+#[derive(Debug)]
+pub struct Journal {
+	dir: path::PathBuf,
+	/// Number of entries so far, or None before the first change
+	count: Cell<Option<usize>>,
+}
+
+impl Journal {
+	pub fn new(root: &path::Path) -> Journal {
+		Journal {
+			dir: root.join(JOURNAL_DIR_NAME),
+			count: Cell::new(None),
+		}
+	}
+
+	/// Write new contents to a file, keeping the old contents in the journal
+	pub fn write(&self, path: &path::Path, contents: &[u8]) -> io::Result<()> {
+		let old_contents = fs::read(path)?;
+		let backup = self.start()?;
+		write_atomic(&self.dir.join(format!("{}.old", backup)), &old_contents)?;
+		write_atomic(&self.dir.join(format!("{}.new", backup)), contents)?;
+		self.append(&Entry::Write {
+			path: path.to_path_buf(),
+			backup,
+		})?;
+		write_atomic(path, contents)
+	}
+
+	pub fn rename(&self, from: &path::Path, to: &path::Path) -> io::Result<()> {
+		self.start()?;
+		self.append(&Entry::Rename {
+			from: from.to_path_buf(),
+			to: to.to_path_buf(),
+		})?;
+		fs::rename(from, to)
+	}
+
+	/// Create a directory and its missing parents, recording each of them
+	pub fn create_dir_all(&self, path: &path::Path) -> io::Result<()> {
+		let mut missing: Vec<&path::Path> = path.ancestors().take_while(|dir| !dir.exists()).collect();
+		missing.reverse();
+		for dir in missing {
+			self.start()?;
+			self.append(&Entry::CreateDir { path: dir.to_path_buf() })?;
+			fs::create_dir(dir)?;
+		}
+		Ok(())
+	}
+
+	/// Start a new run, where the next change replaces the journal
+	pub fn restart(&self) {
+		self.count.set(None);
+	}
+
+	/// Remove the journal of the previous run before the first change, and
+	/// get the number of the next entry
+	fn start(&self) -> io::Result<usize> {
+		if let Some(count) = self.count.get() {
+			return Ok(count);
+		}
+		if self.dir.exists() {
+			fs::remove_dir_all(&self.dir)?;
+		}
+		fs::create_dir_all(&self.dir)?;
+		self.count.set(Some(0));
+		Ok(0)
+	}
+
+	fn append(&self, entry: &Entry) -> io::Result<()> {
+		let mut file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(self.dir.join(JOURNAL_FILE_NAME))?;
+		let mut line = serde_json::to_vec(entry)?;
+		line.push(b'\n');
+		file.write_all(&line)?;
+		file.sync_data()?;
+		self.count.set(Some(self.count.get().unwrap_or(0) + 1));
+		Ok(())
+	}
+}
+
+/// Write a file with or without a journal
+pub fn write(journal: Option<&Journal>, path: &path::Path, contents: &[u8]) -> io::Result<()> {
+	match journal {
+		Some(journal) => journal.write(path, contents),
+		None => write_atomic(path, contents),
+	}
+}
+
+/// Rename a file with or without a journal
+pub fn rename(journal: Option<&Journal>, from: &path::Path, to: &path::Path) -> io::Result<()> {
+	match journal {
+		Some(journal) => journal.rename(from, to),
+		None => fs::rename(from, to),
+	}
+}
+
+/// Create a directory and its missing parents, with or without a journal
+pub fn create_dir_all(journal: Option<&Journal>, path: &path::Path) -> io::Result<()> {
+	match journal {
+		Some(journal) => journal.create_dir_all(path),
+		None => fs::create_dir_all(path),
+	}
+}
+
+/// Write to a hidden file next to `path` first, and then rename it, so that
+/// the file is never half-written
+pub fn write_atomic(path: &path::Path, contents: &[u8]) -> io::Result<()> {
+	let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+	let tmp_path = path.with_file_name(format!(".{}.noteexplorer-tmp", file_name));
+	let mut file = fs::File::create(&tmp_path)?;
+	file.write_all(contents)?;
+	if let Ok(metadata) = fs::metadata(path) {
+		file.set_permissions(metadata.permissions())?;
+	}
+	file.sync_all()?;
+	fs::rename(&tmp_path, path)
+}
+
+/// Undo the changes of the last run, in reverse order, and get the paths
+/// that were restored, or None if there is nothing to undo. Files that have
+/// changed again since are left as they are, and reported.
+pub fn undo(root: &path::Path, report: &mut Report) -> io::Result<Option<Vec<path::PathBuf>>> {
+	let dir = root.join(JOURNAL_DIR_NAME);
+	let entries = match fs::read_to_string(dir.join(JOURNAL_FILE_NAME)) {
+		Ok(contents) => parse_entries(&contents)?,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+		Err(err) => return Err(err),
+	};
+
+	let mut restored = Vec::new();
+	let mut conflicts = Vec::new();
+	for entry in entries.iter().rev() {
+		let undone = match entry {
+			Entry::Write { path, backup } => {
+				let old_contents = fs::read(dir.join(format!("{}.old", backup)))?;
+				let new_contents = fs::read(dir.join(format!("{}.new", backup)))?;
+				match fs::read(path) {
+					Ok(contents) if contents == new_contents => {
+						write_atomic(path, &old_contents).map_err(|err| Error::io(path, err))
+					}
+					// The file wasn't written before the run stopped
+					Ok(contents) if contents == old_contents => continue,
+					_ => Err(Error::UndoConflict { path: path.clone() }),
+				}
+				.map(|_| path)
+			}
+			Entry::Rename { from, to } => match (from.exists(), to.exists()) {
+				(false, true) => fs::rename(to, from).map(|_| from).map_err(|err| Error::io(to, err)),
+				// The file wasn't renamed before the run stopped
+				(true, false) => continue,
+				_ => Err(Error::UndoConflict { path: to.clone() }),
+			},
+			// Directories are only removed if nothing else has been put there
+			Entry::CreateDir { path } => match fs::remove_dir(path) {
+				Ok(()) => continue,
+				Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+				Err(_) => Err(Error::UndoConflict { path: path.clone() }),
+			},
+		};
+		match undone {
+			Ok(path) if !restored.contains(path) => restored.push(path.clone()),
+			Ok(_) => {}
+			Err(err) => {
+				report.push(err);
+				conflicts.push(entry);
+			}
+		}
+	}
+
+	if conflicts.is_empty() {
+		fs::remove_dir_all(&dir)?;
+	} else {
+		// Keep the changes that weren't undone, to try again when the
+		// conflicts are fixed
+		let mut contents = Vec::new();
+		for entry in conflicts.iter().rev() {
+			contents.extend(serde_json::to_vec(entry)?);
+			contents.push(b'\n');
+		}
+		write_atomic(&dir.join(JOURNAL_FILE_NAME), &contents)?;
+	}
+	Ok(Some(restored))
+}
+
+/// Parse the lines of a journal file. The last line is left out if it is
+/// incomplete, since the run may have stopped while it was written.
+fn parse_entries(contents: &str) -> io::Result<Vec<Entry>> {
+	let lines: Vec<&str> = contents.lines().collect();
+	let mut entries = Vec::with_capacity(lines.len());
+	for (i, line) in lines.iter().enumerate() {
+		match serde_json::from_str(line) {
+			Ok(entry) => entries.push(entry),
+			Err(_) if i + 1 == lines.len() => {}
+			Err(err) => return Err(err.into()),
+		}
+	}
+	Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::journal::*;
+	use std::env::temp_dir;
+
+	#[test]
+	fn undo_changes() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-journal");
+		if dir.exists() {
+			fs::remove_dir_all(&dir).unwrap();
+		}
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(dir.join("a.md"), "# A").unwrap();
+		fs::write(dir.join("b.md"), "# B").unwrap();
+		fs::write(dir.join("c.md"), "# C").unwrap();
+
+		let journal = Journal::new(&dir);
+		journal.write(&dir.join("a.md"), b"# A, changed").unwrap();
+		journal.rename(&dir.join("a.md"), &dir.join("Alpha.md")).unwrap();
+		journal.write(&dir.join("Alpha.md"), b"# A, changed twice").unwrap();
+		journal.write(&dir.join("b.md"), b"# B, changed").unwrap();
+		journal.write(&dir.join("c.md"), b"# C, changed").unwrap();
+		journal.create_dir_all(&dir.join("sub/dir")).unwrap();
+		journal.rename(&dir.join("b.md"), &dir.join("sub/dir/b.md")).unwrap();
+		// Changed again after the run
+		fs::write(dir.join("c.md"), "# C, edited").unwrap();
+
+		let mut report = Report::default();
+		let restored = undo(&dir, &mut report).unwrap().unwrap();
+		assert_eq!(restored, [dir.join("b.md"), dir.join("Alpha.md"), dir.join("a.md")]);
+		assert!(!dir.join("sub").exists());
+		assert!(matches!(report.errors(), [Error::UndoConflict { path }] if *path == dir.join("c.md")));
+		assert_eq!(fs::read_to_string(dir.join("a.md")).unwrap(), "# A");
+		assert!(!dir.join("Alpha.md").exists());
+		assert_eq!(fs::read_to_string(dir.join("b.md")).unwrap(), "# B");
+		assert_eq!(fs::read_to_string(dir.join("c.md")).unwrap(), "# C, edited");
+
+		// The changes that weren't undone are kept in the journal
+		fs::write(dir.join("c.md"), "# C, changed").unwrap();
+		let mut report = Report::default();
+		assert_eq!(undo(&dir, &mut report).unwrap().unwrap(), [dir.join("c.md")]);
+		assert!(report.is_empty());
+		assert_eq!(fs::read_to_string(dir.join("c.md")).unwrap(), "# C");
+		assert_eq!(undo(&dir, &mut report).unwrap(), None);
+	}
+
+	#[test]
+	fn restart_journal() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-journal-restart");
+		if dir.exists() {
+			fs::remove_dir_all(&dir).unwrap();
+		}
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(dir.join("a.md"), "# A").unwrap();
+		fs::write(dir.join("b.md"), "# B").unwrap();
+
+		let journal = Journal::new(&dir);
+		journal.write(&dir.join("a.md"), b"# A, changed").unwrap();
+		journal.restart();
+		journal.write(&dir.join("b.md"), b"# B, changed").unwrap();
+
+		let mut report = Report::default();
+		assert_eq!(undo(&dir, &mut report).unwrap().unwrap(), [dir.join("b.md")]);
+		assert_eq!(fs::read_to_string(dir.join("a.md")).unwrap(), "# A, changed");
+		assert_eq!(fs::read_to_string(dir.join("b.md")).unwrap(), "# B");
+	}
+
+	#[test]
+	fn incomplete_journal() {
+		let entries = parse_entries("{\"rename\":{\"from\":\"a.md\",\"to\":\"b.md\"}}\n{\"write\":{\"pa").unwrap();
+		assert_eq!(
+			entries,
+			[Entry::Rename {
+				from: path::PathBuf::from("a.md"),
+				to: path::PathBuf::from("b.md"),
+			}]
+		);
+		assert!(parse_entries("{\"write\"\n{\"rename\":{\"from\":\"a.md\",\"to\":\"b.md\"}}").is_err());
+	}
+}
@@ -1,51 +1,212 @@
+//! Finds links between Markdown notes, and keeps backlinks sections and
+//! file names up to date.
+//!
+//! The command line program is run with [`run`]. To use the notes from
+//! another program, build a [`NoteCollection`], either from a directory
+//! with [`NoteCollection::collect_files`] or from documents in memory with
+//! [`NoteCollection::from_documents`]. The collection can then be queried
+//! for notes, links and backlinks, and the list and update operations
+//! return their results instead of printing them.
+//!
+//! ```
+//! use noteexplorer::{Change, NoteCollection, NoteParser};
+//! use std::path::PathBuf;
+//!
+//! let parser = NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+//! let notes = NoteCollection::from_documents(
+//!     vec![
+//!         (PathBuf::from("/notes/a.md"), "# A\n\n[[20210119212027]]".to_owned()),
+//!         (PathBuf::from("/notes/20210119212027 B.md"), "# B\n".to_owned()),
+//!     ],
+//!     parser,
+//!     "md",
+//! );
+//!
+//! let b = notes.get_note_by_id("20210119212027").unwrap();
+//! assert_eq!(b.title, "B");
+//! let backlinks = notes.get_backlinks(b.path.as_ref());
+//! assert_eq!(backlinks[0].get_wikilink_to(), "[[a]]");
+//!
+//! // Documents in memory are never written to, so the changes are returned
+//! notes.update_backlinks(false);
+//! let changes = notes.take_planned_changes();
+//! assert_eq!(
+//!     changes,
+//!     [Change::Write {
+//!         path: "/notes/20210119212027 B.md".to_owned(),
+//!         old_contents: "# B\n".to_owned(),
+//!         new_contents: "# B\n\n## Backlinks\n\n- [[a]]\n".to_owned(),
+//!     }]
+//! );
+//! ```
+
+mod cache;
+mod check;
+mod configfile;
+mod encoding;
+mod error;
+mod export;
 mod ftree;
+mod graph;
+mod journal;
+mod lsp;
 mod mdparse;
 mod note;
+mod output;
+mod rank;
+mod watch;
+
+pub use cache::ParseCache;
+pub use check::CheckFailed;
+pub use configfile::{ConfigFile, ConfigOptions};
+pub use error::{Error, Report};
+pub use export::GraphFormat;
+pub use ftree::FileFilter;
+pub use graph::{Direction, Node, NodeIndex, NoteGraph};
+pub use mdparse::{NoteData, NoteParser, ParserProfile};
+pub use note::{Anchor, Change, Link, NoteCollection, NoteMeta, Task, WikiLink};
+pub use output::OutputFormat;
+pub use rank::{Metric, RankedNote};
 
+use ansi_term::Colour;
 use chrono::Utc;
-use debug_print::debug_println;
-use note::{NoteCollection, NoteMeta};
-use std::error::Error;
-use std::fs;
+use debug_print::debug_eprintln;
+use output::{AttachmentRecord, ComponentRecord, LinkRecord, NeighbourRecord, NoteRecord, PathRecord, RankRecord, StatsRecord, TaskRecord};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::{fs, io, path};
 
+/// Settings for the command line program
 #[derive(Debug)]
 pub struct Config {
 	pub id_pattern: String,
 	pub backlinks_heading: String,
+	/// File extensions of notes, separated by commas
 	pub extension: String,
+	/// Parser profile names for file extensions
+	pub profiles: HashMap<String, String>,
 	pub path: String,
+	pub markdown_links: bool,
 	pub command: String,
 	pub force: bool,
+	pub aliased: bool,
+	/// Directory to move unused attachments to, relative to `path`
+	pub quarantine: Option<String>,
+	/// Glob patterns for files and directories to skip
+	pub ignore: Vec<String>,
+	/// Glob patterns for the files to use, or empty for all files
+	pub include: Vec<String>,
+	/// Directory of the config file, that `config_ignore` and
+	/// `config_include` patterns containing a slash are relative to
+	pub config_dir: Option<path::PathBuf>,
+	/// Glob patterns from the config file for files and directories to skip
+	pub config_ignore: Vec<String>,
+	/// Glob patterns from the config file for the files to use
+	pub config_include: Vec<String>,
+	/// Output format of list subcommands, statistics and check
+	pub format: OutputFormat,
+	/// Rules for the check subcommand, or empty for all rules
+	pub rules: Vec<String>,
+	/// Print the changes instead of writing them to disk
+	pub dry_run: bool,
+	/// Keep parsed notes in a cache file between runs
+	pub cache: bool,
+	/// Save changed notes as UTF-8, instead of in the encoding they had
+	pub normalize_encoding: bool,
+	/// File format of `export-graph`
+	pub graph_format: GraphFormat,
+	/// Note that graph subcommands start from, by ID, file name or title
+	pub note: Option<String>,
+	/// Number of links to follow from `note`
+	pub depth: usize,
+	/// Links to follow from `note`
+	pub direction: Direction,
+	/// Metric that the `rank` subcommand sorts notes by
+	pub metric: Metric,
+	/// Number of notes to list in `rank`, or 0 for all
+	pub top: usize,
+	/// Find strongly connected components, instead of weakly
+	pub strong: bool,
+	/// Note that `path` ends at, by ID, file name or title
+	pub target: Option<String>,
+	/// Also follow links backwards in `path`
+	pub backwards: bool,
+	/// Number of shortest paths to find
+	pub paths: usize,
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+/// Run the command line program
+pub fn run(config: Config) -> Result<(), Box<dyn StdError>> {
+	let root = fs::canonicalize(&config.path)?;
+	let mut filter = ftree::FileFilter::new(&config.extension, &config.ignore)?.with_include(&config.include)?;
+	if let Some(dir) = &config.config_dir {
+		filter = filter.with_patterns_in(dir, &config.config_ignore, &config.config_include)?;
+	}
+	// Attachments that were quarantined before shouldn't be moved again
+	if let Some(dir) = &config.quarantine {
+		filter = filter.with_excluded_dir(&root.join(dir));
+	}
+	if config.command == "undo" {
+		return undo(&root);
+	}
 	let start_time = Utc::now();
-	let notes = NoteCollection::collect_files(
-		&fs::canonicalize(&config.path)?,
-		&config.extension,
-		mdparse::NoteParser::new(&config.id_pattern, &config.backlinks_heading)?,
-	);
+	let mut parser = mdparse::NoteParser::new(&config.id_pattern, &config.backlinks_heading)?
+		.with_markdown_links(config.markdown_links);
+	for (extension, profile) in &config.profiles {
+		parser = parser.with_profile(extension.trim_start_matches('.'), profile.parse()?);
+	}
+	// A dry run shouldn't leave any files behind, not even the cache
+	let mut cache = (config.cache && !config.dry_run).then(|| ParseCache::load(&root, &parser));
+	let mut notes = NoteCollection::collect_files(&root, &filter, parser, cache.as_mut())
+		.with_dry_run(config.dry_run)
+		.with_normalized_encoding(config.normalize_encoding);
+	if !config.dry_run {
+		notes = notes.with_journal(&root);
+	}
+	if let Some(cache) = cache {
+		if let Err(err) = cache.save() {
+			eprintln!("{} Couldn't save the parse cache: {}", Colour::Yellow.paint("Warning:"), err);
+		}
+	}
 	let duration_collect_files = Utc::now() - start_time;
 
+	if config.command == "watch" {
+		notes.take_report().print();
+		return watch::watch(notes, &root, &filter, config.aliased);
+	}
+	if config.command == "lsp" {
+		notes.take_report().print();
+		return lsp::serve(notes, &root, filter);
+	}
+
 	let start_time = Utc::now();
-	match config.command.as_str() {
-		"list-broken-links" => print_broken_links(&notes),
-		"list-sources" => print_sources(&notes),
-		"list-sinks" => print_sinks(&notes),
-		"list-isolated" => print_isolated(&notes),
-		"list-tasks" => print_tasks(&notes),
-		"remove-backlinks" => remove_backlinks(&notes),
-		"update-backlinks" => update_backlinks(&notes),
-		"update-filenames" => update_filenames(&notes, config.force)?,
-		_ => print_stats(&notes),
+	let mut report = Report::default();
+	let result = run_subcommand(&config, &mut notes, &root, &mut report);
+	if result.is_ok() && config.dry_run {
+		print_planned_changes(&notes, &root);
 	}
 	let duration_subcommand = Utc::now() - start_time;
 
-	debug_println!(
+	// Failures from reading the notes come first
+	let mut failures = notes.take_report();
+	// The duplicate-ids rule of `check` reports them instead
+	let checks_duplicate_ids = config.command == "check"
+		&& check::parse_rules(&config.rules).is_ok_and(|rules| rules.contains(&check::Rule::DuplicateIds));
+	if checks_duplicate_ids {
+		failures.retain(|error| !matches!(error, Error::DuplicateId { .. }));
+	}
+	failures.append(report);
+	failures.print();
+	result?;
+	if failures.has_errors() {
+		return Err(Box::new(failures));
+	}
+
+	debug_eprintln!(
 		"NoteCollection::collect_files() took {} ms",
 		duration_collect_files.num_milliseconds()
 	);
-	debug_println!(
+	debug_eprintln!(
 		"Subcommand {} took {} ms",
 		&config.command,
 		duration_subcommand.num_milliseconds()
@@ -54,12 +215,121 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
 	Ok(())
 }
 
+fn run_subcommand(
+	config: &Config,
+	notes: &mut NoteCollection,
+	root: &path::Path,
+	report: &mut Report,
+) -> Result<(), Box<dyn StdError>> {
+	let is_list = config.command.is_empty() || config.command.starts_with("list-");
+	if config.format != OutputFormat::Markdown && is_list && config.quarantine.is_none() {
+		return write_list(notes, config);
+	}
+
+	match config.command.as_str() {
+		"list-broken-links" => print_broken_links(notes),
+		"list-broken-anchors" => print_broken_anchors(notes),
+		"list-sources" => print_sources(notes),
+		"list-sinks" => print_sinks(notes),
+		"list-isolated" => print_isolated(notes),
+		"list-components" => print_components(notes, config.strong),
+		"list-tasks" => print_tasks(notes),
+		"list-unused-attachments" => match &config.quarantine {
+			Some(dir) if config.dry_run => print_planned_quarantine(notes, root, &root.join(dir)),
+			Some(dir) => quarantine_unused_attachments(notes, root, &root.join(dir))?,
+			None => print_unused_attachments(notes, root),
+		},
+		"check" => check(notes, &config.rules, config.aliased, config.format)?,
+		"remove-backlinks" => remove_backlinks(notes),
+		"update-backlinks" => update_backlinks(notes, config.aliased),
+		"update-filenames" => update_filenames(notes, config.force || config.dry_run, report)?,
+		"export-graph" => export_graph(notes, config)?,
+		"rank" => rank(notes, config.metric, config.top, config.format)?,
+		"path" => print_paths(notes, config)?,
+		"neighbours" => print_neighbours(notes, config)?,
+		_ => print_stats(notes),
+	}
+	Ok(())
+}
+
+/// Write the result of a list subcommand, or the statistics, as JSON or CSV
+fn write_list(note_collection: &NoteCollection, config: &Config) -> Result<(), Box<dyn StdError>> {
+	let format = config.format;
+	let note_records = |notes: Vec<NoteMeta>| -> Vec<NoteRecord> {
+		notes.iter().map(NoteRecord::from).collect()
+	};
+
+	match config.command.as_str() {
+		"list-broken-links" => {
+			let mut records: Vec<LinkRecord> = note_collection
+				.get_broken_link_occurrences()
+				.iter()
+				.map(|(note, link)| LinkRecord::new(note, link, "note"))
+				.collect();
+			records.extend(
+				note_collection
+					.get_broken_attachment_occurrences()
+					.iter()
+					.map(|(note, link)| LinkRecord::new(note, link, "attachment")),
+			);
+			output::write_records(format, &records)
+		}
+		"list-broken-anchors" => {
+			let records: Vec<LinkRecord> = note_collection
+				.get_broken_anchors()
+				.iter()
+				.map(|(note, link)| LinkRecord::new(note, link, LinkRecord::anchor_kind(link)))
+				.collect();
+			output::write_records(format, &records)
+		}
+		"list-sources" => output::write_records(format, &note_records(note_collection.get_sources())),
+		"list-sinks" => output::write_records(format, &note_records(note_collection.get_sinks())),
+		"list-isolated" => output::write_records(format, &note_records(note_collection.get_isolated())),
+		"list-tasks" => {
+			let records: Vec<TaskRecord> = note_collection
+				.get_tasks()
+				.iter()
+				.flat_map(|(note, tasks)| tasks.iter().map(move |task| TaskRecord::new(note, task)))
+				.collect();
+			output::write_records(format, &records)
+		}
+		"list-components" => {
+			let records: Vec<ComponentRecord> = note_collection
+				.get_components(config.strong)
+				.iter()
+				.enumerate()
+				.skip(1)
+				.flat_map(|(i, notes)| notes.iter().map(move |note| ComponentRecord::new(i + 1, notes.len(), note)))
+				.collect();
+			output::write_records(format, &records)
+		}
+		"list-unused-attachments" => {
+			let records: Vec<AttachmentRecord> = note_collection
+				.get_unused_attachments(None)
+				.iter()
+				.map(AttachmentRecord::from)
+				.collect();
+			output::write_records(format, &records)
+		}
+		_ => output::write_records(
+			format,
+			&[StatsRecord {
+				notes: note_collection.count(),
+				notes_with_id: note_collection.count_with_id(),
+				links: note_collection.count_links(),
+				attachments: note_collection.count_attachments(),
+			}],
+		),
+	}
+}
+
 fn print_stats(note_collection: &NoteCollection) {
 	println!("# Statistics\n");
 
 	println!("- Notes in collection: {}", note_collection.count());
 	println!("- Notes with ID: {}", note_collection.count_with_id());
 	println!("- Wikilinks: {}", note_collection.count_links());
+	println!("- Attachments: {}", note_collection.count_attachments());
 }
 
 fn print_tasks(note_collection: &NoteCollection) {
@@ -107,6 +377,170 @@ fn print_isolated(note_collection: &NoteCollection) {
 	print_note_wikilink_list(&notes);
 }
 
+/// Write the link graph, or the part of it within `config.depth` links of
+/// `config.note` in `config.direction`
+fn export_graph(note_collection: &NoteCollection, config: &Config) -> Result<(), Box<dyn StdError>> {
+	let graph = note_collection.get_graph();
+	let nodes: Vec<NodeIndex> = match &config.note {
+		Some(name) => {
+			let start = find_node(note_collection, &graph, name)?;
+			graph
+				.get_neighbourhood(start, config.depth, config.direction)
+				.into_iter()
+				.map(|(node, _)| node)
+				.collect()
+		}
+		None => (0..graph.len()).collect(),
+	};
+	export::write_graph(io::BufWriter::new(io::stdout().lock()), &graph, &nodes, config.graph_format)?;
+	Ok(())
+}
+
+/// Print the notes with the highest score for the metric
+fn rank(
+	note_collection: &NoteCollection,
+	metric: Metric,
+	top: usize,
+	format: OutputFormat,
+) -> Result<(), Box<dyn StdError>> {
+	let ranked = rank::rank_notes(&note_collection.get_graph(), metric, top);
+	if format != OutputFormat::Markdown {
+		let records: Vec<RankRecord> = ranked
+			.iter()
+			.enumerate()
+			.map(|(i, note)| RankRecord::new(i + 1, note))
+			.collect();
+		return output::write_records(format, &records);
+	}
+
+	println!("# Notes by {}\n", metric.title());
+	for (i, note) in ranked.iter().enumerate() {
+		let score = match metric {
+			Metric::InDegree | Metric::OutDegree => note.score(metric).to_string(),
+			Metric::PageRank | Metric::Betweenness => format!("{:.4}", note.score(metric)),
+		};
+		println!("{}. {} ({})", i + 1, note.note.get_wikilink_to(), score);
+	}
+	Ok(())
+}
+
+/// Print the notes within `config.depth` links of `config.note`, with the
+/// number of links to each
+fn print_neighbours(note_collection: &NoteCollection, config: &Config) -> Result<(), Box<dyn StdError>> {
+	let graph = note_collection.get_graph();
+	let start = find_node(note_collection, &graph, config.note.as_deref().unwrap_or_default())?;
+	let mut neighbours: Vec<(usize, &NoteMeta)> = graph
+		.get_neighbourhood(start, config.depth, config.direction)
+		.into_iter()
+		.skip(1)
+		.filter_map(|(node, distance)| graph.nodes()[node].note().map(|note| (distance, note)))
+		.collect();
+	neighbours.sort_by_cached_key(|(distance, note)| (*distance, note.title.to_lowercase()));
+
+	if config.format != OutputFormat::Markdown {
+		let records: Vec<NeighbourRecord> = neighbours
+			.iter()
+			.map(|(distance, note)| NeighbourRecord::new(*distance, note))
+			.collect();
+		return output::write_records(config.format, &records);
+	}
+
+	let start_link = graph.nodes()[start].note().map(NoteMeta::get_wikilink_to).unwrap_or_default();
+	let unit = if config.depth == 1 { "link" } else { "links" };
+	println!("# Notes within {} {} of {}\n", config.depth, unit, start_link);
+	println!("{} notes were found", neighbours.len());
+	for (i, (distance, note)) in neighbours.iter().enumerate() {
+		if i == 0 || neighbours[i - 1].0 != *distance {
+			println!("\n## Distance {}\n", distance);
+		}
+		println!("- {}", note.get_wikilink_to());
+	}
+	Ok(())
+}
+
+/// Print the shortest chains of links from `config.note` to `config.target`
+fn print_paths(note_collection: &NoteCollection, config: &Config) -> Result<(), Box<dyn StdError>> {
+	let graph = note_collection.get_graph().without_missing();
+	let from = find_node(note_collection, &graph, config.note.as_deref().unwrap_or_default())?;
+	let to = find_node(note_collection, &graph, config.target.as_deref().unwrap_or_default())?;
+	let direction = if config.backwards { Direction::Both } else { Direction::Out };
+	let paths = graph.get_shortest_paths(from, to, config.paths, direction);
+
+	// Links that are followed backwards are marked
+	let steps = |path: &[NodeIndex]| -> Vec<(&'static str, &NoteMeta)> {
+		path.iter()
+			.enumerate()
+			.filter_map(|(i, &node)| {
+				let step = match i {
+					0 => "",
+					_ if graph.has_link(path[i - 1], node) => "forward",
+					_ => "backward",
+				};
+				graph.nodes()[node].note().map(|note| (step, note))
+			})
+			.collect()
+	};
+
+	if config.format != OutputFormat::Markdown {
+		let records: Vec<PathRecord> = paths
+			.iter()
+			.enumerate()
+			.flat_map(|(i, path)| {
+				steps(path)
+					.into_iter()
+					.enumerate()
+					.map(move |(step, (direction, note))| PathRecord::new(i + 1, step, direction, note))
+			})
+			.collect();
+		return output::write_records(config.format, &records);
+	}
+
+	let from_link = graph.nodes()[from].note().map(NoteMeta::get_wikilink_to).unwrap_or_default();
+	let to_link = graph.nodes()[to].note().map(NoteMeta::get_wikilink_to).unwrap_or_default();
+	println!("# Paths from {} to {}\n", from_link, to_link);
+	if paths.is_empty() {
+		println!("There is no chain of links between the notes");
+	}
+	for (i, path) in paths.iter().enumerate() {
+		let unit = if path.len() == 2 { "link" } else { "links" };
+		println!("## Path {} ({} {})\n", i + 1, path.len() - 1, unit);
+		for (direction, note) in steps(path) {
+			let arrow = match direction {
+				"forward" => "→ ",
+				"backward" => "← ",
+				_ => "",
+			};
+			println!("- {}{}", arrow, note.get_wikilink_to());
+		}
+		println!();
+	}
+	Ok(())
+}
+
+/// Find the node of a note in the graph, by ID, file name, path or title
+fn find_node(note_collection: &NoteCollection, graph: &NoteGraph, name: &str) -> Result<NodeIndex, Error> {
+	note_collection
+		.find_note(name)
+		.and_then(|note| graph.find(&note.path))
+		.ok_or_else(|| Error::UnknownNote { stem: name.to_owned() })
+}
+
+/// Print the sizes of the connected components, and the notes in all but
+/// the largest one
+fn print_components(note_collection: &NoteCollection, strong: bool) {
+	let components = note_collection.get_components(strong);
+	let kind = if strong { "Strongly" } else { "Weakly" };
+	println!("# {} connected components\n", kind);
+	let sizes: Vec<String> = components.iter().map(|notes| notes.len().to_string()).collect();
+	println!("{} components, with these numbers of notes: {}", components.len(), sizes.join(", "));
+
+	for (i, notes) in components.iter().enumerate().skip(1) {
+		let unit = if notes.len() == 1 { "note" } else { "notes" };
+		println!("\n## Component {} ({} {})\n", i + 1, notes.len(), unit);
+		print_note_wikilink_list(notes);
+	}
+}
+
 fn print_note_wikilink_list(notes: &[NoteMeta]) {
 	for note in notes {
 		println!("- {}", note.get_wikilink_to());
@@ -115,7 +549,8 @@ fn print_note_wikilink_list(notes: &[NoteMeta]) {
 
 fn print_broken_links(note_collection: &NoteCollection) {
 	let broken_links = note_collection.get_broken_links();
-	if broken_links.len() == 0 {
+	let broken_attachments = note_collection.get_broken_attachments();
+	if broken_links.is_empty() && broken_attachments.is_empty() {
 		println!("No broken links found");
 	} else {
 		println!("# Broken links\n");
@@ -124,6 +559,197 @@ fn print_broken_links(note_collection: &NoteCollection) {
 			let linkers: Vec<String> = notes.iter().map(|n| n.get_wikilink_to()).collect();
 			println!("- \"{}\" links to unknown {}", linkers.join(" and "), link);
 		}
+
+		if !broken_attachments.is_empty() {
+			println!("\n## Missing attachments\n");
+
+			for (link, notes) in broken_attachments {
+				let linkers: Vec<String> = notes.iter().map(|n| n.get_wikilink_to()).collect();
+				println!(
+					"- \"{}\" links to unknown attachment {}",
+					linkers.join(" and "),
+					link
+				);
+			}
+		}
+	}
+}
+
+fn print_broken_anchors(note_collection: &NoteCollection) {
+	let broken_anchors = note_collection.get_broken_anchors();
+	if broken_anchors.is_empty() {
+		println!("No broken anchors found");
+	} else {
+		println!("# Broken anchors\n");
+
+		for (note, link) in broken_anchors {
+			println!(
+				"- \"{}\" links to unknown section {}",
+				note.get_wikilink_to(),
+				link
+			);
+		}
+	}
+}
+
+fn print_unused_attachments(note_collection: &NoteCollection, root: &path::Path) {
+	let attachments = note_collection.get_unused_attachments(None);
+
+	println!("# Unused attachments\n");
+	println!(
+		"{} attachments are not linked from any note\n",
+		attachments.len()
+	);
+	for attachment in attachments {
+		println!("- {}", attachment.strip_prefix(root).unwrap_or(&attachment).display());
+	}
+}
+
+fn quarantine_unused_attachments(
+	note_collection: &NoteCollection,
+	root: &path::Path,
+	dir: &path::Path,
+) -> Result<(), Box<dyn StdError>> {
+	let attachments = note_collection.get_unused_attachments(Some(dir));
+	println!(
+		"Moving {} unused attachments to {}\n",
+		attachments.len(),
+		dir.display()
+	);
+
+	for attachment in attachments {
+		ftree::move_to_dir(root, &attachment, dir, note_collection.journal())?;
+		println!("- {}", attachment.strip_prefix(root).unwrap_or(&attachment).display());
+	}
+
+	Ok(())
+}
+
+/// Restore the changes of the last run from the journal
+fn undo(root: &path::Path) -> Result<(), Box<dyn StdError>> {
+	let mut report = Report::default();
+	match journal::undo(root, &mut report)? {
+		Some(paths) => {
+			println!("Restored {} files from the last run\n", paths.len());
+			for path in paths {
+				println!("- {}", path.strip_prefix(root).unwrap_or(&path).display());
+			}
+		}
+		None => println!("Nothing to undo"),
+	}
+
+	report.print();
+	if report.has_errors() {
+		return Err(Box::new(report));
+	}
+	Ok(())
+}
+
+fn check(
+	note_collection: &NoteCollection,
+	rule_names: &[String],
+	aliased: bool,
+	format: OutputFormat,
+) -> Result<(), Box<dyn StdError>> {
+	let rules = check::parse_rules(rule_names)?;
+	let violations = check::check(note_collection, &rules, aliased);
+
+	if format != OutputFormat::Markdown {
+		output::write_records(format, &violations)?;
+	} else {
+		println!("# Check\n");
+		for violation in &violations {
+			println!("{}", violation);
+		}
+		if !violations.is_empty() {
+			println!();
+		}
+
+		println!(
+			"Checked {} notes with {} rules: {} problems found",
+			note_collection.count(),
+			rules.len(),
+			violations.len()
+		);
+		for rule in &rules {
+			let count = violations.iter().filter(|v| v.rule == rule.name()).count();
+			println!("- {}: {}", rule, count);
+		}
+	}
+
+	if violations.is_empty() {
+		Ok(())
+	} else {
+		Err(Box::new(CheckFailed {
+			problems: violations.len(),
+		}))
+	}
+}
+
+fn print_planned_quarantine(note_collection: &NoteCollection, root: &path::Path, dir: &path::Path) {
+	let attachments = note_collection.get_unused_attachments(Some(dir));
+	println!(
+		"Would move {} unused attachments to {}\n",
+		attachments.len(),
+		dir.display()
+	);
+	for attachment in attachments {
+		println!("- {}", attachment.strip_prefix(root).unwrap_or(&attachment).display());
+	}
+}
+
+/// Print the renames and a unified diff of the changes that a dry run
+/// would have written to disk
+fn print_planned_changes(note_collection: &NoteCollection, root: &path::Path) {
+	let relative = |path: &str| {
+		let path = path::Path::new(path);
+		path.strip_prefix(root).unwrap_or(path).display().to_string()
+	};
+
+	let changes = note_collection.take_planned_changes();
+	let renames: Vec<&Change> = changes
+		.iter()
+		.filter(|c| matches!(c, Change::Rename { .. }))
+		.collect();
+	let writes: Vec<&Change> = changes
+		.iter()
+		.filter(|c| matches!(c, Change::Write { .. }))
+		.collect();
+
+	println!("\n# Dry run\n");
+	println!(
+		"Nothing was written to disk. {} files would be renamed and {} files changed.",
+		renames.len(),
+		writes.len()
+	);
+
+	if !renames.is_empty() {
+		println!("\n## Planned renames\n");
+		for change in renames {
+			if let Change::Rename { from, to } = change {
+				println!("- \"{}\" => \"{}\"", relative(from), relative(to));
+			}
+		}
+	}
+
+	if !writes.is_empty() {
+		println!("\n## Planned changes\n");
+		for change in writes {
+			if let Change::Write {
+				path,
+				old_contents,
+				new_contents,
+			} = change
+			{
+				let path = relative(path);
+				print!(
+					"{}",
+					similar::TextDiff::from_lines(old_contents, new_contents)
+						.unified_diff()
+						.header(&format!("a/{}", path), &format!("b/{}", path))
+				);
+			}
+		}
 	}
 }
 
@@ -132,8 +758,8 @@ fn remove_backlinks(note_collection: &NoteCollection) {
 	println!("Removed backlinks section from {} notes", removed.len());
 }
 
-fn update_backlinks(note_collection: &NoteCollection) {
-	let updated = note_collection.update_backlinks();
+fn update_backlinks(note_collection: &NoteCollection, aliased: bool) {
+	let updated = note_collection.update_backlinks(aliased);
 	println!("Updated backlinks section in {} notes", updated.len());
 
 	for note in updated {
@@ -141,7 +767,12 @@ fn update_backlinks(note_collection: &NoteCollection) {
 	}
 }
 
-fn update_filenames(note_collection: &NoteCollection, force: bool) -> Result<(), Box<dyn Error>> {
+/// Rename notes, and add the notes that couldn't be renamed to `report`
+fn update_filenames(
+	note_collection: &mut NoteCollection,
+	force: bool,
+	report: &mut Report,
+) -> Result<(), Box<dyn StdError>> {
 	let mut affected_backlinks = false;
 	for (note, new_stem) in note_collection.get_mismatched_filenames() {
 		let original_filename = format!("{}.{}", note.stem, note.extension);
@@ -156,20 +787,16 @@ fn update_filenames(note_collection: &NoteCollection, force: bool) -> Result<(),
 		};
 
 		if reply == "y" || reply.is_empty() {
-			if note.path.ends_with(&original_filename) {
-				// If note has links to other notes and NOT an ID, this means that there
-				// are backlinks in other notes that are now linking to the old filename
-				affected_backlinks = affected_backlinks || (note.has_links && note.id.is_none());
-
-				let updated_notes = note_collection.rename_note(&note, &new_stem)?;
-				if updated_notes.len() > 0 {
+			match note_collection.rename_note(&note, &new_stem) {
+				Ok(updated_notes) => {
+					// If note has links to other notes and NOT an ID, this means that there
+					// are backlinks in other notes that are now linking to the old filename
+					affected_backlinks = affected_backlinks || (note.has_links && note.id.is_none());
 					for n in updated_notes {
 						println!("- Updated link from {}", n.get_wikilink_to());
 					}
 				}
-			} else {
-				// TODO: Return as Err
-				eprintln!("Error: probably a bug in how the file name path is determined");
+				Err(err) => report.push(err),
 			}
 		}
 	}
@@ -196,7 +823,7 @@ mod tests {
 			fs::remove_dir_all(dir)?;
 		}
 
-		fs::create_dir(&dir)?;
+		fs::create_dir(dir)?;
 
 		Ok(())
 	}
@@ -224,10 +851,11 @@ mod tests {
 		)
 		.unwrap();
 
-		let notes_before = NoteCollection::collect_files(
+		let mut notes_before = NoteCollection::collect_files(
 			&dir,
-			&"md",
-			crate::mdparse::NoteParser::new(&r"\d{14}", &"## Backlinks").unwrap(),
+			&ftree::FileFilter::new("md", &[]).unwrap(),
+			crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+			None,
 		);
 
 		// No extra notes should be found
@@ -235,15 +863,16 @@ mod tests {
 		// No broken links in the test data
 		assert_eq!(notes_before.get_broken_links().len(), 0);
 
-		update_filenames(&notes_before, true).unwrap();
+		update_filenames(&mut notes_before, true, &mut Report::default()).unwrap();
 
 		let notes_after = NoteCollection::collect_files(
 			&dir,
-			&"md",
-			crate::mdparse::NoteParser::new(&r"\d{14}", &"## Backlinks").unwrap(),
+			&ftree::FileFilter::new("md", &[]).unwrap(),
+			crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+			None,
 		);
 
-		for note in notes_after.into_meta_vec() {
+		for note in notes_after.to_meta_vec() {
 			match note.title.as_str() {
 				"Rename This 1" => {
 					assert_eq!(note.stem, "Rename This 1");
@@ -269,4 +898,647 @@ mod tests {
 		assert_eq!(notes_after.get_sources().len(), 1);
 		assert_eq!(notes_after.get_sinks().len(), 1);
 	}
+
+	#[test]
+	fn rename_file_with_labelled_links() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-rename-labels");
+		create_dir(&dir).unwrap();
+
+		write_to_tmp_file(&mut dir.clone(), "old name.md", "# New name").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "linker.md", "# Linker\n\n[[old name|the first note]] and [[Old Name]]").unwrap();
+
+		let mut notes = NoteCollection::collect_files(
+			&dir,
+			&ftree::FileFilter::new("md", &[]).unwrap(),
+			crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+			None,
+		);
+		update_filenames(&mut notes, true, &mut Report::default()).unwrap();
+
+		assert!(dir.join("New name.md").exists());
+		assert_eq!(
+			fs::read_to_string(dir.join("linker.md")).unwrap(),
+			"# Linker\n\n[[New name|the first note]] and [[New name]]"
+		);
+	}
+
+	#[test]
+	fn broken_anchors() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-anchors");
+		create_dir(&dir).unwrap();
+
+		write_to_tmp_file(&mut dir.clone(), "target.md", "# Target\n\n## Section\n\nSome text ^block").unwrap();
+		write_to_tmp_file(
+			&mut dir.clone(),
+			"linker.md",
+			"# Linker\n\n[[target#Section]] [[target#^block]] [[target#Missing]] [[target#^missing|label]] [[unknown#Section]] [[#Own]]\n\n## Own",
+		)
+		.unwrap();
+
+		let notes = NoteCollection::collect_files(
+			&dir,
+			&ftree::FileFilter::new("md", &[]).unwrap(),
+			crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+			None,
+		);
+
+		let broken: Vec<String> = notes
+			.get_broken_anchors()
+			.iter()
+			.map(|(note, link)| format!("{} {}", note.stem, link))
+			.collect();
+		assert_eq!(
+			broken,
+			["linker [[target#Missing]]", "linker [[target#^missing|label]]"]
+		);
+		assert_eq!(notes.get_broken_links().len(), 1);
+	}
+
+	#[test]
+	fn markdown_links() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-mdlinks");
+		create_dir(&dir).unwrap();
+		fs::create_dir(dir.join("sub")).unwrap();
+
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# Alpha\n\n[To beta](sub/b.md) and [missing](nope.md) and [[c]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "sub/b.md", "# Beta Note\n\nNo links here").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# c\n\n[Beta](<sub/b.md>)").unwrap();
+
+		let parser = || crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None);
+		assert_eq!(notes.get_sinks().len(), 1);
+		assert_eq!(notes.get_isolated().len(), 1);
+		assert_eq!(notes.get_broken_links().len(), 0);
+
+		let mut notes = NoteCollection::collect_files(&dir, &filter, parser().with_markdown_links(true), None);
+		assert_eq!(notes.get_sources().len(), 1);
+		assert_eq!(notes.get_sinks().len(), 1);
+		assert_eq!(notes.get_isolated().len(), 0);
+		assert_eq!(notes.get_broken_links().len(), 1);
+
+		update_filenames(&mut notes, true, &mut Report::default()).unwrap();
+
+		let contents = fs::read_to_string(dir.join("Alpha.md")).unwrap();
+		assert!(contents.contains("[To beta](sub/Beta%20Note.md)"));
+		let contents = fs::read_to_string(dir.join("c.md")).unwrap();
+		assert!(contents.contains("[Beta](<sub/Beta Note.md>)"));
+
+		let notes = NoteCollection::collect_files(&dir, &filter, parser().with_markdown_links(true), None);
+		assert_eq!(notes.get_sinks().len(), 1);
+		assert_eq!(notes.get_broken_links().len(), 1);
+	}
+
+	#[test]
+	fn attachments() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-attachments");
+		create_dir(&dir).unwrap();
+		fs::create_dir(dir.join("assets")).unwrap();
+
+		write_to_tmp_file(
+			&mut dir.clone(),
+			"a.md",
+			"# A\n\n![[img.png]] ![[missing.png]] [[doc.pdf]] ![[b]] ![Pic](assets/pic.jpg) ![](nope.gif) [[Node.js]] [[ASP.NET]] [[Dr. No]]",
+		)
+		.unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# B").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# C\n\n![[missing.png]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "assets/img.png", "").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "assets/pic.jpg", "").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "doc.pdf", "").unwrap();
+
+		let notes = NoteCollection::collect_files(
+			&dir,
+			&ftree::FileFilter::new("md", &[]).unwrap(),
+			crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+			None,
+		);
+
+		assert_eq!(notes.count(), 3);
+		assert_eq!(notes.count_attachments(), 3);
+		assert_eq!(notes.get_sinks().len(), 1);
+		// C only links to an attachment
+		assert_eq!(notes.get_isolated().len(), 1);
+
+		let broken_links: Vec<String> = notes
+			.get_broken_links()
+			.iter()
+			.map(|(link, _)| link.to_string())
+			.collect();
+		assert_eq!(broken_links, ["[[ASP.NET]]", "[[Dr. No]]", "[[Node.js]]"]);
+
+		let broken_attachments: Vec<(String, usize)> = notes
+			.get_broken_attachments()
+			.iter()
+			.map(|(link, notes)| (link.value().to_owned(), notes.len()))
+			.collect();
+		assert_eq!(
+			broken_attachments,
+			[
+				("missing.png".to_owned(), 2),
+				(dir.join("nope.gif").to_string_lossy().to_string(), 1),
+			]
+		);
+	}
+
+	#[test]
+	fn unused_attachments() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-unused");
+		create_dir(&dir).unwrap();
+		fs::create_dir(dir.join("sub")).unwrap();
+
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# A\n\n![[img.png]] [Doc](sub/doc.pdf)").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "img.png", "").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "sub/img.png", "").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "sub/doc.pdf", "").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "doc.pdf", "").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "sub/old.png", "").unwrap();
+
+		let parser = || crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None);
+		assert_eq!(
+			notes.get_unused_attachments(None),
+			[dir.join("doc.pdf"), dir.join("sub/old.png")]
+		);
+
+		let quarantine = dir.join(".unused");
+		quarantine_unused_attachments(&notes, &dir, &quarantine).unwrap();
+		assert!(quarantine.join("sub/old.png").exists());
+		assert!(!dir.join("sub/old.png").exists());
+
+		// Hidden directories are not traversed
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None);
+		assert_eq!(notes.get_unused_attachments(None).len(), 0);
+	}
+
+	#[test]
+	fn quarantine_twice() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-quarantine-twice");
+		create_dir(&dir).unwrap();
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# A").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "img.png", "").unwrap();
+
+		let parser = || crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let quarantine = dir.join("unused");
+		let filter = ftree::FileFilter::new("md", &[]).unwrap().with_excluded_dir(&quarantine);
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None);
+		quarantine_unused_attachments(&notes, &dir, &quarantine).unwrap();
+		assert!(quarantine.join("img.png").exists());
+
+		// The quarantine directory is skipped, so nothing is moved again
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None);
+		assert_eq!(notes.get_unused_attachments(None).len(), 0);
+		quarantine_unused_attachments(&notes, &dir, &quarantine).unwrap();
+		assert!(quarantine.join("img.png").exists());
+		assert!(!quarantine.join("unused").exists());
+
+		// Even without the filter
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None);
+		assert_eq!(notes.get_unused_attachments(None), [quarantine.join("img.png")]);
+		assert_eq!(notes.get_unused_attachments(Some(&quarantine)).len(), 0);
+	}
+
+	#[test]
+	fn config_file_and_ignore_patterns() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-config");
+		create_dir(&dir).unwrap();
+		fs::create_dir_all(dir.join("notes/templates")).unwrap();
+		fs::create_dir_all(dir.join("notes/sub")).unwrap();
+
+		write_to_tmp_file(
+			&mut dir.clone(),
+			configfile::CONFIG_FILE_NAME,
+			"extension = \"txt\"\nignore = [\"templates\", \"notes/sub/*.txt\"]\n\n[list-sinks]\nignore = [\"b.txt\"]",
+		)
+		.unwrap();
+		write_to_tmp_file(&mut dir.clone(), "notes/a.txt", "# A\n\n[[b]] [[c]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "notes/b.txt", "# B").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "notes/templates/c.txt", "# C").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "notes/sub/d.txt", "# D").unwrap();
+
+		// The config file is found in a parent directory
+		let config = ConfigFile::find(&dir.join("notes")).unwrap().unwrap();
+		let options = config.options("list-sources");
+		assert_eq!(options.extension.as_deref(), Some("txt"));
+
+		let parser = || crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let filter = ftree::FileFilter::new("txt", &[])
+			.unwrap()
+			.with_patterns_in(config.dir(), &options.ignore, &[])
+			.unwrap();
+		let notes = NoteCollection::collect_files(&dir.join("notes"), &filter, parser(), None);
+		assert_eq!(notes.count(), 2);
+		assert_eq!(notes.get_broken_links().len(), 1);
+
+		let options = config.options("list-sinks");
+		let filter = ftree::FileFilter::new("txt", &[])
+			.unwrap()
+			.with_patterns_in(config.dir(), &options.ignore, &[])
+			.unwrap();
+		let notes = NoteCollection::collect_files(&dir.join("notes"), &filter, parser(), None);
+		assert_eq!(notes.count(), 1);
+
+		assert!(ConfigFile::find(&dir.join("nonexistent")).is_err());
+	}
+
+	#[test]
+	fn check_rules() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-check");
+		create_dir(&dir).unwrap();
+
+		write_to_tmp_file(&mut dir.clone(), "20210101120000 A.md", "# A\n\n[[b]]\n\n[[Missing]] ![[gone.png]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# B\n\n20210101120000\n\n[[c#Nope]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# C\n\n## Backlinks\n\n- [[Old link]]").unwrap();
+
+		let collect = || {
+			NoteCollection::collect_files(
+				&dir,
+				&ftree::FileFilter::new("md", &[]).unwrap(),
+				crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+				None,
+			)
+		};
+		let notes = collect();
+
+		let rules = check::parse_rules(&[]).unwrap();
+		let violations: Vec<String> = check::check(&notes, &rules, false)
+			.iter()
+			.map(|v| format!("{}:{:?} {}", v.rule, v.line, v.message))
+			.collect();
+		assert_eq!(
+			violations,
+			[
+				"broken-links:Some(5) [[Missing]] links to an unknown note",
+				"broken-anchors:Some(5) [[c#Nope]] links to an unknown section",
+				"missing-attachments:Some(5) ![[gone.png]] links to an unknown attachment",
+				"duplicate-ids:None The ID 20210101120000 is also used in \"b\"",
+				"duplicate-ids:None The ID 20210101120000 is also used in \"20210101120000 A\"",
+				"stale-backlinks:None The backlinks section is missing",
+				"stale-backlinks:Some(3) The backlinks section is out of date",
+			]
+		);
+
+		let result = check(&notes, &["stale-backlinks".to_owned()], false, OutputFormat::Markdown);
+		assert_eq!(result.unwrap_err().downcast_ref::<CheckFailed>().unwrap().problems, 2);
+		assert!(check(&notes, &["unknown".to_owned()], false, OutputFormat::Markdown).is_err());
+
+		notes.update_backlinks(false);
+		let notes = collect();
+		assert!(check(&notes, &["stale-backlinks".to_owned()], false, OutputFormat::Markdown).is_ok());
+		assert_eq!(notes.get_stale_backlinks(true).len(), 2);
+	}
+
+	#[test]
+	fn dry_run() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-dryrun");
+		create_dir(&dir).unwrap();
+
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# Alpha").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# B\n\n[[a]] [[c]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# Gamma").unwrap();
+
+		let collect = || {
+			NoteCollection::collect_files(
+				&dir,
+				&ftree::FileFilter::new("md", &[]).unwrap(),
+				crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+				None,
+			)
+			.with_dry_run(true)
+		};
+
+		let notes = collect();
+		assert_eq!(notes.update_backlinks(false).len(), 2);
+		assert_eq!(notes.take_planned_changes().len(), 2);
+
+		let mut notes = collect();
+		update_filenames(&mut notes, true, &mut Report::default()).unwrap();
+		let changes = notes.take_planned_changes();
+		assert_eq!(changes.len(), 3);
+		// Both renames change the same file, which is shown as one change
+		assert!(changes.contains(&Change::Write {
+			path: dir.join("b.md").to_string_lossy().to_string(),
+			old_contents: "# B\n\n[[a]] [[c]]".to_owned(),
+			new_contents: "# B\n\n[[Alpha]] [[Gamma]]".to_owned(),
+		}));
+		assert!(changes.contains(&Change::Rename {
+			from: dir.join("a.md").to_string_lossy().to_string(),
+			to: dir.join("Alpha.md").to_string_lossy().to_string(),
+		}));
+
+		assert_eq!(fs::read_to_string(dir.join("a.md")).unwrap(), "# Alpha");
+		assert_eq!(fs::read_to_string(dir.join("b.md")).unwrap(), "# B\n\n[[a]] [[c]]");
+		assert!(!dir.join("Alpha.md").exists());
+		assert!(!dir.join("Gamma.md").exists());
+	}
+
+	#[test]
+	fn reload_files() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-reload");
+		create_dir(&dir).unwrap();
+
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# A\n\n![[img.png]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# B\n\n[[a]]").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let mut notes = NoteCollection::collect_files(
+			&dir,
+			&filter,
+			crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+			None,
+		);
+		assert_eq!(notes.get_sinks().len(), 1);
+		assert_eq!(notes.get_broken_attachments().len(), 1);
+
+		// Create, change and delete files
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# C\n\n[[b]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "img.png", "").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# A\n\n![[img.png]] [[c]]").unwrap();
+		notes.reload_files(&filter, &[dir.join("a.md"), dir.join("c.md"), dir.join("img.png")]);
+		assert_eq!(notes.count(), 3);
+		assert_eq!(notes.get_sinks().len(), 0);
+		assert_eq!(notes.get_broken_attachments().len(), 0);
+
+		fs::remove_file(dir.join("b.md")).unwrap();
+		notes.reload_files(&filter, &[dir.join("b.md")]);
+		assert_eq!(notes.count(), 2);
+		assert_eq!(notes.get_broken_links().len(), 1);
+
+		notes.update_backlinks(false);
+		let contents = fs::read_to_string(dir.join("c.md")).unwrap();
+		assert!(contents.ends_with("## Backlinks\n\n- [[a]]"));
+	}
+
+	#[test]
+	fn parse_cache() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-parse-cache");
+		create_dir(&dir).unwrap();
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# A\n\n[[b]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# B\n\n## Backlinks\n\n- [[a]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# C").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let parser = || crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let collect = || {
+			let mut cache = ParseCache::load(&dir, &parser());
+			let notes = NoteCollection::collect_files(&dir, &filter, parser(), Some(&mut cache));
+			cache.save().unwrap();
+			notes
+		};
+
+		let notes = collect();
+		assert_eq!(notes.count(), 3);
+		assert!(dir.join(cache::CACHE_FILE_NAME).exists());
+
+		// Only c.md is parsed again, and the cached notes are read when
+		// their backlinks sections are updated
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# C\n\n[[b]]").unwrap();
+		let notes = collect();
+		assert_eq!(notes.count(), 3);
+		assert_eq!(notes.get_sources().len(), 2);
+		let updated: Vec<String> = notes.update_backlinks(false).into_iter().map(|n| n.stem).collect();
+		assert_eq!(updated, ["b"]);
+		let contents = fs::read_to_string(dir.join("b.md")).unwrap();
+		assert_eq!(contents, "# B\n\n## Backlinks\n\n- [[a]]\n- [[c]]");
+
+		// Deleted notes are not found in the cache
+		fs::remove_file(dir.join("a.md")).unwrap();
+		let notes = collect();
+		assert_eq!(notes.count(), 2);
+	}
+
+	#[test]
+	fn deterministic_output() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-deterministic");
+		create_dir(&dir).unwrap();
+		write_to_tmp_file(&mut dir.clone(), "20200101000000 B.md", "# B").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "20200101000000 A.md", "# A").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# C\n\n[[20200101000000]] [[zeta]] [[Alpha]] [[beta]]").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let parser = || crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		for _ in 0..5 {
+			let notes = NoteCollection::collect_files(&dir, &filter, parser(), None).with_dry_run(true);
+			let updated: Vec<String> = notes.update_backlinks(false).into_iter().map(|n| n.stem).collect();
+			assert_eq!(updated, ["20200101000000 A", "20200101000000 B"]);
+			assert_eq!(notes.get_duplicate_ids()[0].1[0].stem, "20200101000000 A");
+
+			let broken: Vec<&str> = notes.get_broken_links().into_iter().map(|(link, _)| link.value()).collect();
+			assert_eq!(broken, ["Alpha", "beta", "zeta"]);
+		}
+	}
+
+	#[test]
+	fn failure_report() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-failures");
+		create_dir(&dir).unwrap();
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# b").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# B, too").unwrap();
+		// An odd number of bytes can't be UTF-16
+		fs::write(dir.join("c.md"), b"\xff\xfe# C").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let parser = crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let mut notes = NoteCollection::collect_files(&dir, &filter, parser, None);
+		assert_eq!(notes.count(), 2);
+		let report = notes.take_report();
+		assert!(report.has_errors());
+		assert!(matches!(report.errors(), [Error::UnknownEncoding { path }] if *path == dir.join("c.md")));
+		assert!(notes.take_report().is_empty());
+
+		// a.md isn't renamed to b.md, but the other notes are still renamed
+		let mut report = Report::default();
+		update_filenames(&mut notes, true, &mut report).unwrap();
+		assert!(matches!(report.errors(), [Error::RenameConflict { .. }]));
+		assert!(dir.join("a.md").exists());
+		assert!(dir.join("B, too.md").exists());
+	}
+
+	#[test]
+	fn save_in_encoding() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-encoding");
+		create_dir(&dir).unwrap();
+		fs::write(dir.join("a.md"), b"# \xc5ngstr\xf6m\n\n[[b]]\n").unwrap();
+		fs::write(dir.join("b.md"), b"\xff\xfe#\x00 \x00B\x00").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let parser = || crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None);
+		assert!(notes.take_report().is_empty());
+		assert_eq!(notes.get_note_by_filename("a").unwrap().title, "Ångström");
+
+		notes.update_backlinks(false);
+		let contents = fs::read(dir.join("b.md")).unwrap();
+		assert!(contents.starts_with(b"\xff\xfe#\x00 \x00B\x00"));
+		let text = String::from_utf16(&contents[2..].chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<_>>()).unwrap();
+		assert!(text.contains("[[a]]"));
+
+		// The note is saved as UTF-8 when it is changed
+		fs::write(dir.join("b.md"), b"\xff\xfe#\x00 \x00B\x00").unwrap();
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None).with_normalized_encoding(true);
+		notes.update_backlinks(false);
+		let contents = fs::read_to_string(dir.join("b.md")).unwrap();
+		assert!(contents.starts_with("# B\n\n## Backlinks"));
+	}
+
+	#[test]
+	fn keep_line_endings() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-line-endings");
+		create_dir(&dir).unwrap();
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# A\r\n\r\nText  \r\n[[b]]\r\n\r\n").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# B\r\n\r\n## Backlinks\r\n\r\n- [[x]]\r\n\r\n<!-- end -->").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# C\n[[a]]").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let parser = || crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None);
+		assert_eq!(notes.update_backlinks(false).len(), 2);
+		assert_eq!(
+			fs::read_to_string(dir.join("a.md")).unwrap(),
+			"# A\r\n\r\nText  \r\n[[b]]\r\n\r\n## Backlinks\r\n\r\n- [[c]]\r\n"
+		);
+		assert_eq!(
+			fs::read_to_string(dir.join("b.md")).unwrap(),
+			"# B\r\n\r\n## Backlinks\r\n\r\n- [[a]]\r\n\r\n<!-- end -->"
+		);
+		assert_eq!(fs::read_to_string(dir.join("c.md")).unwrap(), "# C\n[[a]]");
+
+		// The backlinks sections are up to date
+		let notes = NoteCollection::collect_files(&dir, &filter, parser(), None);
+		assert!(notes.update_backlinks(false).is_empty());
+
+		notes.remove_backlinks();
+		assert_eq!(fs::read_to_string(dir.join("a.md")).unwrap(), "# A\r\n\r\nText  \r\n[[b]]\r\n");
+		assert_eq!(fs::read_to_string(dir.join("b.md")).unwrap(), "# B\r\n\r\n<!-- end -->");
+	}
+
+	#[test]
+	fn undo_last_run() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-undo");
+		create_dir(&dir).unwrap();
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# Alpha").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# B\n\n[[a]]").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let parser = crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let mut notes = NoteCollection::collect_files(&dir, &filter, parser, None).with_journal(&dir);
+		update_filenames(&mut notes, true, &mut Report::default()).unwrap();
+		assert!(dir.join("Alpha.md").exists());
+		assert_eq!(fs::read_to_string(dir.join("b.md")).unwrap(), "# B\n\n[[Alpha]]");
+
+		undo(&dir).unwrap();
+		assert!(!dir.join("Alpha.md").exists());
+		assert_eq!(fs::read_to_string(dir.join("a.md")).unwrap(), "# Alpha");
+		assert_eq!(fs::read_to_string(dir.join("b.md")).unwrap(), "# B\n\n[[a]]");
+		assert!(!dir.join(".noteexplorer-journal").exists());
+	}
+
+	#[test]
+	fn notes_without_backlinks() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-no-backlinks");
+		create_dir(&dir).unwrap();
+		write_to_tmp_file(&mut dir.clone(), "a.md", "---\nbacklinks: false\n---\n# A").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# B\n\n[[a]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "c.md", "# C\n\n[[b]] [[d]]").unwrap();
+		// An old backlinks section is removed
+		write_to_tmp_file(
+			&mut dir.clone(),
+			"d.md",
+			"---\nbacklinks: false\n---\n# D\n\n## Backlinks\n\n- [[c]]\n",
+		)
+		.unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let parser = crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let notes = NoteCollection::collect_files(&dir, &filter, parser, None);
+		assert_eq!(notes.get_stale_backlinks(false).len(), 2);
+		let updated: Vec<String> = notes.update_backlinks(false).into_iter().map(|n| n.stem).collect();
+		assert_eq!(updated, ["b", "d"]);
+		assert_eq!(fs::read_to_string(dir.join("a.md")).unwrap(), "---\nbacklinks: false\n---\n# A");
+		assert_eq!(fs::read_to_string(dir.join("d.md")).unwrap(), "---\nbacklinks: false\n---\n# D\n");
+		assert!(notes.get_broken_links().is_empty());
+	}
+
+	#[test]
+	fn paths_between_notes() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-paths");
+		create_dir(&dir).unwrap();
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# Alpha\n\n[[b]] [[Missing]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.md", "# Beta\n\n[[20210101120000]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "20210101120000 c.md", "# Gamma").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "d.md", "# Delta\n\n[[Missing]] [[20210101120000]]").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let parser = crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks").unwrap();
+		let notes = NoteCollection::collect_files(&dir, &filter, parser, None);
+		let graph = notes.get_graph().without_missing();
+		let alpha = find_node(&notes, &graph, "alpha").unwrap();
+		let gamma = find_node(&notes, &graph, "20210101120000").unwrap();
+		let delta = find_node(&notes, &graph, "d").unwrap();
+		assert!(find_node(&notes, &graph, "Missing").is_err());
+
+		let stems = |paths: Vec<Vec<NodeIndex>>| -> Vec<Vec<String>> {
+			paths
+				.iter()
+				.map(|path| path.iter().map(|&n| graph.nodes()[n].note().unwrap().stem.clone()).collect())
+				.collect()
+		};
+		assert_eq!(stems(graph.get_shortest_paths(alpha, gamma, 1, Direction::Out)), [["a", "b", "20210101120000 c"]]);
+		// Links to unknown notes don't connect notes
+		assert!(graph.get_shortest_paths(alpha, delta, 5, Direction::Out).is_empty());
+		assert_eq!(
+			stems(graph.get_shortest_paths(alpha, delta, 5, Direction::Both)),
+			[["a", "b", "20210101120000 c", "d"]]
+		);
+	}
+
+	#[test]
+	fn several_extensions() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-extensions");
+		create_dir(&dir).unwrap();
+		write_to_tmp_file(&mut dir.clone(), "a.md", "# Alpha").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "b.MARKDOWN", "# Beta\n\n[[a]]").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "c.txt", "# c\n\n    [[a]]\n\n```\n[[b]]\n```").unwrap();
+		write_to_tmp_file(&mut dir.clone(), "d.html", "[[a]]").unwrap();
+
+		let filter = ftree::FileFilter::new("md, .markdown,TXT", &[]).unwrap();
+		let parser = crate::mdparse::NoteParser::new(r"\d{14}", "## Backlinks")
+			.unwrap()
+			.with_profile("txt", ParserProfile::Text);
+		let mut notes = NoteCollection::collect_files(&dir, &filter, parser, None);
+		assert_eq!(notes.count(), 3);
+		// Code blocks aren't detected in plain text
+		assert_eq!(notes.get_links(&dir.join("c.txt")).len(), 2);
+		assert_eq!(notes.get_backlinks(&dir.join("a.md")).len(), 2);
+
+		let mut report = Report::default();
+		update_filenames(&mut notes, true, &mut report).unwrap();
+		assert!(report.is_empty());
+		assert!(dir.join("Alpha.md").exists());
+		assert!(dir.join("Beta.MARKDOWN").exists());
+		assert!(dir.join("c.txt").exists());
+		assert_eq!(fs::read_to_string(dir.join("c.txt")).unwrap(), "# c\n\n    [[Alpha]]\n\n```\n[[Beta]]\n```");
+	}
 }
@@ -0,0 +1,716 @@
+use crate::check::{self, Rule};
+use crate::encoding;
+use crate::error::Report;
+use crate::ftree;
+use crate::mdparse;
+use crate::note::{Change, NoteCollection, NoteFile};
+use crate::watch;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{self, Notification as _};
+use lsp_types::request::{self, Request as _};
+use lsp_types::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path;
+use std::time::Duration;
+
+/// Rules that are published as diagnostics. Stale backlinks are left out,
+/// since every new link would make a note stale while typing.
+const DIAGNOSTIC_RULES: [Rule; 4] = [
+	Rule::BrokenLinks,
+	Rule::BrokenAnchors,
+	Rule::MissingAttachments,
+	Rule::DuplicateIds,
+];
+
+/// Time without changes before the diagnostics are published, since
+/// checking all notes on every keystroke would be too slow
+const DIAGNOSTICS_DELAY: Duration = Duration::from_millis(300);
+
+/// Serve the Language Server Protocol over stdin and stdout, until the
+/// client shuts the server down
+pub fn serve(notes: NoteCollection, root: &path::Path, filter: ftree::FileFilter) -> Result<(), Box<dyn Error>> {
+	let (connection, io_threads) = Connection::stdio();
+	let capabilities = ServerCapabilities {
+		text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+		completion_provider: Some(CompletionOptions {
+			trigger_characters: Some(vec!["[".to_owned()]),
+			..Default::default()
+		}),
+		definition_provider: Some(OneOf::Left(true)),
+		references_provider: Some(OneOf::Left(true)),
+		rename_provider: Some(OneOf::Left(true)),
+		..Default::default()
+	};
+	let params = connection.initialize(serde_json::to_value(capabilities)?)?;
+	let params: InitializeParams = serde_json::from_value(params)?;
+
+	let mut server = Server::new(connection, notes, root, filter);
+	let watches_files = params
+		.capabilities
+		.workspace
+		.and_then(|w| w.did_change_watched_files)
+		.and_then(|d| d.dynamic_registration)
+		.unwrap_or(false);
+	if watches_files {
+		server.register_file_watcher()?;
+	}
+	server.publish_diagnostics()?;
+	server.main_loop()?;
+
+	drop(server);
+	io_threads.join()?;
+	Ok(())
+}
+
+struct Server {
+	connection: Connection,
+	notes: NoteCollection,
+	root: path::PathBuf,
+	filter: ftree::FileFilter,
+	/// Contents of the notes that are open in the editor
+	documents: HashMap<path::PathBuf, String>,
+	/// Diagnostics that the client has, by note path
+	diagnostics: HashMap<path::PathBuf, Vec<Diagnostic>>,
+}
+
+impl Server {
+	fn new(connection: Connection, notes: NoteCollection, root: &path::Path, filter: ftree::FileFilter) -> Server {
+		Server {
+			connection,
+			notes,
+			root: root.to_path_buf(),
+			filter,
+			documents: HashMap::new(),
+			diagnostics: HashMap::new(),
+		}
+	}
+
+	fn main_loop(&mut self) -> Result<(), Box<dyn Error>> {
+		let mut changed = false;
+		loop {
+			let message = if changed {
+				match self.connection.receiver.recv_timeout(DIAGNOSTICS_DELAY) {
+					Ok(message) => message,
+					Err(err) if err.is_timeout() => {
+						self.publish_diagnostics()?;
+						changed = false;
+						continue;
+					}
+					Err(_) => break,
+				}
+			} else {
+				match self.connection.receiver.recv() {
+					Ok(message) => message,
+					Err(_) => break,
+				}
+			};
+			match message {
+				Message::Request(req) => {
+					if self.connection.handle_shutdown(&req)? {
+						return Ok(());
+					}
+					let response = self.handle_request(req);
+					self.connection.sender.send(response.into())?;
+				}
+				Message::Notification(not) => {
+					self.handle_notification(not);
+					// Notes that couldn't be read are logged by the editor
+					self.notes.take_report().print();
+					changed = true;
+				}
+				Message::Response(_) => {}
+			}
+		}
+		Ok(())
+	}
+
+	fn handle_request(&mut self, req: Request) -> Response {
+		match req.method.as_str() {
+			request::Completion::METHOD => self.handle::<request::Completion>(req, Server::completion),
+			request::GotoDefinition::METHOD => self.handle::<request::GotoDefinition>(req, Server::definition),
+			request::References::METHOD => self.handle::<request::References>(req, Server::references),
+			request::Rename::METHOD => self.handle::<request::Rename>(req, Server::rename),
+			_ => Response::new_err(
+				req.id,
+				ErrorCode::MethodNotFound as i32,
+				format!("Unknown method {}", req.method),
+			),
+		}
+	}
+
+	fn handle<R: request::Request>(
+		&mut self,
+		req: Request,
+		handler: fn(&mut Server, R::Params) -> Result<R::Result, String>,
+	) -> Response {
+		match serde_json::from_value(req.params) {
+			Ok(params) => match handler(self, params) {
+				Ok(result) => Response::new_ok(req.id, result),
+				Err(message) => Response::new_err(req.id, ErrorCode::RequestFailed as i32, message),
+			},
+			Err(err) => Response::new_err(req.id, ErrorCode::InvalidParams as i32, err.to_string()),
+		}
+	}
+
+	fn handle_notification(&mut self, not: Notification) {
+		match not.method.as_str() {
+			notification::DidOpenTextDocument::METHOD => {
+				if let Ok(params) = serde_json::from_value::<DidOpenTextDocumentParams>(not.params) {
+					self.set_document(&params.text_document.uri, params.text_document.text);
+				}
+			}
+			notification::DidChangeTextDocument::METHOD => {
+				if let Ok(params) = serde_json::from_value::<DidChangeTextDocumentParams>(not.params) {
+					// With full sync, the last change has all of the text
+					if let Some(change) = params.content_changes.into_iter().last() {
+						self.set_document(&params.text_document.uri, change.text);
+					}
+				}
+			}
+			notification::DidCloseTextDocument::METHOD => {
+				if let Ok(params) = serde_json::from_value::<DidCloseTextDocumentParams>(not.params) {
+					// Unsaved changes are thrown away
+					if let Some(path) = to_path(&params.text_document.uri) {
+						if self.documents.remove(&path).is_some() {
+							self.reload(&[path]);
+						}
+					}
+				}
+			}
+			notification::DidChangeWatchedFiles::METHOD => {
+				if let Ok(params) = serde_json::from_value::<DidChangeWatchedFilesParams>(not.params) {
+					let paths: Vec<path::PathBuf> =
+						params.changes.iter().filter_map(|change| to_path(&change.uri)).collect();
+					let mut report = Report::default();
+					let files = watch::get_affected_files(&self.notes, &self.root, &self.filter, &paths, &mut report);
+					report.print();
+					self.reload(&files);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	/// Ask the client to tell about changes to files that are not open
+	fn register_file_watcher(&self) -> Result<(), Box<dyn Error>> {
+		let options = DidChangeWatchedFilesRegistrationOptions {
+			watchers: vec![FileSystemWatcher {
+				glob_pattern: GlobPattern::String("**/*".to_owned()),
+				kind: None,
+			}],
+		};
+		let params = RegistrationParams {
+			registrations: vec![Registration {
+				id: "noteexplorer-watched-files".to_owned(),
+				method: notification::DidChangeWatchedFiles::METHOD.to_owned(),
+				register_options: Some(serde_json::to_value(options)?),
+			}],
+		};
+		let req = Request::new(
+			"register-watched-files".to_owned().into(),
+			request::RegisterCapability::METHOD.to_owned(),
+			params,
+		);
+		self.connection.sender.send(req.into())?;
+		Ok(())
+	}
+
+	fn is_note(&self, path: &path::Path) -> bool {
+		self.filter.is_note(path) && self.filter.includes(&self.root, path)
+	}
+
+	/// Update a note with the contents in the editor
+	fn set_document(&mut self, uri: &Url, contents: String) {
+		if let Some(path) = to_path(uri).filter(|path| self.is_note(path)) {
+			self.notes.set_note_contents(&path, contents.clone());
+			self.documents.insert(path, contents);
+		}
+	}
+
+	/// Reread files from disk, except for notes that are open in the editor
+	fn reload(&mut self, paths: &[path::PathBuf]) {
+		let files: Vec<path::PathBuf> = paths
+			.iter()
+			.filter(|path| !self.documents.contains_key(*path))
+			.cloned()
+			.collect();
+		self.notes.reload_files(&self.filter, &files);
+	}
+
+	/// Get a line of a note, as it is in the editor
+	fn get_line(&self, path: &path::Path, line: u32) -> Option<String> {
+		let contents = match self.documents.get(path) {
+			Some(contents) => contents.clone(),
+			None => encoding::read_text(path).ok()?.0,
+		};
+		mdparse::nth_line(&contents, line as usize).map(|l| l.to_owned())
+	}
+
+	/// Get the note that a request is about: the note linked at the
+	/// position, or else the note itself
+	fn get_target_note(&self, params: &TextDocumentPositionParams) -> Option<path::PathBuf> {
+		let path = to_path(&params.text_document.uri)?;
+		let line = self.get_line(&path, params.position.line).unwrap_or_default();
+		let column = byte_offset(&line, params.position.character);
+		match self.notes.parser().get_link_at(&line, column) {
+			Some(link) => self.notes.get_link_target(&path, &link),
+			None => Some(path),
+		}
+		.filter(|target| self.notes.get_note(target).is_some())
+	}
+
+	/// Complete "[[" with the titles and IDs of all notes
+	fn completion(&mut self, params: CompletionParams) -> Result<Option<CompletionResponse>, String> {
+		let position = params.text_document_position.position;
+		let path = match to_path(&params.text_document_position.text_document.uri) {
+			Some(path) => path,
+			None => return Ok(None),
+		};
+		let line = self.get_line(&path, position.line).unwrap_or_default();
+		let cursor = byte_offset(&line, position.character);
+
+		let start = match line[..cursor].rfind("[[") {
+			Some(start) => start + 2,
+			None => return Ok(None),
+		};
+		let typed = &line[start..cursor];
+		if typed.contains("]]") || typed.contains('|') || typed.contains('#') {
+			return Ok(None);
+		}
+		// Editors often close the brackets by themselves
+		let closing = if line[cursor..].starts_with("]]") { "" } else { "]]" };
+		let range = Range::new(Position::new(position.line, utf16_len(&line[..start])), position);
+
+		let items = self
+			.notes
+			.to_meta_vec()
+			.into_iter()
+			.map(|note| {
+				let target = note.id.clone().unwrap_or_else(|| note.stem.clone());
+				let label = if note.title.is_empty() { note.stem.clone() } else { note.title.clone() };
+				CompletionItem {
+					filter_text: Some(format!("{} {}", label, target)),
+					detail: Some(target.clone()),
+					label,
+					kind: Some(CompletionItemKind::FILE),
+					text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+						range,
+						format!("{}{}", target, closing),
+					))),
+					..Default::default()
+				}
+			})
+			.collect();
+		Ok(Some(CompletionResponse::Array(items)))
+	}
+
+	/// Go to the note or attachment that the link at the position points to
+	fn definition(&mut self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>, String> {
+		let params = params.text_document_position_params;
+		let path = match to_path(&params.text_document.uri) {
+			Some(path) => path,
+			None => return Ok(None),
+		};
+		let line = self.get_line(&path, params.position.line).unwrap_or_default();
+		let column = byte_offset(&line, params.position.character);
+		let target = self
+			.notes
+			.parser()
+			.get_link_at(&line, column)
+			.and_then(|link| self.notes.get_link_target(&path, &link));
+
+		Ok(target.map(|target| {
+			GotoDefinitionResponse::Scalar(Location::new(to_uri(&target), Range::default()))
+		}))
+	}
+
+	/// Find the links to the note at the position, using the backlinks
+	fn references(&mut self, params: ReferenceParams) -> Result<Option<Vec<Location>>, String> {
+		let target = match self.get_target_note(&params.text_document_position) {
+			Some(target) => target,
+			None => return Ok(None),
+		};
+
+		let mut locations = Vec::new();
+		if params.context.include_declaration {
+			locations.push(Location::new(to_uri(&target), Range::default()));
+		}
+		for (note, link) in self.notes.get_references(&target) {
+			locations.push(Location::new(to_uri(&note.path), line_range(link.line)));
+		}
+		Ok(Some(locations))
+	}
+
+	/// Rename the note at the position, and update the links to it in all
+	/// notes. The changes are planned here, and made by the editor.
+	fn rename(&mut self, params: RenameParams) -> Result<Option<WorkspaceEdit>, String> {
+		let target = self
+			.get_target_note(&params.text_document_position)
+			.ok_or("There is no note to rename here")?;
+		let note = self.notes.get_note(&target).ok_or("There is no note to rename here")?;
+
+		let new_stem = params.new_name.trim();
+		if new_stem.is_empty() || NoteFile::clean_filename(new_stem) != new_stem {
+			return Err(format!("\"{}\" can't be used as a file name", new_stem));
+		}
+		let new_path = target.with_file_name(format!("{}.{}", new_stem, note.extension));
+
+		self.notes.set_dry_run(true);
+		let renamed = self.notes.rename_note(&note, new_stem);
+		self.notes.set_dry_run(false);
+		let changes = self.notes.take_planned_changes();
+
+		// Undo the changes in memory. The editor tells when they are made.
+		let mut paths = vec![target, new_path];
+		for change in &changes {
+			if let Change::Write { path, .. } = change {
+				paths.push(path::PathBuf::from(path));
+			}
+		}
+		self.notes.reload_files(&self.filter, &paths);
+		for path in &paths {
+			if let Some(contents) = self.documents.get(path) {
+				self.notes.set_note_contents(path, contents.clone());
+			}
+		}
+		renamed.map_err(|err| err.to_string())?;
+
+		Ok(Some(get_workspace_edit(changes)))
+	}
+
+	/// Publish the problems in notes that have changed since last time
+	fn publish_diagnostics(&mut self) -> Result<(), Box<dyn Error>> {
+		let mut diagnostics: HashMap<path::PathBuf, Vec<Diagnostic>> = HashMap::new();
+		for violation in check::check(&self.notes, &DIAGNOSTIC_RULES, false) {
+			diagnostics
+				.entry(path::PathBuf::from(&violation.path))
+				.or_default()
+				.push(Diagnostic {
+					range: line_range(violation.line.unwrap_or(1)),
+					severity: Some(DiagnosticSeverity::WARNING),
+					code: Some(NumberOrString::String(violation.rule.to_owned())),
+					source: Some("noteexplorer".to_owned()),
+					message: violation.message,
+					..Default::default()
+				});
+		}
+		// Clear the diagnostics of notes that have no problems left
+		for path in self.diagnostics.keys() {
+			diagnostics.entry(path.clone()).or_default();
+		}
+
+		for (path, note_diagnostics) in diagnostics {
+			if self.diagnostics.get(&path) == Some(&note_diagnostics) {
+				continue;
+			}
+			let params = PublishDiagnosticsParams::new(to_uri(&path), note_diagnostics.clone(), None);
+			let not = Notification::new(notification::PublishDiagnostics::METHOD.to_owned(), params);
+			self.connection.sender.send(not.into())?;
+
+			if note_diagnostics.is_empty() {
+				self.diagnostics.remove(&path);
+			} else {
+				self.diagnostics.insert(path, note_diagnostics);
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Turn changes planned in a dry run into edits for the editor
+fn get_workspace_edit(changes: Vec<Change>) -> WorkspaceEdit {
+	let mut edits = Vec::new();
+	let mut renames = Vec::new();
+	for change in changes {
+		match change {
+			Change::Write {
+				path,
+				old_contents,
+				new_contents,
+			} => edits.push(DocumentChangeOperation::Edit(TextDocumentEdit {
+				text_document: OptionalVersionedTextDocumentIdentifier {
+					uri: to_uri(&path),
+					version: None,
+				},
+				edits: vec![OneOf::Left(TextEdit::new(full_range(&old_contents), new_contents))],
+			})),
+			Change::Rename { from, to } => {
+				renames.push(DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+					old_uri: to_uri(&from),
+					new_uri: to_uri(&to),
+					options: None,
+					annotation_id: None,
+				})))
+			}
+		}
+	}
+	// Edit the linking notes before the renamed note moves
+	edits.append(&mut renames);
+
+	WorkspaceEdit {
+		document_changes: Some(DocumentChanges::Operations(edits)),
+		..Default::default()
+	}
+}
+
+fn to_path(uri: &Url) -> Option<path::PathBuf> {
+	uri.to_file_path().ok()
+}
+
+fn to_uri<P: AsRef<path::Path>>(path: P) -> Url {
+	Url::from_file_path(path).expect("Note paths are absolute")
+}
+
+/// Byte offset in `line` of a character position, which is counted in
+/// UTF-16 code units in LSP
+fn byte_offset(line: &str, character: u32) -> usize {
+	let mut units = 0;
+	for (offset, c) in line.char_indices() {
+		if units >= character as usize {
+			return offset;
+		}
+		units += c.len_utf16();
+	}
+	line.len()
+}
+
+fn utf16_len(text: &str) -> u32 {
+	text.encode_utf16().count() as u32
+}
+
+/// Range of a whole line, with line numbers starting at 1
+fn line_range(line: usize) -> Range {
+	let line = line.saturating_sub(1) as u32;
+	Range::new(Position::new(line, 0), Position::new(line + 1, 0))
+}
+
+/// Range that covers all of the text
+fn full_range(text: &str) -> Range {
+	let last_line_start = text.rfind(['\n', '\r']).map_or(0, |i| i + 1);
+	let lines = crate::mdparse::line_number(text, text.len()) - 1;
+	Range::new(
+		Position::new(0, 0),
+		Position::new(lines as u32, utf16_len(&text[last_line_start..])),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::lsp::*;
+	use crate::mdparse::NoteParser;
+	use std::env::temp_dir;
+	use std::fs;
+
+	fn create_server(name: &str, files: &[(&str, &str)]) -> (Server, Connection, path::PathBuf) {
+		let mut dir = temp_dir();
+		dir.push(name);
+		if dir.exists() {
+			fs::remove_dir_all(&dir).unwrap();
+		}
+		fs::create_dir_all(&dir).unwrap();
+		let dir = fs::canonicalize(dir).unwrap();
+		for (name, contents) in files {
+			fs::write(dir.join(name), contents).unwrap();
+		}
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let notes = NoteCollection::collect_files(
+			&dir,
+			&filter,
+			NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+			None,
+		);
+		let (server_connection, client_connection) = Connection::memory();
+		(Server::new(server_connection, notes, &dir, filter), client_connection, dir)
+	}
+
+	fn position(path: &path::Path, line: u32, character: u32) -> TextDocumentPositionParams {
+		TextDocumentPositionParams::new(TextDocumentIdentifier::new(to_uri(path)), Position::new(line, character))
+	}
+
+	#[test]
+	fn completion_and_definition() {
+		let (mut server, _client, dir) = create_server(
+			"noteexplorer-test-lsp-completion",
+			&[("a.md", "# Note A\n\nSee [[b"), ("20210101120000 B.md", "# Note B")],
+		);
+
+		let response = server
+			.completion(CompletionParams {
+				text_document_position: position(&dir.join("a.md"), 2, 7),
+				work_done_progress_params: Default::default(),
+				partial_result_params: Default::default(),
+				context: None,
+			})
+			.unwrap();
+		let items = match response {
+			Some(CompletionResponse::Array(items)) => items,
+			_ => panic!("Expected completion items"),
+		};
+		let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+		assert_eq!(labels, ["Note A", "Note B"]);
+		assert_eq!(
+			items[1].text_edit,
+			Some(CompletionTextEdit::Edit(TextEdit::new(
+				Range::new(Position::new(2, 6), Position::new(2, 7)),
+				"20210101120000]]".to_owned()
+			)))
+		);
+
+		// Not inside a link
+		server.set_document(&to_uri(dir.join("a.md")), "# Note A\n\nSee [[20210101120000]]".to_owned());
+		let response = server.completion(CompletionParams {
+			text_document_position: position(&dir.join("a.md"), 0, 3),
+			work_done_progress_params: Default::default(),
+			partial_result_params: Default::default(),
+			context: None,
+		});
+		assert_eq!(response, Ok(None));
+
+		let response = server
+			.definition(GotoDefinitionParams {
+				text_document_position_params: position(&dir.join("a.md"), 2, 10),
+				work_done_progress_params: Default::default(),
+				partial_result_params: Default::default(),
+			})
+			.unwrap();
+		assert_eq!(
+			response,
+			Some(GotoDefinitionResponse::Scalar(Location::new(
+				to_uri(dir.join("20210101120000 B.md")),
+				Range::default()
+			)))
+		);
+	}
+
+	#[test]
+	fn references_and_diagnostics() {
+		let (mut server, client, dir) = create_server(
+			"noteexplorer-test-lsp-references",
+			&[("a.md", "# A\n\n[[b]]\n\n[[b#Nope]]"), ("b.md", "# B\n\n[[c]]"), ("c.md", "# C")],
+		);
+
+		let response = server
+			.references(ReferenceParams {
+				text_document_position: position(&dir.join("b.md"), 0, 0),
+				work_done_progress_params: Default::default(),
+				partial_result_params: Default::default(),
+				context: ReferenceContext {
+					include_declaration: false,
+				},
+			})
+			.unwrap();
+		assert_eq!(
+			response,
+			Some(vec![
+				Location::new(to_uri(dir.join("a.md")), line_range(3)),
+				Location::new(to_uri(dir.join("a.md")), line_range(5)),
+			])
+		);
+
+		let published = |client: &Connection| -> Vec<PublishDiagnosticsParams> {
+			client
+				.receiver
+				.try_iter()
+				.map(|message| match message {
+					Message::Notification(not) => serde_json::from_value(not.params).unwrap(),
+					_ => panic!("Expected a notification"),
+				})
+				.collect()
+		};
+
+		server.publish_diagnostics().unwrap();
+		let diagnostics = published(&client);
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].uri, to_uri(dir.join("a.md")));
+		assert_eq!(diagnostics[0].diagnostics[0].range, line_range(5));
+		assert_eq!(
+			diagnostics[0].diagnostics[0].code,
+			Some(NumberOrString::String("broken-anchors".to_owned()))
+		);
+
+		// Nothing changed, so nothing is published
+		server.publish_diagnostics().unwrap();
+		assert!(published(&client).is_empty());
+
+		// The note is edited, and then the deleted note is noticed
+		server.set_document(&to_uri(dir.join("a.md")), "# A\n\n[[b]]".to_owned());
+		fs::remove_file(dir.join("c.md")).unwrap();
+		server.reload(&[dir.join("c.md")]);
+		server.publish_diagnostics().unwrap();
+		let mut diagnostics = published(&client);
+		diagnostics.sort_by(|a, b| a.uri.cmp(&b.uri));
+		assert_eq!(diagnostics.len(), 2);
+		assert!(diagnostics[0].diagnostics.is_empty());
+		assert_eq!(diagnostics[1].uri, to_uri(dir.join("b.md")));
+		assert_eq!(
+			diagnostics[1].diagnostics[0].message,
+			"[[c]] links to an unknown note"
+		);
+	}
+
+	#[test]
+	fn rename() {
+		let (mut server, _client, dir) = create_server(
+			"noteexplorer-test-lsp-rename",
+			&[("a.md", "# A\n\n[[b]] and [[B|bee]]"), ("b.md", "# B")],
+		);
+
+		let params = |new_name: &str| RenameParams {
+			text_document_position: position(&dir.join("a.md"), 2, 3),
+			new_name: new_name.to_owned(),
+			work_done_progress_params: Default::default(),
+		};
+		assert!(server.rename(params("a")).is_err());
+		assert!(server.rename(params("c?")).is_err());
+
+		let edit = server.rename(params("c")).unwrap().unwrap();
+		assert_eq!(
+			edit.document_changes,
+			Some(DocumentChanges::Operations(vec![
+				DocumentChangeOperation::Edit(TextDocumentEdit {
+					text_document: OptionalVersionedTextDocumentIdentifier {
+						uri: to_uri(dir.join("a.md")),
+						version: None,
+					},
+					edits: vec![OneOf::Left(TextEdit::new(
+						Range::new(Position::new(0, 0), Position::new(2, 19)),
+						"# A\n\n[[c]] and [[c|bee]]".to_owned()
+					))],
+				}),
+				DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+					old_uri: to_uri(dir.join("b.md")),
+					new_uri: to_uri(dir.join("c.md")),
+					options: None,
+					annotation_id: None,
+				})),
+			]))
+		);
+
+		// Nothing is changed until the editor has made the changes
+		assert!(dir.join("b.md").exists());
+		assert!(server.notes.get_note(&dir.join("b.md")).is_some());
+		assert_eq!(server.notes.get_references(&dir.join("b.md")).len(), 2);
+	}
+
+	#[test]
+	fn positions() {
+		assert_eq!(byte_offset("aö€b", 0), 0);
+		assert_eq!(byte_offset("aö€b", 2), 3);
+		assert_eq!(byte_offset("aö€b", 3), 6);
+		assert_eq!(byte_offset("a😀b", 3), 5);
+		assert_eq!(byte_offset("ab", 10), 2);
+		assert_eq!(utf16_len("a😀b"), 4);
+
+		assert_eq!(full_range(""), Range::new(Position::new(0, 0), Position::new(0, 0)));
+		assert_eq!(
+			full_range("a\r\nbö\n"),
+			Range::new(Position::new(0, 0), Position::new(2, 0))
+		);
+		assert_eq!(
+			full_range("a\nbö"),
+			Range::new(Position::new(0, 0), Position::new(1, 2))
+		);
+	}
+}
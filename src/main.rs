@@ -1,7 +1,8 @@
 use chrono::Utc;
-use clap::{crate_version, App, Arg, SubCommand};
-use debug_print::debug_println;
-use noteexplorer::{run, Config};
+use clap::{crate_version, App, Arg, ArgMatches, SubCommand};
+use debug_print::debug_eprintln;
+use noteexplorer::{run, CheckFailed, Config, ConfigFile, Direction, GraphFormat, Metric, Report};
+use std::path::Path;
 use std::process;
 
 fn main() {
@@ -13,7 +14,7 @@ fn main() {
 			Arg::with_name("extension")
 				.short("e")
 				.long("extension")
-				.help("File extension of note files")
+				.help("File extensions of note files, separated by commas")
 				.takes_value(true)
 				.value_name("ext")
 				.default_value("md"),
@@ -36,6 +37,73 @@ fn main() {
 				.value_name("format")
 				.default_value("## Links to this note"),
 		)
+		.arg(
+			Arg::with_name("markdown_links")
+				.short("m")
+				.long("markdown-links")
+				.help("Also follow Markdown links to relative paths, like [text](note.md)"),
+		)
+		.arg(
+			Arg::with_name("no_markdown_links")
+				.long("no-markdown-links")
+				.help("Don't follow Markdown links, even if set in the config file")
+				.conflicts_with("markdown_links"),
+		)
+		.arg(
+			Arg::with_name("format")
+				.long("format")
+				.help("Output format of list subcommands")
+				.takes_value(true)
+				.value_name("format")
+				.possible_values(&["markdown", "json", "csv"])
+				.default_value("markdown"),
+		)
+		.arg(
+			Arg::with_name("cache")
+				.long("cache")
+				.help("Keep parsed notes in a cache file, so that the next run only parses changed notes")
+				.conflicts_with("no_cache"),
+		)
+		.arg(
+			Arg::with_name("no_cache")
+				.long("no-cache")
+				.help("Read and parse all notes, without using or updating the cache file"),
+		)
+		.arg(
+			Arg::with_name("exclude")
+				.long("exclude")
+				.help("Skip files and directories matching the glob pattern")
+				.takes_value(true)
+				.value_name("glob")
+				.multiple(true)
+				.number_of_values(1),
+		)
+		.arg(
+			Arg::with_name("include")
+				.long("include")
+				.help("Only use files matching the glob pattern")
+				.takes_value(true)
+				.value_name("glob")
+				.multiple(true)
+				.number_of_values(1),
+		)
+		.arg(
+			Arg::with_name("profile")
+				.long("profile")
+				.help("Parse files with the extension as \"markdown\" or \"text\"")
+				.takes_value(true)
+				.value_name("ext=profile")
+				.multiple(true)
+				.number_of_values(1),
+		)
+		.arg(
+			Arg::with_name("normalize_encoding")
+				.long("normalize-encoding")
+				.help("Save changed notes in this encoding, instead of the encoding they had")
+				.takes_value(true)
+				.value_name("encoding")
+				.possible_values(&["utf8"]),
+		)
 		.arg(
 			Arg::with_name("PATH")
 				.help("Path to the note files directory")
@@ -47,11 +115,27 @@ fn main() {
 				.alias("brokenlinks")
 				.about("Prints a list of broken links"),
 		)
+		.subcommand(
+			SubCommand::with_name("list-broken-anchors")
+				.alias("brokenanchors")
+				.about("Prints a list of links to unknown headings and block IDs"),
+		)
 		.subcommand(
 			SubCommand::with_name("list-isolated")
 				.alias("isolated")
 				.about("Prints a list of notes with no incoming or outgoing links"),
 		)
+		.subcommand(
+			SubCommand::with_name("list-components")
+				.alias("components")
+				.about("Prints groups of linked notes that aren't linked to the largest group")
+				.arg(
+					Arg::with_name("strong")
+						.short("s")
+						.long("strong")
+						.help("Only group notes that can all be reached from each other by following links"),
+				),
+		)
 		.subcommand(
 			SubCommand::with_name("list-sinks")
 				.alias("sinks")
@@ -67,14 +151,74 @@ fn main() {
 				.aliases(&["tasks", "todos"])
 				.about("Prints a list of tasks"),
 		)
+		.subcommand(
+			SubCommand::with_name("list-unused-attachments")
+				.alias("unused")
+				.about("Prints a list of attachments that no note links to")
+				.arg(
+					Arg::with_name("quarantine")
+						.short("q")
+						.long("quarantine")
+						.help("Move unused attachments to this directory [default: .unused]")
+						.takes_value(true)
+						.min_values(0)
+						.value_name("dir"),
+				)
+				.arg(dry_run_arg()),
+		)
+		.subcommand(
+			SubCommand::with_name("check")
+				.about("Checks notes for problems, and fails if any are found")
+				.arg(
+					Arg::with_name("rules")
+						.short("r")
+						.long("rules")
+						.help("Comma-separated rules to check [default: all rules]")
+						.takes_value(true)
+						.multiple(true)
+						.use_delimiter(true)
+						.value_name("rules"),
+				)
+				.arg(
+					Arg::with_name("aliased")
+						.short("a")
+						.long("aliased")
+						.help("Expect backlinks written with update-backlinks --aliased"),
+				)
+				.arg(no_aliased_arg()),
+		)
 		.subcommand(
 			SubCommand::with_name("update-backlinks")
 				.alias("backlinks")
-				.about("Updates backlink sections in all notes"),
+				.about("Updates backlink sections in all notes")
+				.arg(
+					Arg::with_name("aliased")
+						.short("a")
+						.long("aliased")
+						.help("Write links as [[target|title]] instead of [[target]] title"),
+				)
+				.arg(no_aliased_arg())
+				.arg(dry_run_arg()),
+		)
+		.subcommand(
+			SubCommand::with_name("watch")
+				.about("Watches for changes to notes, and updates backlink sections")
+				.arg(
+					Arg::with_name("aliased")
+						.short("a")
+						.long("aliased")
+						.help("Write links as [[target|title]] instead of [[target]] title"),
+				)
+				.arg(no_aliased_arg()),
+		)
+		.subcommand(
+			SubCommand::with_name("lsp")
+				.about("Serves the Language Server Protocol over stdio, for editors"),
 		)
 		.subcommand(
 			SubCommand::with_name("remove-backlinks")
-				.about("Removes backlink sections in all notes"),
+				.about("Removes backlink sections in all notes")
+				.arg(dry_run_arg()),
 		)
 		.subcommand(
 			SubCommand::with_name("update-filenames")
@@ -84,30 +228,361 @@ fn main() {
 					Arg::with_name("force")
 						.short("f")
 						.help("Always update names, never prompt"),
+				)
+				.arg(
+					Arg::with_name("no_force")
+						.long("no-force")
+						.help("Prompt for each name, even if force is set in the config file")
+						.conflicts_with("force"),
+				)
+				.arg(dry_run_arg()),
+		)
+		.subcommand(
+			SubCommand::with_name("export-graph")
+				.alias("graph")
+				.about("Writes the links between notes as a graph")
+				.arg(
+					Arg::with_name("to")
+						.short("t")
+						.long("to")
+						.help("File format of the graph")
+						.takes_value(true)
+						.value_name("format")
+						.possible_values(&["dot", "graphml", "gexf", "mermaid"])
+						.default_value("dot"),
+				)
+				.arg(
+					Arg::with_name("around")
+						.long("around")
+						.help("Only write the notes near this note (ID, filename or title)")
+						.takes_value(true)
+						.value_name("note"),
+				)
+				.arg(depth_arg())
+				.arg(direction_arg()),
+		)
+		.subcommand(
+			SubCommand::with_name("neighbours")
+				.alias("neighbors")
+				.about("Prints the notes within a number of links from a note")
+				.arg(
+					Arg::with_name("NOTE")
+						.help("Note to start from (ID, filename or title)")
+						.required(true)
+						.index(1),
+				)
+				.arg(depth_arg())
+				.arg(direction_arg()),
+		)
+		.subcommand(
+			SubCommand::with_name("path")
+				.about("Prints the shortest chain of links from one note to another")
+				.arg(
+					Arg::with_name("FROM")
+						.help("Note to start from (ID, filename or title)")
+						.required(true)
+						.index(1),
+				)
+				.arg(
+					Arg::with_name("TO")
+						.help("Note to end at (ID, filename or title)")
+						.required(true)
+						.index(2),
+				)
+				.arg(
+					Arg::with_name("backwards")
+						.long("backwards")
+						.help("Also follow links backwards, from the linked note to the linking note"),
+				)
+				.arg(
+					Arg::with_name("paths")
+						.short("k")
+						.long("paths")
+						.help("Number of shortest paths to print")
+						.takes_value(true)
+						.value_name("k")
+						.default_value("1")
+						.validator(is_positive_number),
 				),
 		)
+		.subcommand(
+			SubCommand::with_name("rank")
+				.about("Prints the most central notes in the link graph")
+				.arg(
+					Arg::with_name("by")
+						.long("by")
+						.help("Metric to sort notes by")
+						.takes_value(true)
+						.value_name("metric")
+						.possible_values(&["in-degree", "out-degree", "pagerank", "betweenness"])
+						.default_value("pagerank"),
+				)
+				.arg(
+					Arg::with_name("top")
+						.long("top")
+						.help("Number of notes to print, or 0 for all")
+						.takes_value(true)
+						.value_name("n")
+						.default_value("10")
+						.validator(is_number),
+				),
+		)
+		.subcommand(
+			SubCommand::with_name("undo")
+				.about("Restores the files that the last run changed, renamed or moved"),
+		)
 		.get_matches();
 
 	let command = matches.subcommand_name().unwrap_or_default();
-	let mut force = false;
-	if let Some(c) = matches.subcommand_matches("update-filenames") {
-	 	force = c.is_present("force");
+	let path = matches.value_of("PATH").unwrap();
+	let (options, config_dir) = match ConfigFile::find(Path::new(path)) {
+		Ok(Some(config_file)) => {
+			debug_eprintln!("Using config file {}", config_file.path.display());
+			(config_file.options(command), Some(config_file.dir().to_path_buf()))
+		}
+		Ok(None) => (Default::default(), None),
+		Err(e) => {
+			eprintln!("Application error: {}", e);
+			process::exit(1);
+		}
+	};
+
+	let no_subcommand = ArgMatches::default();
+	let subcommand_matches = matches.subcommand().1.unwrap_or(&no_subcommand);
+	let force = flag_of(subcommand_matches, "force", options.force);
+	let aliased = flag_of(subcommand_matches, "aliased", options.aliased);
+	let mut rules = options.rules.unwrap_or_default();
+	if let Some(c) = matches.subcommand_matches("check") {
+		if let Some(values) = c.values_of("rules") {
+			rules = values.map(|rule| rule.to_string()).collect();
+		}
+	}
+	let mut quarantine = None;
+	if let Some(c) = matches.subcommand_matches("list-unused-attachments") {
+		if c.is_present("quarantine") {
+			quarantine = Some(
+				c.value_of("quarantine")
+					.map(|dir| dir.to_string())
+					.or(options.quarantine)
+					.unwrap_or_else(|| ".unused".to_string()),
+			);
+		}
+	}
+
+	// Only lists, statistics and a few other subcommands can be written as
+	// JSON or CSV. A format set in the config file is ignored by the others.
+	let writes_records = (command.is_empty() || command.starts_with("list-") && quarantine.is_none())
+		|| ["check", "rank", "path", "neighbours"].contains(&command);
+	if matches.occurrences_of("format") > 0 && matches.value_of("format") != Some("markdown") && !writes_records {
+		eprintln!(
+			"Application error: --format {} can't be used with {}{}",
+			matches.value_of("format").unwrap(),
+			command,
+			if quarantine.is_some() { " --quarantine" } else { "" }
+		);
+		process::exit(1);
+	}
+
+	let format = match value_of(&matches, "format", options.format).parse() {
+		Ok(format) => format,
+		Err(e) => {
+			eprintln!("Application error: {}", e);
+			process::exit(1);
+		}
+	};
+
+	let values_of = |name: &str| -> Vec<String> {
+		matches
+			.values_of(name)
+			.map(|values| values.map(|v| v.to_string()).collect())
+			.unwrap_or_default()
+	};
+
+	let mut profiles = options.profiles;
+	for value in values_of("profile") {
+		match value.split_once('=') {
+			Some((extension, profile)) => {
+				profiles.insert(extension.trim().to_string(), profile.trim().to_string());
+			}
+			None => {
+				eprintln!("Application error: Expected a profile like 'txt=text', got '{}'", value);
+				process::exit(1);
+			}
+		}
+	}
+
+	let normalize_encoding = match matches
+		.value_of("normalize_encoding")
+		.map(|e| e.to_string())
+		.or(options.normalize_encoding)
+	{
+		None => false,
+		Some(encoding) if encoding == "utf8" => true,
+		Some(encoding) => {
+			eprintln!("Application error: Unknown encoding '{}', expected utf8", encoding);
+			process::exit(1);
+		}
+	};
+
+	let mut graph_format = GraphFormat::Dot;
+	let mut note = None;
+	let mut depth = 1;
+	let mut direction = Direction::Both;
+	if let Some(c) = matches.subcommand_matches("export-graph") {
+		graph_format = c.value_of("to").unwrap().parse().unwrap();
+		note = c.value_of("around").map(|n| n.to_string());
+	}
+	if let Some(c) = matches.subcommand_matches("neighbours") {
+		note = c.value_of("NOTE").map(|n| n.to_string());
+	}
+	if let Some(c) = matches
+		.subcommand_matches("export-graph")
+		.or_else(|| matches.subcommand_matches("neighbours"))
+	{
+		depth = c.value_of("depth").unwrap().parse().unwrap();
+		direction = c.value_of("direction").unwrap().parse().unwrap();
+	}
+	let strong = matches
+		.subcommand_matches("list-components")
+		.is_some_and(|c| c.is_present("strong"));
+	let mut target = None;
+	let mut backwards = false;
+	let mut paths = 1;
+	if let Some(c) = matches.subcommand_matches("path") {
+		note = c.value_of("FROM").map(|n| n.to_string());
+		target = c.value_of("TO").map(|n| n.to_string());
+		backwards = c.is_present("backwards");
+		paths = c.value_of("paths").unwrap().parse().unwrap();
 	}
+	let mut metric = Metric::PageRank;
+	let mut top = 10;
+	if let Some(c) = matches.subcommand_matches("rank") {
+		metric = c.value_of("by").unwrap().parse().unwrap();
+		top = c.value_of("top").unwrap().parse().unwrap();
+	}
+
+	let dry_run = matches
+		.subcommand()
+		.1
+		.map(|c| c.is_present("dry_run"))
+		.unwrap_or(false);
 
 	let config = Config {
-		extension: matches.value_of("extension").unwrap().to_string(),
-		id_pattern: matches.value_of("id_format").unwrap().to_string(),
-		backlinks_heading: matches.value_of("backlinks_heading").unwrap().to_string(),
-		path: matches.value_of("PATH").unwrap().to_string(),
+		extension: value_of(&matches, "extension", options.extension),
+		id_pattern: value_of(&matches, "id_format", options.id_format),
+		backlinks_heading: value_of(&matches, "backlinks_heading", options.backlinks_heading),
+		path: path.to_string(),
+		markdown_links: flag_of(&matches, "markdown_links", options.markdown_links),
 		command: command.to_string(),
-		force
+		force,
+		aliased,
+		quarantine,
+		ignore: values_of("exclude"),
+		include: values_of("include"),
+		config_dir,
+		config_ignore: options.ignore,
+		config_include: options.include,
+		format,
+		rules,
+		dry_run,
+		cache: flag_of(&matches, "cache", options.cache),
+		normalize_encoding,
+		profiles,
+		graph_format,
+		note,
+		depth,
+		direction,
+		metric,
+		top,
+		strong,
+		target,
+		backwards,
+		paths,
 	};
 
 	let start_time = Utc::now();
 	if let Err(e) = run(config) {
+		// Failed checks and files have already been reported
+		if e.downcast_ref::<Report>().is_some() {
+			process::exit(2);
+		}
+		if e.downcast_ref::<CheckFailed>().is_some() {
+			process::exit(3);
+		}
 		eprintln!("Application error: {}", e);
 		process::exit(1);
 	}
 	let duration = Utc::now() - start_time;
-	debug_println!("run() took {} ms", duration.num_milliseconds());
+	debug_eprintln!("run() took {} ms", duration.num_milliseconds());
+}
+
+fn dry_run_arg<'a, 'b>() -> Arg<'a, 'b> {
+	Arg::with_name("dry_run")
+		.short("n")
+		.long("dry-run")
+		.help("Print the changes instead of writing them to disk")
+}
+
+fn no_aliased_arg<'a, 'b>() -> Arg<'a, 'b> {
+	Arg::with_name("no_aliased")
+		.long("no-aliased")
+		.help("Don't use labelled links, even if aliased is set in the config file")
+		.conflicts_with("aliased")
+}
+
+fn depth_arg<'a, 'b>() -> Arg<'a, 'b> {
+	Arg::with_name("depth")
+		.short("d")
+		.long("depth")
+		.help("Number of links to follow from the note")
+		.takes_value(true)
+		.value_name("n")
+		.default_value("1")
+		.validator(is_number)
+}
+
+fn direction_arg<'a, 'b>() -> Arg<'a, 'b> {
+	Arg::with_name("direction")
+		.long("direction")
+		.help("Follow links to the note (in), from the note (out) or both")
+		.takes_value(true)
+		.value_name("direction")
+		.possible_values(&["in", "out", "both"])
+		.default_value("both")
+}
+
+fn is_number(value: String) -> Result<(), String> {
+	match value.parse::<usize>() {
+		Ok(_) => Ok(()),
+		Err(_) => Err(format!("'{}' isn't a number", value)),
+	}
+}
+
+fn is_positive_number(value: String) -> Result<(), String> {
+	match value.parse::<usize>() {
+		Ok(0) => Err("must be at least 1".to_owned()),
+		Ok(_) => Ok(()),
+		Err(_) => Err(format!("'{}' isn't a number", value)),
+	}
+}
+
+/// Get an argument's value, where a value from the config file
+/// overrides the default value, but not an explicit argument
+fn value_of(matches: &ArgMatches, name: &str, config_value: Option<String>) -> String {
+	match config_value {
+		Some(value) if matches.occurrences_of(name) == 0 => value,
+		_ => matches.value_of(name).unwrap().to_string(),
+	}
+}
+
+/// Get a flag's value, where a value from the config file overrides the
+/// default, and the flag or its "no_" counterpart overrides the config file
+fn flag_of(matches: &ArgMatches, name: &str, config_value: Option<bool>) -> bool {
+	if matches.is_present(name) {
+		true
+	} else if matches.is_present(format!("no_{}", name)) {
+		false
+	} else {
+		config_value.unwrap_or(false)
+	}
 }
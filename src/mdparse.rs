@@ -1,21 +1,36 @@
-use crate::note::WikiLink;
+use crate::error::Error;
+use crate::note::{Anchor, Link, Task, WikiLink};
 use lazy_static::*;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::str;
 
 lazy_static! {
 	static ref YAML_TITLE_EXPR: Regex =
 		Regex::new(r#"\A\s*['"]?title['"]?\s*: \s*['"]?([^'"]+)['"]?\z"#).unwrap();
+	/// "backlinks: false" in the front matter
+	static ref YAML_NO_BACKLINKS_EXPR: Regex =
+		Regex::new(r#"(?i)\A\s*['"]?backlinks['"]?\s*:\s*['"]?(false|no|off)['"]?\s*\z"#).unwrap();
 
 	static ref LINK_CHARS: &'static str = "[^<>:*?|/\\]\\[\"\\\\\\t]";
 	static ref WIKILINK_SIMPLE_EXPR: Regex = Regex::new(
-		&"\\[\\[(.+?)\\]\\]"
+		&"(!?)\\[\\[(.+?)\\]\\]"
 		.replace("{:link_chars:}", *LINK_CHARS)
 	)
 	.unwrap();
+	/// Markdown links ("[text](href)") and images ("![alt](src)")
+	static ref MARKDOWN_LINK_EXPR: Regex = Regex::new(
+		r#"(!?)\[([^\[\]]*)\]\(\s*(<[^<>]*>|[^()\s]+)(?:\s+(?:"[^"]*"|'[^']*'))?\s*\)"#
+	)
+	.unwrap();
 	static ref TASK_EXPR: Regex = Regex::new(r"\A\s*[-+*]\s+\[ \]\s+(.+?)\z").unwrap();
 	static ref BACKLINK_EXPR: Regex = Regex::new(r"\A[-+*]\s*(.*?)\z").unwrap();
 	static ref INDENTED_LIST_EXPR: Regex = Regex::new(r"\A\s+([-+*]|\d+\.)\s.+\z").unwrap();
+	static ref HEADING_EXPR: Regex = Regex::new(r"\A#{2,6}\s+(.+?)\z").unwrap();
+	/// Block IDs ("^block-id") at the end of a line
+	static ref BLOCK_ID_EXPR: Regex = Regex::new(r"(?:\A|\s)\^([A-Za-z0-9-]+)\s*\z").unwrap();
 
 	/// Characters that can be escaped in Markdown
 	static ref ESCAPED_CHARS_EXPR: Regex = Regex::new(r"\\([\\`\*_{}\[\]<>()#+-\.!|])").unwrap();
@@ -34,51 +49,129 @@ enum ParseState<'a> {
 	BackLinks,
 }
 
-#[derive(Debug)]
+/// What the parser found in a note. Positions are byte offsets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NoteData {
 	pub titles: Vec<String>,
+	pub headings: Vec<String>,
+	pub block_ids: Vec<String>,
 	pub ids: Vec<String>,
-	pub links: Vec<WikiLink>,
-	pub tasks: Vec<String>,
+	pub links: Vec<Link>,
+	pub tasks: Vec<Task>,
 	pub backlinks_start: Option<usize>,
 	pub backlinks_end: Option<usize>,
+	/// The note may be linked to, but never gets a backlinks section
+	pub no_backlinks: bool,
 }
 
+/// How the notes with a file extension are parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParserProfile {
+	/// Markdown, with front matter, code blocks and Markdown links
+	Markdown,
+	/// Plain text, where only wikilinks, IDs, "# " titles, tasks and
+	/// backlinks sections are found. Indented and fenced lines are text too.
+	Text,
+}
+
+impl str::FromStr for ParserProfile {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"markdown" | "md" => Ok(ParserProfile::Markdown),
+			"text" | "txt" => Ok(ParserProfile::Text),
+			_ => Err(format!("Unknown parser profile \"{}\"", s)),
+		}
+	}
+}
+
+/// Finds titles, IDs, links, tasks and backlinks sections in notes
 #[derive(Debug)]
 pub struct NoteParser {
-	id_pattern: String,
 	id_expr: Regex,
 	pub backlinks_heading: String,
+	pub markdown_links: bool,
+	/// Profiles by lowercase file extension, for notes that aren't Markdown
+	profiles: HashMap<String, ParserProfile>,
 }
 
 impl NoteParser {
-	pub fn new(id_pattern: &str, backlinks_heading: &str) -> Result<NoteParser, &'static str> {
+	/// `id_pattern` is a regular expression for note IDs, like `\d{14}`, and
+	/// `backlinks_heading` is the heading line of backlinks sections, like
+	/// `## Backlinks`
+	pub fn new(id_pattern: &str, backlinks_heading: &str) -> Result<NoteParser, Error> {
 		let id_expr_str = format!(r"(?:\A|\s)({})(?:\z|\b)", &id_pattern);
-		let id_expr = match Regex::new(&id_expr_str) {
-			Ok(expr) => expr,
-			Err(_) => return Err("Cannot parse ID format as regular expression"),
-		};
+		let id_expr = Regex::new(&id_expr_str)?;
 
 		// Replace whitespace character representations
 		let backlinks_heading = backlinks_heading.to_string();
 
 		Ok(NoteParser {
-			id_pattern: id_pattern.to_string(),
 			id_expr,
 			backlinks_heading,
+			markdown_links: false,
+			profiles: HashMap::new(),
 		})
 	}
 
+	/// The settings that change the result of `parse`
+	pub fn settings_key(&self) -> String {
+		let mut profiles: Vec<String> = self
+			.profiles
+			.iter()
+			.map(|(extension, profile)| format!("{}={:?}", extension, profile))
+			.collect();
+		profiles.sort();
+		format!("{}\n{}\n{}", self.id_expr.as_str(), self.backlinks_heading, profiles.join(","))
+	}
+
+	/// Parse the notes with the file extension in another way than Markdown
+	pub fn with_profile(mut self, extension: &str, profile: ParserProfile) -> NoteParser {
+		self.profiles.insert(extension.to_lowercase(), profile);
+		self
+	}
+
+	/// Get the profile for notes with the file extension
+	pub fn get_profile(&self, extension: &str) -> ParserProfile {
+		let profile = self.profiles.get(&extension.to_lowercase());
+		profile.copied().unwrap_or(ParserProfile::Markdown)
+	}
+
+	/// Follow relative Markdown links to other notes ("[text](path/to/note.md)").
+	/// Markdown links and images are always parsed, since they can point to
+	/// attachments.
+	pub fn with_markdown_links(mut self, enabled: bool) -> NoteParser {
+		self.markdown_links = enabled;
+		self
+	}
+
+	/// Parse the contents of a Markdown note
 	pub fn parse(&self, text: &str) -> NoteData {
+		self.parse_with_profile(text, ParserProfile::Markdown)
+	}
+
+	/// Parse a note with the profile for its file extension
+	pub fn parse_file(&self, text: &str, extension: &str) -> NoteData {
+		self.parse_with_profile(text, self.get_profile(extension))
+	}
+
+	fn parse_with_profile(&self, text: &str, profile: ParserProfile) -> NoteData {
+		let markdown = profile == ParserProfile::Markdown;
 		let mut titles = Vec::new();
+		let mut headings = Vec::new();
+		let mut block_ids = Vec::new();
 		let mut ids = Vec::new();
 		let mut links = Vec::new();
 		let mut tasks = Vec::new();
 		let mut backlinks_start: Option<usize> = None;
 		let mut backlinks_end: Option<usize> = None;
+		let mut no_backlinks = false;
 
 		let mut state = ParseState::Initial;
-		let mut start_end = find_first_line(&text, starts_with_bom(&text));
+		let mut start_end = find_first_line(text, starts_with_bom(text));
+		let mut line_number = 1;
+		let mut counted_to = 0;
 
 		loop {
 			if start_end.is_none() {
@@ -90,9 +183,14 @@ impl NoteParser {
 			let ln = &text[start..end];
 			let ln_bytes = ln.as_bytes();
 
+			// Empty lines are skipped, so count the line breaks since last line
+			line_number += count_line_breaks(&text[counted_to..start]);
+			counted_to = start;
+			let first_link = links.len();
+
 			match state {
 				ParseState::Initial => {
-					if ln.starts_with("---") {
+					if markdown && ln.starts_with("---") {
 						state = ParseState::Yaml;
 					} else {
 						// Parse the line again in another state
@@ -111,6 +209,9 @@ impl NoteParser {
 								titles.push(capture[1].to_owned());
 							}
 						}
+						if ln.contains("acklinks") && YAML_NO_BACKLINKS_EXPR.is_match(ln) {
+							no_backlinks = true;
+						}
 						if let Some(capture) = self.id_expr.captures(ln) {
 							ids.push(capture[1].to_owned());
 						}
@@ -124,14 +225,9 @@ impl NoteParser {
 				ParseState::Regular => {
 					// Heading 1
 					if ln_bytes.len() > 2 && ln_bytes[0] == b'#' && ln_bytes[1] == b' ' {
-						titles.push(
-							// Remove {.attributes} and trailing spaces
-							// See https://pandoc.org/MANUAL.html#pandocs-markdown
-							escape_markdown(
-								NoteParser::strip_heading_attributes(&ln[2..]).trim_end(),
-							)
-							.to_string(),
-						);
+						let title = clean_heading(&ln[2..]);
+						headings.push(title.clone());
+						titles.push(title);
 						if let Some(capture) = self.id_expr.captures(ln) {
 							ids.push(capture[1].to_owned());
 						}
@@ -140,18 +236,29 @@ impl NoteParser {
 								links.extend(wl);
 							}
 						}
-					} else if (ln_bytes[0] == b'\t' || ln.starts_with("    "))
+					} else if markdown
+						&& (ln_bytes[0] == b'\t' || ln.starts_with("    "))
 						&& !INDENTED_LIST_EXPR.is_match(ln)
 					{
 						// Ignore code blocks (not indented list items) and line-breaks
-					} else if ln.starts_with(*CODEBLOCK_TOKEN_1)
-						|| ln.starts_with(*CODEBLOCK_TOKEN_2)
+					} else if markdown
+						&& (ln.starts_with(*CODEBLOCK_TOKEN_1) || ln.starts_with(*CODEBLOCK_TOKEN_2))
 					{
 						state = ParseState::CodeBlock(&ln[..3]);
 					} else if ln == self.backlinks_heading {
 						backlinks_start = Some(start);
 						state = ParseState::BackLinks;
 					} else {
+						if ln_bytes[0] == b'#' {
+							if let Some(capture) = HEADING_EXPR.captures(ln) {
+								headings.push(clean_heading(&capture[1]));
+							}
+						}
+						if ln.contains('^') {
+							if let Some(capture) = BLOCK_ID_EXPR.captures(ln) {
+								block_ids.push(capture[1].to_owned());
+							}
+						}
 						if let Some(capture) = self.id_expr.captures(ln) {
 							ids.push(capture[1].to_owned());
 						}
@@ -159,8 +266,16 @@ impl NoteParser {
 							if let Some(wl) = self.get_wiki_links(ln) {
 								links.extend(wl);
 							}
+							if markdown && ln.contains("](") {
+								if let Some(ml) = self.get_markdown_links(ln) {
+									links.extend(ml);
+								}
+							}
 							if let Some(capture) = TASK_EXPR.captures(ln) {
-								tasks.push(capture[1].to_string());
+								tasks.push(Task {
+									text: capture[1].to_string(),
+									line: line_number,
+								});
 							}
 						}
 					}
@@ -183,17 +298,24 @@ impl NoteParser {
 				}
 			}
 
+			for link in &mut links[first_link..] {
+				link.line = line_number;
+			}
+
 			// Parse the next line
-			start_end = find_next_line(&text, end);
+			start_end = find_next_line(text, end);
 		}
 
 		NoteData {
 			titles,
+			headings,
+			block_ids,
 			ids,
 			links,
 			tasks,
 			backlinks_start,
 			backlinks_end,
+			no_backlinks,
 		}
 	}
 
@@ -208,10 +330,7 @@ impl NoteParser {
 	}
 
 	pub fn get_id(&self, text: &str) -> Option<String> {
-		match self.id_expr.captures(&text) {
-			None => None,
-			Some(capture) => Some(capture[1].to_string()),
-		}
+		self.id_expr.captures(text).map(|capture| capture[1].to_string())
 	}
 
 	#[inline]
@@ -223,27 +342,204 @@ impl NoteParser {
 		self.id_expr.replace(text, "").trim().to_owned()
 	}
 
-	pub fn get_wiki_links(&self, text: &str) -> Option<Vec<WikiLink>> {
-		let mut captures = WIKILINK_SIMPLE_EXPR.captures_iter(&text).peekable();
-		if captures.peek().is_none() {
-			return None;
+	pub fn get_wiki_links(&self, text: &str) -> Option<Vec<Link>> {
+		let mut captures = WIKILINK_SIMPLE_EXPR.captures_iter(text).peekable();
+		captures.peek()?;
+		Some(captures.map(|capture| self.get_wiki_link(&capture)).collect())
+	}
+
+	fn get_wiki_link(&self, capture: &regex::Captures) -> Link {
+		let (target, label) = split_link_label(&capture[2]);
+		let (target, anchor) = split_link_anchor(target);
+		let target = target.to_string();
+		let target = if self.is_id(&target) {
+			WikiLink::Id(target)
+		} else {
+			WikiLink::FileName(target)
+		};
+		Link {
+			target,
+			anchor,
+			label: label.map(|l| l.to_string()),
+			embed: !capture[1].is_empty(),
+			line: 0,
 		}
+	}
+
+	/// Get Markdown links and images with relative paths. URLs and absolute
+	/// paths are ignored.
+	pub fn get_markdown_links(&self, text: &str) -> Option<Vec<Link>> {
 		let mut links = Vec::new();
-		for capture in captures {
-			let link = capture[1].to_string();
-			if self.is_id(&link) {
-				links.push(WikiLink::Id(link));
-			} else {
-				links.push(WikiLink::FileName(link));
+		for capture in MARKDOWN_LINK_EXPR.captures_iter(text) {
+			if let Some(link) = get_markdown_link(&capture) {
+				links.push(link);
 			}
 		}
-		Some(links)
+		if links.is_empty() {
+			None
+		} else {
+			Some(links)
+		}
+	}
+
+	/// Get the link at byte offset `column` in a line of text, if any
+	pub fn get_link_at(&self, line: &str, column: usize) -> Option<Link> {
+		let contains = |capture: &regex::Captures| {
+			let m = capture.get(0).unwrap();
+			m.start() <= column && column <= m.end()
+		};
+		if let Some(capture) = WIKILINK_SIMPLE_EXPR.captures_iter(line).find(contains) {
+			return Some(self.get_wiki_link(&capture));
+		}
+		if self.markdown_links {
+			if let Some(capture) = MARKDOWN_LINK_EXPR.captures_iter(line).find(contains) {
+				return get_markdown_link(&capture);
+			}
+		}
+		None
+	}
+}
+
+fn get_markdown_link(capture: &regex::Captures) -> Option<Link> {
+	Some(Link {
+		target: WikiLink::Path(get_relative_href(&capture[3])?),
+		anchor: None,
+		label: Some(capture[2].to_string()).filter(|l| !l.is_empty()),
+		embed: !capture[1].is_empty(),
+		line: 0,
+	})
+}
+
+/// Get the decoded path of a Markdown link target, without fragment and
+/// query, or None if it's not a relative path
+pub fn get_relative_href(href: &str) -> Option<String> {
+	let href = href
+		.strip_prefix('<')
+		.and_then(|h| h.strip_suffix('>'))
+		.unwrap_or(href);
+	let end = href.find(['#', '?']).unwrap_or(href.len());
+	let href = &href[..end];
+	if href.is_empty() || href.starts_with('/') || href.contains(':') {
+		// Fragment only, absolute path or URL
+		None
+	} else {
+		Some(percent_decode(href))
+	}
+}
+
+/// Replace the file name in a Markdown link target, keeping the directory,
+/// fragment and escaping style ("dir/Old%20Name.md#x" => "dir/New%20Name.md#x")
+pub fn replace_href_file_name(href: &str, new_file_name: &str) -> String {
+	let (open, href, close) = match href.strip_prefix('<').and_then(|h| h.strip_suffix('>')) {
+		Some(h) => ("<", h, ">"),
+		None => ("", href, ""),
+	};
+	let end = href.find(['#', '?']).unwrap_or(href.len());
+	let start = href[..end].rfind('/').map_or(0, |pos| pos + 1);
+	let new_file_name = if open.is_empty() {
+		new_file_name
+			.replace('%', "%25")
+			.replace(' ', "%20")
+			.replace('(', "%28")
+			.replace(')', "%29")
+	} else {
+		new_file_name.to_string()
+	};
+	format!(
+		"{}{}{}{}{}",
+		open,
+		&href[..start],
+		new_file_name,
+		&href[end..],
+		close
+	)
+}
+
+/// Replace the targets of all Markdown links and images in `text`, with the
+/// result from `replace`, if any. `replace` is called with the raw link target.
+pub fn replace_markdown_links<F>(text: &str, replace: F) -> Cow<'_, str>
+where
+	F: Fn(&str) -> Option<String>,
+{
+	MARKDOWN_LINK_EXPR.replace_all(text, |capture: &regex::Captures| {
+		let whole = capture.get(0).unwrap();
+		let href = capture.get(3).unwrap();
+		match replace(href.as_str()) {
+			Some(new_href) => format!(
+				"{}{}{}",
+				&whole.as_str()[..href.start() - whole.start()],
+				new_href,
+				&whole.as_str()[href.end() - whole.start()..]
+			),
+			None => whole.as_str().to_string(),
+		}
+	})
+}
+
+/// Decode %XX escapes, as used in Markdown link targets
+fn percent_decode(text: &str) -> String {
+	if !text.contains('%') {
+		return text.to_string();
+	}
+	let bytes = text.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		if bytes[i] == b'%'
+			&& i + 2 < bytes.len()
+			&& bytes[i + 1].is_ascii_hexdigit()
+			&& bytes[i + 2].is_ascii_hexdigit()
+		{
+			decoded.push(u8::from_str_radix(&text[i + 1..i + 3], 16).unwrap());
+			i += 3;
+		} else {
+			decoded.push(bytes[i]);
+			i += 1;
+		}
+	}
+	String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Split "target|label" into target and optional label. A backslash before
+/// the pipe (used to escape it inside Markdown tables) is removed.
+fn split_link_label(link: &str) -> (&str, Option<&str>) {
+	match link.find('|') {
+		Some(pos) => {
+			let target = link[..pos].strip_suffix('\\').unwrap_or(&link[..pos]);
+			let label = &link[pos + 1..];
+			(target, if label.is_empty() { None } else { Some(label) })
+		}
+		None => (link, None),
+	}
+}
+
+/// Split "target#heading" or "target#^block-id" into target and optional anchor
+fn split_link_anchor(link: &str) -> (&str, Option<Anchor>) {
+	match link.find('#') {
+		Some(pos) => {
+			let anchor = &link[pos + 1..];
+			let anchor = if anchor.is_empty() {
+				None
+			} else if let Some(block_id) = anchor.strip_prefix('^') {
+				Some(Anchor::Block(block_id.to_string()))
+			} else {
+				Some(Anchor::Heading(anchor.to_string()))
+			};
+			(&link[..pos], anchor)
+		}
+		None => (link, None),
 	}
 }
 
+/// Remove {.attributes} and trailing spaces from heading text, and unescape it
+/// See https://pandoc.org/MANUAL.html#pandocs-markdown
+fn clean_heading(text: &str) -> String {
+	escape_markdown(NoteParser::strip_heading_attributes(text).trim_end()).to_string()
+}
+
 /// Returns the size of the BOM if it exists
 fn starts_with_bom(text: &str) -> usize {
-	if text.len() >= 3 && text.chars().next().unwrap() == '\u{feff}' {
+	if text.len() >= 3 && text.starts_with('\u{feff}') {
 		3
 	} else {
 		0
@@ -251,14 +547,37 @@ fn starts_with_bom(text: &str) -> usize {
 }
 
 fn find_newline(text: &str, offset: usize) -> Option<usize> {
-	let mut pos = offset;
-	for char in text[offset..].bytes() {
-		if char == b'\n' || char == b'\r' {
-			return Some(pos);
-		}
-		pos += 1;
+	text[offset..]
+		.bytes()
+		.position(|char| char == b'\n' || char == b'\r')
+		.map(|pos| offset + pos)
+}
+
+/// Get the line number, starting at 1, of the byte position `offset`
+pub fn line_number(text: &str, offset: usize) -> usize {
+	1 + count_line_breaks(&text[..offset])
+}
+
+/// Count "\n", "\r\n" and "\r" line breaks
+fn count_line_breaks(text: &str) -> usize {
+	let bytes = text.as_bytes();
+	bytes
+		.iter()
+		.enumerate()
+		.filter(|(i, &char)| char == b'\n' || (char == b'\r' && bytes.get(i + 1) != Some(&b'\n')))
+		.count()
+}
+
+/// Get the line at `index`, starting at 0, with "\n", "\r\n" and "\r" line
+/// breaks counted like `line_number` does
+pub fn nth_line(text: &str, index: usize) -> Option<&str> {
+	let mut rest = text;
+	for _ in 0..index {
+		let pos = find_newline(rest, 0)?;
+		let len = if rest[pos..].starts_with("\r\n") { 2 } else { 1 };
+		rest = &rest[pos + len..];
 	}
-	None
+	Some(&rest[..find_newline(rest, 0).unwrap_or(rest.len())])
 }
 
 /// Find byte position (start, end) of first line, or None
@@ -268,7 +587,7 @@ fn find_first_line(text: &str, offset: usize) -> Option<(usize, usize)> {
 		if char == '\n' || char == '\r' {
 			pos += 1;
 		} else {
-			match find_newline(&text, pos) {
+			match find_newline(text, pos) {
 				None => return Some((pos, text.len())),
 				Some(pos_next_newline) => {
 					return Some((pos, pos_next_newline));
@@ -281,20 +600,20 @@ fn find_first_line(text: &str, offset: usize) -> Option<(usize, usize)> {
 
 /// Find byte position (start, end) of next line, or None
 fn find_next_line(text: &str, offset: usize) -> Option<(usize, usize)> {
-	match find_newline(&text, offset) {
+	match find_newline(text, offset) {
 		None => None,
-		Some(pos) => find_first_line(&text, pos),
+		Some(pos) => find_first_line(text, pos),
 	}
 }
 
-fn escape_markdown(text: &str) -> Cow<str> {
+fn escape_markdown(text: &str) -> Cow<'_, str> {
 	ESCAPED_CHARS_EXPR.replace_all(text, "$1")
 }
 
 #[cfg(test)]
 mod tests {
 	use crate::mdparse;
-	use crate::mdparse::{NoteParser, WikiLink};
+	use crate::mdparse::{Anchor, NoteParser, ParserProfile, WikiLink};
 	use std::fs;
 
 	#[test]
@@ -304,8 +623,8 @@ mod tests {
 		assert!(with_bom.starts_with('\u{feff}'));
 		assert_eq!(mdparse::starts_with_bom(&with_bom), 3);
 
-		assert_eq!(mdparse::starts_with_bom(&"Hello, world!"), 0);
-		assert_eq!(mdparse::starts_with_bom(&"."), 0);
+		assert_eq!(mdparse::starts_with_bom("Hello, world!"), 0);
+		assert_eq!(mdparse::starts_with_bom("."), 0);
 	}
 
 	#[test]
@@ -357,11 +676,11 @@ mod tests {
 		assert_eq!(s1, 1);
 		assert_eq!(&text[s1..e1], "Lorem");
 
-		let (s1, e1) = mdparse::find_next_line(&text, s1).unwrap();
+		let (s1, e1) = mdparse::find_next_line(text, s1).unwrap();
 		assert_eq!(s1, 7);
 		assert_eq!(&text[s1..e1], "ipsum");
 
-		let (s1, e1) = mdparse::find_next_line(&text, s1).unwrap();
+		let (s1, e1) = mdparse::find_next_line(text, s1).unwrap();
 		assert_eq!(s1, 13);
 		assert_eq!(&text[s1..e1], "dolor");
 
@@ -443,7 +762,7 @@ mod tests {
 
 		assert_eq!(data.links.len(), expected_links.len());
 		for (expected, actual) in expected_links.iter().zip(data.links.iter()) {
-			assert_eq!(actual, expected);
+			assert_eq!(&actual.target, expected);
 		}
 	}
 
@@ -460,14 +779,22 @@ mod tests {
 			WikiLink::FileName("Search Query Link".to_owned()),
 			WikiLink::FileName("Regular Link To Wiki URI".to_owned()),
 			WikiLink::FileName("Org-Mode Link Text][Org-Mode Link".to_owned()),
-			WikiLink::FileName("using labelled links|labelling wiki links".to_owned()),
-			WikiLink::FileName("the filename first#section then".to_owned()),
+			WikiLink::FileName("using labelled links".to_owned()),
+			WikiLink::FileName("the filename first".to_owned()),
 			WikiLink::FileName("my [not so pretty] link".to_owned()),
 			WikiLink::FileName(" some text and then [[a link".to_owned()),
+			WikiLink::FileName("Table Link".to_owned()),
+			WikiLink::FileName("Embedded note".to_owned()),
+			WikiLink::FileName("image.png".to_owned()),
+			WikiLink::Path("Other Note.md".to_owned()),
+			WikiLink::Path("../sub/Third note.md".to_owned()),
+			WikiLink::Path("Fourth note.md".to_owned()),
+			WikiLink::Path("image.png".to_owned()),
 		];
 
+		let targets: Vec<&WikiLink> = data.links.iter().map(|l| &l.target).collect();
 		for expected_link in &expected_links {
-			assert!(data.links.contains(expected_link));
+			assert!(targets.contains(&expected_link));
 		}
 
 		let unexpected_links = vec![
@@ -476,17 +803,195 @@ mod tests {
 		];
 
 		for unexpected_link in &unexpected_links {
-			assert!(!data.links.contains(unexpected_link));
+			assert!(!targets.contains(&unexpected_link));
 		}
 
+		let section_link = data
+			.links
+			.iter()
+			.find(|l| l.target == WikiLink::FileName("the filename first".to_owned()))
+			.unwrap();
+		assert_eq!(
+			section_link.anchor,
+			Some(Anchor::Heading("section then".to_owned()))
+		);
+
 		assert_eq!(data.links.len(), expected_links.len());
 	}
 
+	#[test]
+	fn parse_link_labels() {
+		let parser = NoteParser::new(r"\d{14}", "## Links to this note").unwrap();
+		let links = parser
+			.get_wiki_links("See [[20210101120000|My note]] and [[Other|]] or [[Table\\|cell]]")
+			.unwrap();
+
+		assert_eq!(links.len(), 3);
+		assert_eq!(links[0].target, WikiLink::Id("20210101120000".to_owned()));
+		assert_eq!(links[0].label, Some("My note".to_owned()));
+		assert_eq!(links[1].target, WikiLink::FileName("Other".to_owned()));
+		assert_eq!(links[1].label, None);
+		assert_eq!(links[2].target, WikiLink::FileName("Table".to_owned()));
+		assert_eq!(links[2].label, Some("cell".to_owned()));
+	}
+
+	#[test]
+	fn parse_link_anchors() {
+		let parser = NoteParser::new(r"\d{14}", "## Links to this note").unwrap();
+		let links = parser
+			.get_wiki_links("[[20210101120000#A heading|label]], [[Note#^block-1]], [[#Own heading]] and [[Note#]]")
+			.unwrap();
+
+		assert_eq!(links.len(), 4);
+		assert_eq!(links[0].target, WikiLink::Id("20210101120000".to_owned()));
+		assert_eq!(links[0].anchor, Some(Anchor::Heading("A heading".to_owned())));
+		assert_eq!(links[0].label, Some("label".to_owned()));
+		assert_eq!(links[1].target, WikiLink::FileName("Note".to_owned()));
+		assert_eq!(links[1].anchor, Some(Anchor::Block("block-1".to_owned())));
+		assert_eq!(links[2].target, WikiLink::FileName("".to_owned()));
+		assert_eq!(links[2].anchor, Some(Anchor::Heading("Own heading".to_owned())));
+		assert_eq!(links[3].target, WikiLink::FileName("Note".to_owned()));
+		assert_eq!(links[3].anchor, None);
+	}
+
+	#[test]
+	fn parse_embeds() {
+		let parser = NoteParser::new(r"\d{14}", "## Links to this note").unwrap();
+		let text = "![[Embedded note]] [[Linked note]] ![Alt](image.png) [Text](doc.pdf)";
+
+		let embeds: Vec<(String, bool)> = parser
+			.get_wiki_links(text)
+			.unwrap()
+			.into_iter()
+			.chain(parser.get_markdown_links(text).unwrap())
+			.map(|l| (l.target.value().to_owned(), l.embed))
+			.collect();
+		assert_eq!(
+			embeds,
+			[
+				("Embedded note".to_owned(), true),
+				("Linked note".to_owned(), false),
+				("image.png".to_owned(), true),
+				("doc.pdf".to_owned(), false),
+			]
+		);
+	}
+
+	#[test]
+	fn parse_markdown_links() {
+		let text = fs::read_to_string(r"testdata/Links.md").unwrap();
+		let parser = NoteParser::new(r"\d{11,14}", "**Links to this note**").unwrap();
+		let without = parser.parse(&text);
+		let parser = parser.with_markdown_links(true);
+		let with = parser.parse(&text);
+
+		let paths: Vec<&WikiLink> = with
+			.links
+			.iter()
+			.map(|l| &l.target)
+			.filter(|t| matches!(t, WikiLink::Path(_)))
+			.collect();
+		assert_eq!(
+			paths,
+			[
+				&WikiLink::Path("Other Note.md".to_owned()),
+				&WikiLink::Path("../sub/Third note.md".to_owned()),
+				&WikiLink::Path("Fourth note.md".to_owned()),
+				&WikiLink::Path("image.png".to_owned()),
+			]
+		);
+		// Markdown links are parsed either way, since they can point to attachments
+		assert_eq!(with.links.len(), without.links.len());
+	}
+
+	#[test]
+	fn relative_hrefs() {
+		assert_eq!(mdparse::get_relative_href("a%20b.md#x"), Some("a b.md".to_owned()));
+		assert_eq!(mdparse::get_relative_href("<a b.md>"), Some("a b.md".to_owned()));
+		assert_eq!(mdparse::get_relative_href("%C3%A5%2"), Some("å%2".to_owned()));
+		assert_eq!(mdparse::get_relative_href("https://example.com/a.md"), None);
+		assert_eq!(mdparse::get_relative_href("/absolute.md"), None);
+		assert_eq!(mdparse::get_relative_href("#fragment"), None);
+
+		assert_eq!(
+			mdparse::replace_href_file_name("../dir/Old%20Name.md#x", "New (name).md"),
+			"../dir/New%20%28name%29.md#x"
+		);
+		assert_eq!(
+			mdparse::replace_href_file_name("<dir/Old Name.md>", "New name.md"),
+			"<dir/New name.md>"
+		);
+		assert_eq!(
+			mdparse::replace_markdown_links("[a](x.md \"t\") ![b](x.md) [c](y.md)", |href| {
+				if href == "x.md" {
+					Some("z.md".to_owned())
+				} else {
+					None
+				}
+			}),
+			"[a](z.md \"t\") ![b](z.md) [c](y.md)"
+		);
+	}
+
+	#[test]
+	fn parse_headings_and_block_ids() {
+		let text = fs::read_to_string(r"testdata/Anchors.md").unwrap();
+		let parser = NoteParser::new(r"\d{14}", "## Links to this note").unwrap();
+		let data = parser.parse(&text);
+
+		assert_eq!(
+			data.headings,
+			["Anchors", "Second level", "Third level #3", "Sixth level"]
+		);
+		assert_eq!(data.block_ids, ["para-1", "item2"]);
+		assert_eq!(data.titles, ["Anchors"]);
+	}
+
+	#[test]
+	fn line_numbers() {
+		let text = fs::read_to_string(r"testdata/Tasks.md").unwrap();
+		let parser = NoteParser::new(r"\d{14}", "## Links to this note").unwrap();
+		let data = parser.parse(&text);
+
+		let tasks: Vec<(&str, usize)> = data.tasks.iter().map(|t| (t.text.as_str(), t.line)).collect();
+		assert_eq!(tasks[0], ("Don't forget to remember", 7));
+		assert_eq!(tasks[4], ("Final line", 16));
+		assert_eq!(data.links[0].line, 10);
+
+		let data = parser.parse("# Title\r\n\r\n[[a]]\r\rText\n\n[[b]] [[c]]\r\n---\n[[d]]");
+		let lines: Vec<usize> = data.links.iter().map(|l| l.line).collect();
+		assert_eq!(lines, [3, 7, 7, 9]);
+	}
+
+	#[test]
+	fn nth_lines() {
+		let text = "a\r\nb\rc\n\nd\r";
+		let lines: Vec<Option<&str>> = (0..7).map(|i| mdparse::nth_line(text, i)).collect();
+		assert_eq!(lines, [Some("a"), Some("b"), Some("c"), Some(""), Some("d"), Some(""), None]);
+	}
+
+	#[test]
+	fn link_at_column() {
+		let parser = NoteParser::new(r"\d{14}", "## Links to this note").unwrap();
+		let line = "See [[20210101120000|first]] and [text](other.md)";
+
+		let link = parser.get_link_at(line, 10).unwrap();
+		assert_eq!(link.target, WikiLink::Id("20210101120000".to_owned()));
+		assert_eq!(link.label.as_deref(), Some("first"));
+		assert_eq!(parser.get_link_at(line, 4).unwrap().target, link.target);
+		assert!(parser.get_link_at(line, 1).is_none());
+		assert!(parser.get_link_at(line, 40).is_none());
+
+		let parser = parser.with_markdown_links(true);
+		let link = parser.get_link_at(line, 40).unwrap();
+		assert_eq!(link.target, WikiLink::Path("other.md".to_owned()));
+	}
+
 	#[test]
 	fn oneliner_parser() {
 		let text = r"# Just a heading \#";
 		let parser = NoteParser::new(r"\d{14}", "## Links to this note").unwrap();
-		let data = parser.parse(&text);
+		let data = parser.parse(text);
 
 		assert!(data.titles.contains(&"Just a heading #".to_owned()));
 		assert_eq!(data.titles.len(), 1);
@@ -497,6 +1002,37 @@ mod tests {
 		assert!(data.backlinks_end.is_none());
 	}
 
+	#[test]
+	fn no_backlinks_marker() {
+		let parser = NoteParser::new(r"\d{14}", "## Links to this note").unwrap();
+		assert!(parser.parse("---\ntitle: Template\nbacklinks: false\n---\n# Heading").no_backlinks);
+		assert!(parser.parse("---\nBacklinks: 'no'\n---").no_backlinks);
+		assert!(!parser.parse("---\nbacklinks: true\n---").no_backlinks);
+		// Only in the front matter
+		assert!(!parser.parse("# Heading\n\nbacklinks: false").no_backlinks);
+	}
+
+	#[test]
+	fn text_profile() {
+		let text = "---\ntitle: Front\n---\n# Plain\n\n    [[indented]]\n\n```\n[[fenced]]\n```\n[md](other.md)";
+		let parser = NoteParser::new(r"\d{14}", "## Links to this note")
+			.unwrap()
+			.with_markdown_links(true)
+			.with_profile("TXT", ParserProfile::Text);
+		assert_eq!(parser.get_profile("txt"), ParserProfile::Text);
+		assert_eq!(parser.get_profile("md"), ParserProfile::Markdown);
+
+		let data = parser.parse_file(text, "Txt");
+		let targets: Vec<String> = data.links.iter().map(|l| l.target.to_string()).collect();
+		assert_eq!(targets, ["[[indented]]", "[[fenced]]"]);
+		assert_eq!(data.titles, ["Plain"]);
+
+		let data = parser.parse_file(text, "md");
+		assert_eq!(data.links.len(), 1);
+		assert_eq!("text".parse::<ParserProfile>(), Ok(ParserProfile::Text));
+		assert!("html".parse::<ParserProfile>().is_err());
+	}
+
 	#[test]
 	fn escaped_characters() {
 		assert_eq!(
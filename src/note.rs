@@ -1,19 +1,25 @@
+use crate::cache::{FileStamp, ParseCache};
+use crate::encoding::{self, FileEncoding};
+use crate::journal::{self, Journal};
+use crate::error::{Error, Report};
 use crate::ftree;
-use crate::mdparse::NoteParser;
-use ansi_term::Colour;
+use crate::graph::{Node, NoteGraph};
+use crate::mdparse;
+use crate::mdparse::{NoteData, NoteParser};
 use chrono::Utc;
-use debug_print::debug_println;
+use debug_print::debug_eprintln;
 use lazy_static::*;
-use regex::Error;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::cell::{Ref, RefCell};
+use rayon::prelude::*;
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::iter::FromIterator;
-use std::rc::Rc;
-use std::{fs, io, path};
+use std::mem;
+use std::sync::OnceLock;
+use std::{io, path};
 
 lazy_static! {
 	static ref EMPTY_STRING: String = String::from("");
@@ -25,10 +31,17 @@ lazy_static! {
 	static ref DOUBLE_SPACES: Regex = Regex::new(r" +").unwrap();
 }
 
-macro_rules! wikilink {
-    ($i:expr) => { format!("[[{}]]", $i); }
-}
-
+/// File extensions of attachments, for links to files that don't exist
+const ATTACHMENT_EXTENSIONS: [&str; 47] = [
+	// Images
+	"png", "jpg", "jpeg", "gif", "bmp", "svg", "webp", "avif", "heic", "tif", "tiff", "ico",
+	// Audio and video
+	"mp3", "wav", "ogg", "m4a", "flac", "aac", "mp4", "m4v", "mov", "mkv", "webm", "avi", "ogv",
+	// Documents
+	"pdf", "doc", "docx", "odt", "rtf", "txt", "csv", "xls", "xlsx", "ods", "ppt", "pptx", "odp", "epub",
+	// Archives and drawings
+	"zip", "7z", "gz", "tar", "rar", "excalidraw", "drawio", "canvas",
+];
 
 #[derive(Debug)]
 pub struct NoteFile {
@@ -38,12 +51,15 @@ pub struct NoteFile {
 	pub stem: String,
 	/// Filename extension without leading dot
 	pub extension: String,
-	/// File contents
-	pub content: String,
+	/// File contents and their encoding on disk, read when first needed if
+	/// the note was cached
+	content: OnceLock<(String, FileEncoding)>,
+	/// Modification time and size of a cached note, that wasn't read
+	stamp: Option<FileStamp>,
 }
 
 impl NoteFile {
-	fn new(path: &path::PathBuf) -> Result<NoteFile, io::Error> {
+	fn new(path: &path::Path) -> Result<NoteFile, io::Error> {
 		Ok(NoteFile {
 			path: path.as_os_str().to_str().unwrap().to_string(),
 			stem: path
@@ -58,51 +74,150 @@ impl NoteFile {
 				.to_str()
 				.unwrap()
 				.to_string(),
-			content: fs::read_to_string(&path)?,
+			content: OnceLock::from(encoding::read_text(path)?),
+			stamp: None,
 		})
 	}
 
+	/// Note file that is read later, if the contents are needed. The file
+	/// must still have the stamp then.
+	fn unread(path: &path::Path, stamp: FileStamp) -> NoteFile {
+		NoteFile {
+			content: OnceLock::new(),
+			stamp: Some(stamp),
+			..NoteFile::with_contents(path, String::new())
+		}
+	}
+
+	/// Note file with contents that are not read from disk, like unsaved
+	/// changes in an editor
+	fn with_contents(path: &path::Path, contents: String) -> NoteFile {
+		NoteFile {
+			path: path.to_string_lossy().to_string(),
+			stem: path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+			extension: path.extension().unwrap_or_default().to_string_lossy().to_string(),
+			content: OnceLock::from((contents, FileEncoding::UTF8)),
+			stamp: None,
+		}
+	}
+
+	pub fn content(&self) -> io::Result<&str> {
+		Ok(&self.read()?.0)
+	}
+
+	/// Encoding of the file on disk
+	pub fn encoding(&self) -> io::Result<FileEncoding> {
+		Ok(self.read()?.1)
+	}
+
+	fn read(&self) -> io::Result<&(String, FileEncoding)> {
+		if let Some(content) = self.content.get() {
+			return Ok(content);
+		}
+		if let Some(stamp) = &self.stamp {
+			// The parsed data in the cache doesn't match a changed file
+			if FileStamp::of(path::Path::new(&self.path))? != *stamp {
+				return Err(io::Error::other(format!("{} was changed while running", self.path)));
+			}
+		}
+		let content = encoding::read_text(path::Path::new(&self.path))?;
+		Ok(self.content.get_or_init(|| content))
+	}
+
 	/** Clean filename to comply with Windows, OSX and Linux rules, plus the extra rule that filenames don't start with dots or have leading spaces */
-	fn clean_filename(filename: &str) -> String {
+	pub fn clean_filename(filename: &str) -> String {
 		DOUBLE_SPACES
 			.replace_all(
-				&SURROUNDING_DOTS
-					.replace_all(
-						&ILLEGAL_FILE_CHARS.replace_all(&filename, " ").to_string(),
-						"",
-					)
-					.to_string(),
+				SURROUNDING_DOTS
+					.replace_all(ILLEGAL_FILE_CHARS.replace_all(filename, " ").as_ref(), "")
+					.as_ref(),
 				" ",
 			)
 			.trim()
 			.to_string()
 	}
 
-	pub fn save(path: &str, contents: &str) -> io::Result<()> {
-		// Make sure file always ends with one newline
-		fs::write(&path, String::from(contents.trim_end()) + "\n")
+	/// Write new contents in the encoding that the file had, or as UTF-8
+	/// with `normalize_encoding`
+	pub fn save(&self, contents: &str, normalize_encoding: bool, journal: Option<&Journal>) -> io::Result<()> {
+		let encoding = if normalize_encoding {
+			FileEncoding::UTF8
+		} else {
+			self.encoding()?
+		};
+		journal::write(journal, path::Path::new(&self.path), &encoding.encode(contents)?)
+	}
+
+	/// Line breaks of the file, that new contents should keep
+	fn line_endings(&self) -> io::Result<LineEndings> {
+		Ok(LineEndings::detect(self.content()?))
 	}
 
 	/// Renames file, assuming that the path is valid and escaped
-	pub fn rename(&self, new_stem: &str) -> io::Result<NoteFile> {
+	pub fn rename(&self, new_stem: &str, journal: Option<&Journal>) -> io::Result<NoteFile> {
+		let new_file = self.with_stem(new_stem)?;
+		journal::rename(journal, path::Path::new(&self.path), path::Path::new(&new_file.path))?;
+		Ok(new_file)
+	}
+
+	/// Copy with another file name, without renaming the file on disk
+	fn with_stem(&self, new_stem: &str) -> io::Result<NoteFile> {
 		let new_path = path::Path::new(&self.path)
 			.with_file_name(new_stem)
 			.with_extension(&self.extension);
-		fs::rename(&self.path, &new_path)?;
 		Ok(NoteFile {
 			path: new_path.as_os_str().to_str().unwrap().to_string(),
 			stem: new_stem.to_string(),
 			extension: self.extension.clone(),
-			content: self.content.clone(),
+			content: OnceLock::from(self.read()?.clone()),
+			stamp: None,
 		})
 	}
 
-	pub fn replace_contents(&self, contents: &str) -> NoteFile {
-		NoteFile {
+	pub fn replace_contents(&self, contents: &str) -> io::Result<NoteFile> {
+		Ok(NoteFile {
 			path: self.path.clone(),
 			stem: self.stem.clone(),
 			extension: self.extension.clone(),
-			content: contents.to_owned(),
+			content: OnceLock::from((contents.to_owned(), self.encoding()?)),
+			stamp: None,
+		})
+	}
+}
+
+/// Line breaks of a note file, that are kept when the file is changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LineEndings {
+	/// "\r\n" if the first line ends with it, else "\n"
+	newline: &'static str,
+	/// The file ends with a line break
+	final_newline: bool,
+}
+
+impl LineEndings {
+	fn detect(text: &str) -> LineEndings {
+		let newline = match text.find('\n') {
+			Some(i) if text[..i].ends_with('\r') => "\r\n",
+			_ => "\n",
+		};
+		LineEndings {
+			newline,
+			final_newline: text.ends_with('\n'),
+		}
+	}
+
+	/// Switch "\n" line breaks to the line breaks of the file
+	fn convert(&self, text: &str) -> String {
+		text.replace('\n', self.newline)
+	}
+
+	/// Trim the end of the text, and end it like the file ended
+	fn end_file(&self, text: &str) -> String {
+		let text = text.trim_end();
+		if self.final_newline {
+			String::from(text) + self.newline
+		} else {
+			text.to_owned()
 		}
 	}
 }
@@ -113,11 +228,15 @@ struct Note {
 	title: String,
 	title_lower: String,
 	id: Option<String>,
-	links: HashSet<WikiLink>,
-	tasks: Vec<String>,
+	headings: Vec<String>,
+	block_ids: Vec<String>,
+	links: Vec<Link>,
+	attachment_links: Vec<Link>,
+	tasks: Vec<Task>,
 	backlinks_start: Option<usize>,
 	backlinks_end: Option<usize>,
-	parser: Rc<NoteParser>,
+	/// Marked with "backlinks: false" in the front matter
+	no_backlinks: bool,
 }
 
 // Use path as unique identifier for notes
@@ -136,12 +255,14 @@ impl Hash for Note {
 	}
 }
 
-type RcRefNote = Rc<RefCell<Note>>;
-
 impl Note {
-	fn new(file: NoteFile, parser: Rc<NoteParser>) -> Note {
-		let data = parser.parse(&file.content);
+	fn new(file: NoteFile, parser: &NoteParser) -> Note {
+		let data = parser.parse_file(file.content.get().map_or("", |(content, _)| content.as_str()), &file.extension);
+		Note::with_data(file, data, parser)
+	}
 
+	/// Note with data that has already been parsed
+	fn with_data(file: NoteFile, data: NoteData, parser: &NoteParser) -> Note {
 		let id = match parser.get_id(&file.stem) {
 			// Prefer ID from filename if it exists
 			Some(file_id) => Some(file_id),
@@ -156,15 +277,23 @@ impl Note {
 			parser.remove_id(&file.stem)
 		};
 
+		let mut links = data.links;
+		for link in links.iter_mut() {
+			resolve_link(&file, link);
+		}
+
 		Note {
 			id,
 			title_lower: title.to_lowercase(),
 			title,
-			links: HashSet::from_iter(data.links),
+			headings: data.headings,
+			block_ids: data.block_ids,
+			links,
+			attachment_links: Vec::new(),
 			tasks: data.tasks,
 			backlinks_start: data.backlinks_start,
 			backlinks_end: data.backlinks_end,
-			parser,
+			no_backlinks: data.no_backlinks,
 			file,
 		}
 	}
@@ -178,72 +307,94 @@ impl Note {
 		self.backlinks_start.is_some()
 	}
 
-	/// Returns note contents with the backlinks section left out.
-	fn get_contents_without_backlinks(&self) -> String {
+	/// Returns note contents with the backlinks section left out. Only the
+	/// section is removed, or the end of the file if the section was last.
+	fn get_contents_without_backlinks(&self) -> io::Result<String> {
+		let content = self.file.content()?;
 		if let Some(start) = self.backlinks_start {
-			let end = self
-				.backlinks_end
-				.unwrap_or_else(|| self.file.content.len());
-
-			let new_len = start + self.file.content.len() - end;
-			let mut contents = String::with_capacity(new_len);
-			contents.push_str(&self.file.content[..start]);
-			if end < self.file.content.len() {
-				contents.push_str(&self.file.content[end..]);
-			}
-			assert_eq!(contents.len(), new_len);
-			contents
+			let end = self.backlinks_end.unwrap_or(content.len());
+			if end < content.len() {
+				Ok(String::from(&content[..start]) + &content[end..])
+			} else {
+				Ok(self.file.line_endings()?.end_file(&content[..start]))
+			}
 		} else {
-			self.file.content.to_owned()
+			Ok(content.to_owned())
 		}
 	}
 
-	/// Returns note contents with the backlinks section switched or added
-	fn get_contents_with_new_backlinks(&self, heading: &str, backlinks: &str) -> String {
-		let make_contents = |before: &str, after: &str| {
-			[before.trim_end(), heading, backlinks, after]
-				.join("\n\n")
-				.trim_end()
-				.to_owned()
+	/// Returns note contents with the backlinks section switched or added,
+	/// with the line breaks of the file. The heading and backlinks have
+	/// "\n" line breaks.
+	fn get_contents_with_new_backlinks(&self, heading: &str, backlinks: &str) -> io::Result<String> {
+		let content = self.file.content()?;
+		let line_endings = self.file.line_endings()?;
+		let (before, after) = match self.backlinks_start {
+			Some(start) => {
+				let end = self.backlinks_end.unwrap_or(content.len());
+				(&content[..start], &content[end..])
+			}
+			None => (content, ""),
 		};
 
-		if let Some(start) = self.backlinks_start {
-			let end = self
-				.backlinks_end
-				.unwrap_or_else(|| self.file.content.len());
-
-			make_contents(&self.file.content[..start], &self.file.content[end..])
+		let blank_line = line_endings.newline.repeat(2);
+		let heading = line_endings.convert(heading);
+		let backlinks = line_endings.convert(backlinks);
+		let section = [before.trim_end(), &heading, &backlinks].join(&blank_line);
+		if after.is_empty() {
+			Ok(line_endings.end_file(&section))
 		} else {
-			make_contents(&self.file.content, &"")
+			Ok(section + &blank_line + after)
 		}
 	}
 
-	/// Returns backlinks section without the heading, trimmed
-	fn get_backlinks_section_without_heading(&self) -> Option<&str> {
+	/// Returns backlinks section without the heading, trimmed and with "\n"
+	/// line breaks. The contents are only read if there is a section.
+	fn get_backlinks_section_without_heading(&self, heading: &str) -> io::Result<Option<String>> {
 		if let Some(start) = self.backlinks_start {
-			let end = self
-				.backlinks_end
-				.unwrap_or_else(|| self.file.content.len());
+			let content = self.file.content()?;
+			let end = self.backlinks_end.unwrap_or(content.len());
 
-			Some(&self.file.content[start + self.parser.backlinks_heading.len()..end].trim())
+			Ok(Some(content[start + heading.len()..end].trim().replace("\r\n", "\n")))
 		} else {
-			None
+			Ok(None)
 		}
 	}
 
+	/// Links to the note itself, like "[[#Heading]]", are not counted
 	fn has_outgoing_links(&self) -> bool {
-		!self.links.is_empty()
+		self.links.iter().any(|link| !self.is_link_to(&link.target))
+	}
+
+	/// Check if a heading or block ID exists in the note. Headings are
+	/// compared without case sensitivity, and for nested headings
+	/// ("#Heading#Subheading") only the last one is checked.
+	fn has_anchor(&self, anchor: &Anchor) -> bool {
+		match anchor {
+			Anchor::Heading(heading) => {
+				let heading = heading.trim().to_lowercase();
+				self.headings.iter().map(|h| h.to_lowercase()).any(|h| {
+					heading == h || heading.ends_with(&format!("#{}", h))
+				})
+			}
+			Anchor::Block(block_id) => self.block_ids.iter().any(|b| b == block_id),
+		}
+	}
+
+	/// Get the unique targets of all outgoing links
+	fn get_link_targets(&self) -> HashSet<&WikiLink> {
+		self.links.iter().map(|link| &link.target).collect()
 	}
 
 	/// Return a copy of the note's meta data
-	fn into_meta(&self) -> NoteMeta {
+	fn to_meta(&self) -> NoteMeta {
 		NoteMeta {
 			path: self.file.path.clone(),
 			stem: self.file.stem.clone(),
 			extension: self.file.extension.clone(),
 			title: self.title.clone(),
 			id: self.id.clone(),
-			has_links: self.links.len() > 0,
+			has_links: self.has_outgoing_links(),
 		}
 	}
 
@@ -268,46 +419,85 @@ impl Note {
 			.to_string()
 	}
 
+	fn get_aliased_wikilink_to(&self) -> String {
+		Note::get_aliased_wikilink(&self.id, &self.title, &self.file.stem)
+	}
+
+	/// Like get_wikilink(), but puts the title inside the link as a label
+	/// ("[[target|title]]")
+	fn get_aliased_wikilink(id: &Option<String>, title: &str, file_stem: &str) -> String {
+		let link_target = if let Some(i) = id { i } else { file_stem };
+
+		if title.is_empty()
+			|| title.to_lowercase() == link_target.to_lowercase()
+			|| title.contains('|')
+			|| title.contains("]]")
+		{
+			// Labels that would be redundant or break the link are left out
+			format!("[[{}]]", link_target)
+		} else {
+			format!("[[{}|{}]]", link_target, title)
+		}
+	}
+
 	pub fn get_filename_link(&self) -> WikiLink {
 		WikiLink::FileName(self.file.stem.to_string())
 	}
 
+	pub fn get_path_link(&self) -> WikiLink {
+		WikiLink::Path(self.file.path.to_string())
+	}
+
+	/// Get all link targets that can be used to link to this note
+	fn get_link_keys(&self) -> Vec<WikiLink> {
+		let mut keys = vec![self.get_filename_link(), self.get_path_link()];
+		if let Some(id) = &self.id {
+			keys.push(WikiLink::Id(id.to_string()));
+		}
+		keys
+	}
+
 	fn is_link_to(&self, link: &WikiLink) -> bool {
 		match link {
 			WikiLink::FileName(filename) => {
 				self.file.stem.to_lowercase() == filename.to_lowercase()
 			}
+			WikiLink::Path(path) => self.file.path.to_lowercase() == path.to_lowercase(),
 			WikiLink::Id(id) => {
 				self.id.as_ref().unwrap_or(&EMPTY_STRING).to_lowercase() == id.to_lowercase()
 			}
 		}
 	}
-
-	pub fn save(&self) -> io::Result<()> {
-		NoteFile::save(&self.file.path, &self.file.content)
-	}
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+/// Information about a note, detached from the collection
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct NoteMeta {
 	pub path: String,
+	/// File name without extension
 	pub stem: String,
 	pub extension: String,
 	pub title: String,
 	pub id: Option<String>,
+	/// The note links to other notes
 	pub has_links: bool,
 }
 
 impl NoteMeta {
+	/// Link to the note, followed by the title if it isn't in the link
+	/// ("[[20210119212027]] Title")
 	pub fn get_wikilink_to(&self) -> String {
 		Note::get_wikilink(&self.id, &self.title, &self.stem)
 	}
 }
 
-#[derive(Eq, Clone, Debug)]
+/// Target of a link, that notes are looked up by
+#[derive(Eq, Clone, Debug, Serialize, Deserialize)]
 pub enum WikiLink {
 	Id(String),
 	FileName(String),
+	/// Full path of the target of a Markdown link ("[text](path/to/note.md)")
+	Path(String),
 }
 
 // Case-insensitive matching for the WikiLink value
@@ -317,6 +507,7 @@ impl PartialEq for WikiLink {
 		match (self, other) {
 			(Id(a), Id(b)) => a == b,
 			(FileName(a), FileName(b)) => a.to_lowercase() == b.to_lowercase(),
+			(Path(a), Path(b)) => a.to_lowercase() == b.to_lowercase(),
 			_ => false,
 		}
 	}
@@ -329,7 +520,84 @@ impl Hash for WikiLink {
 		match self {
 			Id(link) => link.hash(state),
 			FileName(link) => link.to_lowercase().hash(state),
+			Path(link) => link.to_lowercase().hash(state),
+		}
+	}
+}
+
+impl WikiLink {
+	/// The link target as written in the link, without brackets
+	pub fn value(&self) -> &str {
+		match self {
+			WikiLink::Id(link) => link,
+			WikiLink::FileName(link) => link,
+			WikiLink::Path(link) => link,
+		}
+	}
+}
+
+/// Section of a note that a link points to
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum Anchor {
+	/// "[[target#Heading]]"
+	Heading(String),
+	/// "[[target#^block-id]]"
+	Block(String),
+}
+
+impl fmt::Display for Anchor {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Anchor::Heading(heading) => write!(f, "#{}", heading),
+			Anchor::Block(block_id) => write!(f, "#^{}", block_id),
+		}
+	}
+}
+
+/// A link found in a note, split into the target that is used to look up
+/// the linked note, the optional anchor and the optional label
+/// ("[[target#anchor|label]]")
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Link {
+	pub target: WikiLink,
+	pub anchor: Option<Anchor>,
+	pub label: Option<String>,
+	/// Embeds/transclusions ("![[target]]" or "![alt](path)")
+	pub embed: bool,
+	/// Line number in the note, starting at 1
+	pub line: usize,
+}
+
+impl fmt::Display for Link {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.embed {
+			write!(f, "!")?;
+		}
+		if let WikiLink::Path(path) = &self.target {
+			return write!(f, "[{}]({})", self.label.as_deref().unwrap_or_default(), path);
+		}
+		write!(f, "[[{}", self.target.value())?;
+		if let Some(anchor) = &self.anchor {
+			write!(f, "{}", anchor)?;
+		}
+		if let Some(label) = &self.label {
+			write!(f, "|{}", label)?;
 		}
+		write!(f, "]]")
+	}
+}
+
+/// An unchecked task ("- [ ] text")
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Task {
+	pub text: String,
+	/// Line number in the note, starting at 1
+	pub line: usize,
+}
+
+impl fmt::Display for Task {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.text)
 	}
 }
 
@@ -339,100 +607,425 @@ impl fmt::Display for WikiLink {
 		match self {
 			Id(link) => write!(f, "[[{}]]", link),
 			FileName(link) => write!(f, "[[{}]]", link),
+			Path(link) => write!(f, "({})", link),
 		}
 	}
 }
 
+/// Position of a note in `NoteCollection::notes`
+type NoteIndex = usize;
+
+/// All notes and attachments in a directory, or in memory, with lookups
+/// for links and backlinks
 pub struct NoteCollection {
-	/// Lookup for IDs and file names to all notes
-	notes: HashMap<WikiLink, RcRefNote>,
+	/// All notes, sorted by path once the links are indexed
+	notes: Vec<Note>,
+	/// Lookup for IDs, file names and paths to all notes
+	lookup: HashMap<WikiLink, NoteIndex>,
 	/// Lookup for links, with the target as key
-	backlinks: HashMap<WikiLink, Vec<RcRefNote>>,
+	backlinks: HashMap<WikiLink, Vec<NoteIndex>>,
+	/// Lookup for file names (with extension) and paths to all other files
+	attachments: HashMap<WikiLink, path::PathBuf>,
+	parser: NoteParser,
+	/// Lowercase file extensions of notes
+	extensions: Vec<String>,
+	/// Directory the files were collected from, if they were read from disk
+	root: Option<path::PathBuf>,
+	/// Only record changes, instead of writing them to disk
+	dry_run: bool,
+	/// Save notes as UTF-8, instead of in the encoding they had
+	normalize_encoding: bool,
+	/// Record the changes, so that they can be undone
+	journal: Option<Journal>,
+	planned_changes: RefCell<Vec<Change>>,
+	/// Failures for single notes, that didn't stop the operations
+	report: RefCell<Report>,
+}
+
+/// A change to the files on disk, that is planned in a dry run
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+	Write {
+		path: String,
+		old_contents: String,
+		new_contents: String,
+	},
+	Rename {
+		from: String,
+		to: String,
+	},
 }
 
 impl NoteCollection {
-	pub fn collect_files(root: &path::Path, extension: &str, parser: NoteParser) -> NoteCollection {
-		let parser = Rc::new(parser);
-		let mut notes = HashMap::new();
-		let mut backlinks = HashMap::new();
+	/// Read and parse all notes and find all attachments. With a cache,
+	/// notes that haven't changed since they were put in the cache are not
+	/// read or parsed again.
+	pub fn collect_files(
+		root: &path::Path,
+		filter: &ftree::FileFilter,
+		parser: NoteParser,
+		mut cache: Option<&mut ParseCache>,
+	) -> NoteCollection {
+		let mut collection = NoteCollection {
+			notes: Vec::new(),
+			lookup: HashMap::new(),
+			backlinks: HashMap::new(),
+			attachments: HashMap::new(),
+			parser,
+			extensions: filter.extensions.clone(),
+			root: Some(root.to_path_buf()),
+			dry_run: false,
+			normalize_encoding: false,
+			journal: None,
+			planned_changes: RefCell::new(Vec::new()),
+			report: RefCell::new(Report::default()),
+		};
 
 		let start_time = Utc::now();
-		let note_paths = ftree::get_files(root, extension);
+		let (note_paths, attachment_paths) = ftree::get_files(root, filter, collection.report.get_mut());
 		let duration_get_files = Utc::now() - start_time;
-		let start_time = Utc::now();
-		for path in note_paths {
-			let note_file = match NoteFile::new(&path) {
-				Ok(nf) => nf,
-				Err(err) => {
-					eprintln!(
-						"{} Couldn't read file {}: {}",
-						Colour::Red.paint("Error:"),
-						path.to_string_lossy(),
-						err
-					);
-					continue;
-				}
-			};
-
-			let note = Rc::new(RefCell::new(Note::new(note_file, Rc::clone(&parser))));
-
-			if let Some(id) = &note.borrow().id {
-				if let Some(conflicting_note) =
-					notes.insert(WikiLink::Id(id.clone()), Rc::clone(&note))
-				{
-					eprintln!(
-						"{} The id {} was used in both \"{}\" and \"{}\"",
-						Colour::Yellow.paint("Warning:"),
-						id,
-						note.borrow().file.stem,
-						conflicting_note.borrow().file.stem
-					);
-				}
-			}
+		for error in filter.take_errors() {
+			collection.report.get_mut().push(error);
+		}
 
-			notes.insert(note.borrow().get_filename_link(), Rc::clone(&note));
+		for path in attachment_paths {
+			collection.add_attachment(path);
+		}
 
-			for link in &note.borrow().links {
-				// Ignore "backlinks" to self
-				if !note.borrow().is_link_to(link) {
-					backlinks
-						.entry(link.clone())
-						.or_insert_with(Vec::new)
-						.push(Rc::clone(&note));
+		let start_time = Utc::now();
+		// Read and parse notes on all cores, and merge them in the order of
+		// the paths, so that the result doesn't depend on the timing
+		let loaded: Vec<io::Result<LoadedNote>> = note_paths
+			.par_iter()
+			.map(|path| load_note(path, &collection.parser, cache.as_deref()))
+			.collect();
+		for (path, loaded) in note_paths.iter().zip(loaded) {
+			match loaded {
+				Ok(LoadedNote { note, parsed }) => {
+					if let (Some(cache), Some((stamp, data))) = (cache.as_deref_mut(), parsed) {
+						cache.insert(&note.file.path, stamp, data);
+					}
+					collection.notes.push(note);
 				}
+				Err(err) => collection.report.get_mut().push(Error::io(path, err)),
 			}
 		}
+		if let Some(cache) = cache {
+			// Forget notes that no longer exist
+			let paths: HashSet<&str> = collection.notes.iter().map(|n| n.file.path.as_str()).collect();
+			cache.retain(|path| paths.contains(path));
+		}
+		collection.index_links();
 		let duration_note_loop = Utc::now() - start_time;
 
-		debug_println!(
+		collection.report_duplicate_ids();
+
+		debug_eprintln!(
 			"ftree::get_files() took {} ms",
 			duration_get_files.num_milliseconds()
 		);
-		debug_println!(
+		debug_eprintln!(
 			"loading and parsing notes took {} ms",
 			duration_note_loop.num_milliseconds()
 		);
 
-		NoteCollection { notes, backlinks }
+		collection
+	}
+
+	/// Parse notes that are not read from disk, given as paths and contents.
+	/// The paths are only used to identify notes, and for relative links.
+	/// Nothing is ever written to disk, so the changes of the update
+	/// operations are only returned by `take_planned_changes`.
+	pub fn from_documents<I>(documents: I, parser: NoteParser, extension: &str) -> NoteCollection
+	where
+		I: IntoIterator<Item = (path::PathBuf, String)>,
+	{
+		let documents: Vec<(path::PathBuf, String)> = documents.into_iter().collect();
+		let notes = documents
+			.into_par_iter()
+			.map(|(path, contents)| Note::new(NoteFile::with_contents(&path, contents), &parser))
+			.collect();
+
+		let mut collection = NoteCollection {
+			notes,
+			lookup: HashMap::new(),
+			backlinks: HashMap::new(),
+			attachments: HashMap::new(),
+			parser,
+			extensions: ftree::parse_extensions(extension),
+			root: None,
+			dry_run: true,
+			normalize_encoding: false,
+			journal: None,
+			planned_changes: RefCell::new(Vec::new()),
+			report: RefCell::new(Report::default()),
+		};
+		collection.index_links();
+		collection
+	}
+
+	fn add_attachment(&mut self, path: path::PathBuf) {
+		if let Some(file_name) = path.file_name() {
+			let file_name = file_name.to_string_lossy().to_string();
+			self.attachments.insert(WikiLink::FileName(file_name), path.clone());
+		}
+		self.attachments
+			.insert(WikiLink::Path(path.to_string_lossy().to_string()), path);
+	}
+
+	/// Remove notes and attachments. Links must be indexed with
+	/// `index_links` afterwards.
+	fn remove_files(&mut self, paths: &[path::PathBuf]) {
+		let keys: HashSet<WikiLink> = paths
+			.iter()
+			.map(|path| WikiLink::Path(path.to_string_lossy().to_string()))
+			.collect();
+		self.notes.retain(|note| !keys.contains(&note.get_path_link()));
+		for key in &keys {
+			if let Some(path) = self.attachments.remove(key) {
+				self.attachments.retain(|_, p| *p != path);
+			}
+		}
+	}
+
+	/// Sort the notes, build the lookups, and tell links to notes and
+	/// attachments apart. All notes must be known before this can be done.
+	fn index_links(&mut self) {
+		// Keep the order of notes, and thereby all output, stable
+		self.notes.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+
+		// The first note by path wins when notes share an ID or file name
+		self.lookup.clear();
+		for (index, note) in self.notes.iter().enumerate() {
+			self.lookup.entry(note.get_path_link()).or_insert(index);
+			self.lookup.entry(note.get_filename_link()).or_insert(index);
+			if let Some(id) = &note.id {
+				self.lookup.entry(WikiLink::Id(id.clone())).or_insert(index);
+			}
+		}
+
+		// Restore attachment lookups that were shared with removed files
+		let attachment_paths: Vec<path::PathBuf> = self
+			.attachments
+			.iter()
+			.filter(|(k, _)| matches!(k, WikiLink::Path(_)))
+			.map(|(_, v)| v.clone())
+			.collect();
+		for path in attachment_paths {
+			if let Some(file_name) = path.file_name() {
+				let file_name = file_name.to_string_lossy().to_string();
+				self.attachments
+					.entry(WikiLink::FileName(file_name))
+					.or_insert(path);
+			}
+		}
+
+		self.backlinks.clear();
+		for (index, note) in self.notes.iter_mut().enumerate() {
+			split_links(note, &self.lookup, &self.attachments, &self.extensions, self.parser.markdown_links);
+			for link in note.get_link_targets() {
+				// Ignore "backlinks" to self
+				if !note.is_link_to(link) {
+					self.backlinks.entry(link.clone()).or_default().push(index);
+				}
+			}
+		}
+	}
+
+	/// Reread notes and attachments that have been created, changed,
+	/// renamed or deleted. Other notes are not parsed again. Files that are
+	/// now ignored are removed.
+	pub fn reload_files(&mut self, filter: &ftree::FileFilter, paths: &[path::PathBuf]) {
+		self.remove_files(paths);
+		for path in paths {
+			let included = self.root.as_ref().is_none_or(|root| filter.includes(root, path));
+			if included && path.is_file() {
+				if filter.is_note(path) {
+					match load_note(path, &self.parser, None) {
+						Ok(loaded) => self.notes.push(loaded.note),
+						Err(err) => self.report.get_mut().push(Error::io(path, err)),
+					}
+				} else {
+					self.add_attachment(path.clone());
+				}
+			}
+		}
+		for error in filter.take_errors() {
+			self.report.get_mut().push(error);
+		}
+		self.index_links();
+	}
+
+	/// Replace the contents of a note with contents that are not saved to
+	/// disk, or add a new note with the contents. As long as the note keeps
+	/// its ID, only its own links are indexed again.
+	pub fn set_note_contents(&mut self, path: &path::Path, contents: String) {
+		let mut note = Note::new(NoteFile::with_contents(path, contents), &self.parser);
+		let index = match self.lookup.get(&note.get_path_link()) {
+			Some(&index) if self.notes[index].id == note.id => index,
+			_ => {
+				self.remove_files(&[path.to_path_buf()]);
+				self.notes.push(note);
+				self.index_links();
+				return;
+			}
+		};
+
+		split_links(&mut note, &self.lookup, &self.attachments, &self.extensions, self.parser.markdown_links);
+		for link in self.notes[index].get_link_targets() {
+			if let Some(linkers) = self.backlinks.get_mut(link) {
+				linkers.retain(|&linker| linker != index);
+				if linkers.is_empty() {
+					self.backlinks.remove(link);
+				}
+			}
+		}
+		for link in note.get_link_targets() {
+			if !note.is_link_to(link) {
+				let linkers = self.backlinks.entry(link.clone()).or_default();
+				linkers.push(index);
+				linkers.sort();
+			}
+		}
+		self.notes[index] = note;
 	}
 
-	/// Get iterator over notes
-	fn get_notes_iter(&self) -> impl Iterator<Item = Ref<Note>> {
-		self.notes
+	/// Get paths of all notes and attachments
+	pub fn get_paths(&self) -> Vec<path::PathBuf> {
+		let note_paths = self
+			.notes
+			.iter()
+			.map(|note| path::PathBuf::from(&note.file.path));
+		let attachment_paths = self
+			.attachments
 			.iter()
-			// Only look at filename ids/keys, since all notes have
-			// exactly one filename, but 0..1 ids.
-			.filter(|(k, _)| matches!(k, WikiLink::FileName(_)))
-			.map(|(_, v)| v.borrow())
+			.filter(|(k, _)| matches!(k, WikiLink::Path(_)))
+			.map(|(_, path)| path.clone());
+		note_paths.chain(attachment_paths).collect()
+	}
+
+	/// Don't write anything to disk, only record the changes that would
+	/// have been made. Get them with `take_planned_changes`.
+	pub fn with_dry_run(mut self, dry_run: bool) -> NoteCollection {
+		self.set_dry_run(dry_run);
+		self
+	}
+
+	/// Like `with_dry_run`, for an existing collection
+	pub fn set_dry_run(&mut self, dry_run: bool) {
+		self.dry_run = dry_run;
+	}
+
+	/// Save notes as UTF-8, instead of in the encoding that was detected
+	/// when they were read
+	pub fn with_normalized_encoding(mut self, normalize_encoding: bool) -> NoteCollection {
+		self.normalize_encoding = normalize_encoding;
+		self
+	}
+
+	/// Keep a journal of the changes in the notes directory, that the
+	/// `undo` subcommand restores
+	pub fn with_journal(mut self, root: &path::Path) -> NoteCollection {
+		self.journal = Some(Journal::new(root));
+		self
+	}
+
+	/// Let the next change replace the journal, so that `undo` only
+	/// restores the changes made after this
+	pub fn restart_journal(&self) {
+		if let Some(journal) = &self.journal {
+			journal.restart();
+		}
+	}
+
+	pub(crate) fn journal(&self) -> Option<&Journal> {
+		self.journal.as_ref()
+	}
+
+	pub fn parser(&self) -> &NoteParser {
+		&self.parser
+	}
+
+	/// Get the changes recorded in a dry run, and forget them
+	pub fn take_planned_changes(&self) -> Vec<Change> {
+		self.planned_changes.take()
+	}
+
+	/// Get the failures for single notes since the last call, like notes
+	/// that couldn't be read or saved, and forget them
+	pub fn take_report(&self) -> Report {
+		self.report.take()
+	}
+
+	fn report_duplicate_ids(&mut self) {
+		for (id, notes) in self.get_duplicate_ids() {
+			let paths = notes.iter().map(|note| path::PathBuf::from(&note.path)).collect();
+			self.report.get_mut().push(Error::DuplicateId { id, paths });
+		}
+	}
+
+	/// Add an IO error for the note to the report
+	fn report_io_error(&self, note: &Note, err: io::Error) {
+		let error = Error::io(path::Path::new(&note.file.path), err);
+		self.report.borrow_mut().push(error);
+	}
+
+	/// Save new contents of a note, or record the change in a dry run
+	fn save_note(&self, note: &Note, contents: &str) -> io::Result<()> {
+		if !self.dry_run {
+			return note.file.save(contents, self.normalize_encoding, self.journal());
+		}
+
+		let new_contents = contents.to_owned();
+		let mut changes = self.planned_changes.borrow_mut();
+		// Keep one change per file, from the original to the latest contents
+		for change in changes.iter_mut() {
+			if let Change::Write { path, new_contents: planned, .. } = change {
+				if *path == note.file.path {
+					*planned = new_contents;
+					return Ok(());
+				}
+			}
+		}
+		changes.push(Change::Write {
+			path: note.file.path.clone(),
+			old_contents: note.file.content()?.to_owned(),
+			new_contents,
+		});
+		Ok(())
+	}
+
+	/// Rename a note file, or record the change in a dry run
+	fn rename_note_file(&self, file: &NoteFile, new_stem: &str) -> io::Result<NoteFile> {
+		if !self.dry_run {
+			return file.rename(new_stem, self.journal());
+		}
+
+		let new_file = file.with_stem(new_stem)?;
+		self.planned_changes.borrow_mut().push(Change::Rename {
+			from: file.path.clone(),
+			to: new_file.path.clone(),
+		});
+		Ok(new_file)
+	}
+
+	/// Get iterator over notes, sorted by path
+	fn get_notes_iter(&self) -> impl Iterator<Item = &Note> {
+		self.notes.iter()
 	}
 
 	/// Get vector of notes, sorted by title
-	fn get_sorted_notes(&self) -> Vec<Ref<Note>> {
-		let mut notes: Vec<Ref<Note>> = self.get_notes_iter().collect();
+	fn get_sorted_notes(&self) -> Vec<&Note> {
+		let mut notes: Vec<&Note> = self.get_notes_iter().collect();
 		notes.sort_by(|a, b| a.title_lower.cmp(&b.title_lower));
 		notes
 	}
 
+	/// Look up a note by ID, file name or path
+	fn get(&self, key: &WikiLink) -> Option<&Note> {
+		self.lookup.get(key).map(|&index| &self.notes[index])
+	}
+
 	pub fn count(&self) -> usize {
 		self.get_notes_iter().count()
 	}
@@ -441,57 +1034,123 @@ impl NoteCollection {
 		self.get_notes_iter().filter(|n| n.id.is_some()).count()
 	}
 
+	/// Number of distinct link targets, including unknown notes
 	pub fn count_links(&self) -> usize {
 		self.backlinks.len()
 	}
 
-	pub fn into_meta_vec(&self) -> Vec<NoteMeta> {
+	pub fn count_attachments(&self) -> usize {
+		self.attachments
+			.keys()
+			.filter(|k| matches!(k, WikiLink::Path(_)))
+			.count()
+	}
+
+	/// Get all notes, sorted by title
+	pub fn to_meta_vec(&self) -> Vec<NoteMeta> {
 		let mut notes = Vec::with_capacity(self.count());
 		for note in &self.get_sorted_notes() {
-			notes.push(note.into_meta());
+			notes.push(note.to_meta());
 		}
 		notes
 	}
 
 	fn note_has_incoming_links(&self, note: &Note) -> bool {
-		if self
-			.backlinks
-			.contains_key(&WikiLink::FileName(note.file.stem.to_string()))
-		{
-			return true;
-		}
+		note.get_link_keys()
+			.iter()
+			.any(|key| self.backlinks.contains_key(key))
+	}
 
-		if let Some(id) = &note.id {
-			if self.backlinks.contains_key(&WikiLink::Id(id.to_string())) {
-				return true;
-			}
-		}
+	/// Get incoming links to note. Can contain duplicates!
+	fn get_incoming_links(&self, note: &Note) -> Vec<&Note> {
+		note.get_link_keys()
+			.iter()
+			.filter_map(|key| self.backlinks.get(key))
+			.flatten()
+			.map(|&index| &self.notes[index])
+			.collect()
+	}
 
-		false
+	/// Get the note with the path, if it exists
+	pub fn get_note(&self, path: &path::Path) -> Option<NoteMeta> {
+		let key = WikiLink::Path(path.to_string_lossy().to_string());
+		self.get(&key).map(Note::to_meta)
 	}
 
-	/// Get incoming links to note. Can contain duplicates!
-	fn get_incoming_links(&self, note: &Note) -> Vec<RcRefNote> {
-		let empty: Vec<RcRefNote> = Vec::new();
+	/// Get the note with the ID. If several notes have the ID, the first
+	/// one by path is returned.
+	pub fn get_note_by_id(&self, id: &str) -> Option<NoteMeta> {
+		self.get(&WikiLink::Id(id.to_owned())).map(Note::to_meta)
+	}
 
-		let mut links: Vec<&RcRefNote> = self
-			.backlinks
-			.get(&note.get_filename_link())
-			.unwrap_or(&empty)
-			.iter()
-			.collect();
+	/// Get the note with the file name (without extension), ignoring case
+	pub fn get_note_by_filename(&self, stem: &str) -> Option<NoteMeta> {
+		self.get(&WikiLink::FileName(stem.to_owned())).map(Note::to_meta)
+	}
 
-		if let Some(id) = &note.id {
-			links.extend(
-				&mut self
-					.backlinks
-					.get(&WikiLink::Id(id.to_string()))
-					.unwrap_or(&empty)
-					.iter(),
-			)
+	/// Get the links from the note at `path` to other notes, including
+	/// unknown notes, in the order they appear
+	pub fn get_links(&self, path: &path::Path) -> Vec<Link> {
+		let key = WikiLink::Path(path.to_string_lossy().to_string());
+		self.get(&key).map(|note| note.links.clone()).unwrap_or_default()
+	}
+
+	/// Get the links from the note at `path` to attachments, in the order
+	/// they appear
+	pub fn get_attachment_links(&self, path: &path::Path) -> Vec<Link> {
+		let key = WikiLink::Path(path.to_string_lossy().to_string());
+		self.get(&key)
+			.map(|note| note.attachment_links.clone())
+			.unwrap_or_default()
+	}
+
+	/// Get the notes that link to the note at `path`, sorted by path
+	pub fn get_backlinks(&self, path: &path::Path) -> Vec<NoteMeta> {
+		let key = WikiLink::Path(path.to_string_lossy().to_string());
+		let note = match self.get(&key) {
+			Some(note) => note,
+			None => return Vec::new(),
+		};
+
+		let mut linking_notes = self.get_incoming_links(note);
+		linking_notes.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+		linking_notes.dedup();
+		linking_notes.into_iter().map(Note::to_meta).collect()
+	}
+
+	/// Get the path of the note or attachment that a link in the note at
+	/// `note_path` points to, if it exists
+	pub fn get_link_target(&self, note_path: &path::Path, link: &Link) -> Option<path::PathBuf> {
+		let mut link = link.clone();
+		resolve_link(&NoteFile::with_contents(note_path, String::new()), &mut link);
+		if let Some(note) = self.get(&link.target) {
+			return Some(path::PathBuf::from(&note.file.path));
 		}
+		self.attachments.get(&get_attachment_key(&link.target)).cloned()
+	}
+
+	/// Get each link to the note at `path`, together with the linking note,
+	/// sorted by path and line
+	pub fn get_references(&self, path: &path::Path) -> Vec<(NoteMeta, Link)> {
+		let key = WikiLink::Path(path.to_string_lossy().to_string());
+		let note = match self.get(&key) {
+			Some(note) => note,
+			None => return Vec::new(),
+		};
+
+		let mut linking_notes = self.get_incoming_links(note);
+		linking_notes.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+		linking_notes.dedup();
 
-		links.iter().map(|rcn| Rc::clone(rcn)).collect()
+		let mut references = Vec::new();
+		for linking_note in &linking_notes {
+			for link in &linking_note.links {
+				if note.is_link_to(&link.target) {
+					references.push((linking_note.to_meta(), link.clone()));
+				}
+			}
+		}
+		references
 	}
 
 	/// Get notes with no incoming links, but at least one outgoing
@@ -499,7 +1158,7 @@ impl NoteCollection {
 		let mut sources = Vec::new();
 		for note in &self.get_sorted_notes() {
 			if note.has_outgoing_links() && !self.note_has_incoming_links(note) {
-				sources.push(note.into_meta());
+				sources.push(note.to_meta());
 			}
 		}
 		sources
@@ -510,7 +1169,7 @@ impl NoteCollection {
 		let mut sinks = Vec::new();
 		for note in &self.get_sorted_notes() {
 			if !note.has_outgoing_links() && self.note_has_incoming_links(note) {
-				sinks.push(note.into_meta());
+				sinks.push(note.to_meta());
 			}
 		}
 		sinks
@@ -521,98 +1180,327 @@ impl NoteCollection {
 		let mut isolated = Vec::new();
 		for note in &self.get_sorted_notes() {
 			if !note.has_outgoing_links() && !self.note_has_incoming_links(note) {
-				isolated.push(note.into_meta());
+				isolated.push(note.to_meta());
 			}
 		}
 		isolated
 	}
 
-	pub fn get_broken_links(&self) -> Vec<(&WikiLink, Vec<NoteMeta>)> {
-		let mut notes = Vec::new();
-		let linked: HashSet<&WikiLink> = self.backlinks.keys().collect();
-		let existing: HashSet<&WikiLink> = self.notes.keys().collect();
-		for broken in linked.difference(&existing) {
-			let linkers: Vec<NoteMeta> = self.backlinks[broken]
-				.iter()
-				.map(|note| note.borrow().into_meta())
-				.collect();
-			notes.push((*broken, linkers));
+	/// Find a note by ID, file name, path or title, in that order. Titles
+	/// are compared without case sensitivity.
+	pub fn find_note(&self, name: &str) -> Option<NoteMeta> {
+		let keys = [
+			WikiLink::Id(name.to_owned()),
+			WikiLink::FileName(name.to_owned()),
+			WikiLink::Path(name.to_owned()),
+		];
+		if let Some(note) = keys.iter().find_map(|key| self.get(key)) {
+			return Some(note.to_meta());
+		}
+		let title = name.to_lowercase();
+		self.get_notes_iter()
+			.find(|note| note.title_lower == title)
+			.map(Note::to_meta)
+	}
+
+	/// Get the groups of notes that are connected by links, largest first.
+	/// With `strong`, each note in a group can be reached from all the
+	/// others by following links forward, otherwise in any direction. The
+	/// notes in each group are sorted by title.
+	pub fn get_components(&self, strong: bool) -> Vec<Vec<NoteMeta>> {
+		let graph = self.get_graph().without_missing();
+		let components = if strong {
+			graph.get_strong_components()
+		} else {
+			graph.get_weak_components()
+		};
+		components
+			.into_iter()
+			.map(|members| {
+				let mut notes: Vec<NoteMeta> = members
+					.into_iter()
+					.filter_map(|index| graph.nodes()[index].note().cloned())
+					.collect();
+				notes.sort_by_cached_key(|note| note.title.to_lowercase());
+				notes
+			})
+			.collect()
+	}
+
+	/// Get the links between notes as a graph, where the notes come first,
+	/// sorted by path, followed by the targets of broken links, sorted by
+	/// name
+	pub fn get_graph(&self) -> NoteGraph {
+		let mut nodes: Vec<Node> = self.notes.iter().map(|note| Node::Note(note.to_meta())).collect();
+		let mut missing: Vec<&WikiLink> = self
+			.backlinks
+			.keys()
+			.filter(|link| !self.lookup.contains_key(link))
+			.collect();
+		missing.sort_by_cached_key(|link| (link.value().to_lowercase(), link.to_string()));
+		let missing_index: HashMap<&WikiLink, usize> = missing
+			.iter()
+			.enumerate()
+			.map(|(i, &link)| (link, nodes.len() + i))
+			.collect();
+		nodes.extend(missing.iter().map(|&link| Node::Missing(link.clone())));
+
+		let mut outgoing = vec![Vec::new(); nodes.len()];
+		for (index, note) in self.notes.iter().enumerate() {
+			for link in note.get_link_targets() {
+				// Links to the note itself, like "[[#Heading]]", are not edges
+				match self.lookup.get(link).or_else(|| missing_index.get(link)) {
+					Some(&target) if target != index => outgoing[index].push(target),
+					_ => {}
+				}
+			}
 		}
+		NoteGraph::new(nodes, outgoing)
+	}
+
+	/// Get links to unknown notes, with the linking notes, sorted by target
+	pub fn get_broken_links(&self) -> Vec<(&WikiLink, Vec<NoteMeta>)> {
+		let mut notes: Vec<(&WikiLink, Vec<NoteMeta>)> = self
+			.backlinks
+			.iter()
+			.filter(|(link, _)| !self.lookup.contains_key(link))
+			.map(|(link, linkers)| {
+				let linkers = linkers.iter().map(|&index| self.notes[index].to_meta()).collect();
+				(link, linkers)
+			})
+			.collect();
+		notes.sort_by_cached_key(|(link, _)| (link.value().to_lowercase(), link.to_string()));
 		notes
 	}
 
-	pub fn get_tasks(&self) -> Vec<(NoteMeta, Vec<String>)> {
+	/// Get each link to an unknown note, together with the linking note
+	pub fn get_broken_link_occurrences(&self) -> Vec<(NoteMeta, Link)> {
+		let mut broken = Vec::new();
+		for note in &self.get_sorted_notes() {
+			for link in &note.links {
+				if !self.lookup.contains_key(&link.target) {
+					broken.push((note.to_meta(), link.clone()));
+				}
+			}
+		}
+		broken
+	}
+
+	/// Get each link to a missing attachment, together with the linking note
+	pub fn get_broken_attachment_occurrences(&self) -> Vec<(NoteMeta, Link)> {
+		let mut broken = Vec::new();
+		for note in &self.get_sorted_notes() {
+			for link in &note.attachment_links {
+				if !self.attachments.contains_key(&get_attachment_key(&link.target)) {
+					broken.push((note.to_meta(), link.clone()));
+				}
+			}
+		}
+		broken
+	}
+
+	/// Get links to headings or block IDs that don't exist in the target
+	/// note. Links to unknown notes are not included.
+	pub fn get_broken_anchors(&self) -> Vec<(NoteMeta, Link)> {
+		let mut broken = Vec::new();
+		for note in &self.get_sorted_notes() {
+			for link in &note.links {
+				if let Some(anchor) = &link.anchor {
+					if let Some(target) = self.get(&link.target) {
+						if !target.has_anchor(anchor) {
+							broken.push((note.to_meta(), link.clone()));
+						}
+					}
+				}
+			}
+		}
+		broken
+	}
+
+	/// Get links to attachments that don't exist, with the linking notes
+	pub fn get_broken_attachments(&self) -> Vec<(WikiLink, Vec<NoteMeta>)> {
+		let mut broken: Vec<(WikiLink, Vec<NoteMeta>)> = Vec::new();
+		let mut positions: HashMap<WikiLink, usize> = HashMap::new();
+		for note in &self.get_sorted_notes() {
+			for link in &note.attachment_links {
+				if self.attachments.contains_key(&get_attachment_key(&link.target)) {
+					continue;
+				}
+				let pos = *positions.entry(link.target.clone()).or_insert_with(|| {
+					broken.push((link.target.clone(), Vec::new()));
+					broken.len() - 1
+				});
+				let linker = note.to_meta();
+				if !broken[pos].1.contains(&linker) {
+					broken[pos].1.push(linker);
+				}
+			}
+		}
+		broken
+	}
+
+	/// Get attachments that no note links to, sorted by path, leaving out
+	/// those in the directory `except_in`
+	pub fn get_unused_attachments(&self, except_in: Option<&path::Path>) -> Vec<path::PathBuf> {
+		let mut used = HashSet::new();
+		for note in self.get_notes_iter() {
+			for link in &note.attachment_links {
+				used.insert(get_attachment_key(&link.target));
+			}
+		}
+
+		let mut unused: Vec<path::PathBuf> = self
+			.attachments
+			.iter()
+			.filter(|(key, _)| matches!(key, WikiLink::Path(_)))
+			.filter(|(_, path)| except_in.is_none_or(|dir| !path.starts_with(dir)))
+			.filter(|(key, path)| {
+				// Wikilinks use the file name, so all files with that name are used
+				let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+				!used.contains(*key) && !used.contains(&WikiLink::FileName(file_name.to_string()))
+			})
+			.map(|(_, path)| path.clone())
+			.collect();
+		unused.sort();
+		unused
+	}
+
+	/// Get unchecked tasks, by note sorted by title
+	pub fn get_tasks(&self) -> Vec<(NoteMeta, Vec<Task>)> {
 		let mut tasks = Vec::new();
 		for note in &self.get_sorted_notes() {
 			if !note.tasks.is_empty() {
-				tasks.push((note.into_meta(), note.tasks.clone()));
+				tasks.push((note.to_meta(), note.tasks.clone()));
 			}
 		}
 		tasks
 	}
 
+	/// Remove the backlinks sections, and get the notes that had one
 	pub fn remove_backlinks(&self) -> Vec<NoteMeta> {
 		let mut notes = Vec::new();
 		for note in &self.get_sorted_notes() {
 			if note.has_backlinks() {
-				if let Err(e) =
-					NoteFile::save(&note.file.path, &note.get_contents_without_backlinks())
-				{
-					eprintln!("Error while saving note file {}: {}", note.file.path, e);
-				} else {
-					notes.push(note.into_meta());
+				let saved = note
+					.get_contents_without_backlinks()
+					.and_then(|contents| self.save_note(note, &contents));
+				match saved {
+					Ok(()) => notes.push(note.to_meta()),
+					Err(err) => self.report_io_error(note, err),
 				}
 			}
 		}
 		notes
 	}
 
-	pub fn update_backlinks(&self) -> Vec<NoteMeta> {
-		let mut notes = Vec::new();
-		for note in &self.get_sorted_notes() {
-			let incoming_links = self.get_incoming_links(note);
-			let mut incoming_links: Vec<Ref<Note>> =
-				incoming_links.iter().map(|n| n.borrow()).collect();
+	/// Get the backlinks section that the note should have, without heading.
+	/// Notes that opt out of backlinks should have none.
+	fn get_new_backlinks_section(&self, note: &Note, aliased: bool) -> String {
+		if note.no_backlinks {
+			return String::new();
+		}
+		let mut incoming_links = self.get_incoming_links(note);
 
-			// First sort by filename to get a stable sort when titles are identical
-			incoming_links.sort_by(|a, b| a.file.stem.cmp(&b.file.stem));
-			incoming_links.sort_by(|a, b| a.title_lower.cmp(&b.title_lower));
+		// First sort by filename to get a stable sort when titles are identical
+		incoming_links.sort_by(|a, b| a.file.stem.cmp(&b.file.stem));
+		incoming_links.sort_by(|a, b| a.title_lower.cmp(&b.title_lower));
 
-			let mut new_backlinks: Vec<String> = incoming_links
-				.iter()
-				.map(|linking_note| "- ".to_string() + &linking_note.get_wikilink_to())
-				.collect();
+		let mut new_backlinks: Vec<String> = incoming_links
+			.iter()
+			.map(|linking_note| {
+				if aliased {
+					"- ".to_string() + &linking_note.get_aliased_wikilink_to()
+				} else {
+					"- ".to_string() + &linking_note.get_wikilink_to()
+				}
+			})
+			.collect();
 
-			// Remove possible duplicate links
-			new_backlinks.dedup();
+		// Remove possible duplicate links
+		new_backlinks.dedup();
 
-			let new_section = new_backlinks.join("\n");
+		new_backlinks.join("\n")
+	}
 
-			let current_section = note
-				.get_backlinks_section_without_heading()
-				.unwrap_or_default();
+	/// Update backlinks sections. With `aliased`, the links are written as
+	/// "[[target|title]]" instead of "[[target]] title".
+	pub fn update_backlinks(&self, aliased: bool) -> Vec<NoteMeta> {
+		let mut notes = Vec::new();
+		for note in &self.get_sorted_notes() {
+			let new_section = self.get_new_backlinks_section(note, aliased);
 
-			if current_section != new_section {
+			let heading = &self.parser.backlinks_heading;
+			let saved = note.get_backlinks_section_without_heading(heading).and_then(|current_section| {
+				if current_section.unwrap_or_default() == new_section {
+					return Ok(false);
+				}
 				let new_contents = if !new_section.is_empty() {
 					// Add or update backlinks
-					note.get_contents_with_new_backlinks(
-						&note.parser.backlinks_heading,
-						&new_section,
-					)
+					note.get_contents_with_new_backlinks(heading, &new_section)?
 				} else {
 					// Remove backlinks
-					note.get_contents_without_backlinks()
+					note.get_contents_without_backlinks()?
 				};
-				if let Err(e) = NoteFile::save(&note.file.path, &new_contents) {
-					eprintln!("Error while saving note file {}: {}", note.file.path, e);
-				} else {
-					notes.push(note.into_meta());
+				self.save_note(note, &new_contents)?;
+				Ok(true)
+			});
+			match saved {
+				Ok(true) => notes.push(note.to_meta()),
+				Ok(false) => {}
+				Err(err) => self.report_io_error(note, err),
+			}
+		}
+		notes
+	}
+
+	/// Get notes where `update_backlinks` would change the backlinks section,
+	/// with the line number of the current section, if any
+	pub fn get_stale_backlinks(&self, aliased: bool) -> Vec<(NoteMeta, Option<usize>)> {
+		let mut notes = Vec::new();
+		for note in &self.get_sorted_notes() {
+			let new_section = self.get_new_backlinks_section(note, aliased);
+			let heading = &self.parser.backlinks_heading;
+			let current_section = match note.get_backlinks_section_without_heading(heading) {
+				Ok(section) => section.unwrap_or_default(),
+				Err(err) => {
+					self.report_io_error(note, err);
+					continue;
 				}
+			};
+
+			if current_section != new_section {
+				// The contents have been read if there is a section
+				let line = note.backlinks_start.and_then(|start| {
+					let content = note.file.content().ok()?;
+					Some(mdparse::line_number(content, start))
+				});
+				notes.push((note.to_meta(), line));
 			}
 		}
 		notes
 	}
 
+	/// Get IDs that are used by more than one note, sorted by ID
+	pub fn get_duplicate_ids(&self) -> Vec<(String, Vec<NoteMeta>)> {
+		let mut ids: HashMap<&str, Vec<NoteMeta>> = HashMap::new();
+		let notes = self.get_sorted_notes();
+		for note in &notes {
+			if let Some(id) = &note.id {
+				ids.entry(id).or_default().push(note.to_meta());
+			}
+		}
+
+		let mut duplicates: Vec<(String, Vec<NoteMeta>)> = ids
+			.into_iter()
+			.filter(|(_, notes)| notes.len() > 1)
+			.map(|(id, notes)| (id.to_owned(), notes))
+			.collect();
+		duplicates.sort_by(|a, b| a.0.cmp(&b.0));
+		duplicates
+	}
+
+	/// Get notes whose file names don't match their ID and title, with the
+	/// file name (without extension) that they should have
 	pub fn get_mismatched_filenames(&self) -> Vec<(NoteMeta, String)> {
 		let mut fs = Vec::new();
 		for note in &self.get_sorted_notes() {
@@ -622,52 +1510,112 @@ impl NoteCollection {
 				NoteFile::clean_filename(&note.title)
 			};
 			if note.file.stem.to_lowercase() != new_filename.to_lowercase() {
-				fs.push((note.into_meta(), new_filename));
+				fs.push((note.to_meta(), new_filename));
 			}
 		}
 		fs
 	}
 
-	pub fn rename_note(&self, note_meta: &NoteMeta, new_stem: &str) -> io::Result<Vec<NoteMeta>> {
-		let note = &self.notes[&WikiLink::FileName(note_meta.stem.to_string())];
+	/// Rename a note file, and update the links to it in other notes. Get
+	/// the notes that were changed.
+	pub fn rename_note(&mut self, note_meta: &NoteMeta, new_stem: &str) -> Result<Vec<NoteMeta>, Error> {
+		// Notes in different directories can have the same file name
+		let old_file_name = WikiLink::FileName(note_meta.stem.to_string());
+		let index = match self.lookup.get(&WikiLink::Path(note_meta.path.to_string())) {
+			Some(&index) => index,
+			None => {
+				return Err(Error::UnknownNote {
+					stem: note_meta.stem.to_string(),
+				})
+			}
+		};
+		// Filename links only point to this note if it's the first one by
+		// path with the file name
+		let has_file_name_links = self.lookup.get(&old_file_name) == Some(&index);
+
+		// Never replace another file, but allow changing the case of the name
+		let old_path = path::Path::new(&note_meta.path);
+		let new_path = old_path.with_file_name(format!("{}.{}", new_stem, note_meta.extension));
+		let same_name = note_meta.stem.to_lowercase() == new_stem.to_lowercase();
+		if new_path.exists() && !same_name {
+			return Err(Error::RenameConflict {
+				from: old_path.to_path_buf(),
+				to: new_path,
+			});
+		}
 
 		// Rename note file and replace NoteFile object in Note
-		let new_note_file = note.borrow().file.rename(&new_stem)?;
-		note.borrow_mut().set_file(new_note_file);
-
-		Ok(self.update_filename_backlinks_to(&note_meta.stem, &new_stem)?)
-	}
+		let new_note_file = self
+			.rename_note_file(&self.notes[index].file, new_stem)
+			.map_err(|err| Error::io(old_path, err))?;
+		let new_file_name = format!("{}.{}", new_stem, new_note_file.extension);
+		self.notes[index].set_file(new_note_file);
+
+		// Look up the note by its new file name and path
+		let old_keys = [old_file_name.clone(), WikiLink::Path(note_meta.path.to_string())];
+		for key in &old_keys {
+			if self.lookup.get(key) == Some(&index) {
+				self.lookup.remove(key);
+			}
+		}
+		let new_keys = [self.notes[index].get_filename_link(), self.notes[index].get_path_link()];
+		for key in new_keys {
+			self.lookup.entry(key).or_insert(index);
+		}
 
-	fn update_filename_backlinks_to(
-		&self,
-		old_file_stem: &str,
-		new_file_stem: &str,
-	) -> io::Result<Vec<NoteMeta>> {
 		let mut updated_notes = Vec::new();
-		let old_filename_id = WikiLink::FileName(old_file_stem.to_owned());
-		if self.backlinks.contains_key(&old_filename_id) {
+		if has_file_name_links {
 			// Use Regex to make case-insensitive search and replace
-			let search =
-				literal_to_ci_regex(&Note::get_wikilink(&None, &EMPTY_STRING, &old_file_stem))
-					.unwrap();
-			let new_link = Note::get_wikilink(&None, &EMPTY_STRING, &new_file_stem);
-
-			for backlink in self.backlinks[&old_filename_id].iter() {
-				{
-					let new_contents = &regex_literal_search_replace(
-						&backlink.borrow().file.content,
-						&search,
-						&new_link,
-					)
-					.to_string();
-
-					if &backlink.borrow().file.content != new_contents {
-						let new_note_file = backlink.borrow().file.replace_contents(new_contents);
-						backlink.borrow_mut().set_file(new_note_file);
-						updated_notes.push(backlink.borrow().into_meta());
-					}
+			let search = filename_link_to_ci_regex(&note_meta.stem).unwrap();
+			updated_notes = self.update_links_to(&old_file_name, |linking_note| {
+				Ok(replace_filename_links(linking_note.file.content()?, &search, new_stem).to_string())
+			})?;
+
+			// Another note with the old file name gets the links that are left
+			if let Some(other) = self.notes.iter().position(|note| note.get_filename_link() == old_file_name) {
+				self.lookup.entry(old_file_name).or_insert(other);
+			}
+		}
+
+		let old_path = WikiLink::Path(note_meta.path.to_string());
+		let updated_path_notes = self.update_links_to(&old_path, |linking_note| {
+			let contents = mdparse::replace_markdown_links(linking_note.file.content()?, |href| {
+				let href_path = mdparse::get_relative_href(href)?;
+				let target = WikiLink::Path(resolve_link_path(&linking_note.file.path, &href_path));
+				if target == old_path {
+					Some(mdparse::replace_href_file_name(href, &new_file_name))
+				} else {
+					None
 				}
-				backlink.borrow().save()?;
+			});
+			Ok(contents.to_string())
+		})?;
+		for updated_note in updated_path_notes {
+			if !updated_notes.contains(&updated_note) {
+				updated_notes.push(updated_note);
+			}
+		}
+
+		Ok(updated_notes)
+	}
+
+	/// Replace the contents of all notes that link to `target` with the
+	/// result of `replace`, and save the notes that were changed
+	fn update_links_to<F>(&mut self, target: &WikiLink, replace: F) -> Result<Vec<NoteMeta>, Error>
+	where
+		F: Fn(&Note) -> io::Result<String>,
+	{
+		let mut updated_notes = Vec::new();
+		let linking_notes = self.backlinks.get(target).cloned().unwrap_or_default();
+		for index in linking_notes {
+			let linking_note = &self.notes[index];
+			let io_error = |err| Error::io(path::Path::new(&linking_note.file.path), err);
+			let new_contents = replace(linking_note).map_err(io_error)?;
+			if linking_note.file.content().map_err(io_error)? != new_contents {
+				self.save_note(linking_note, &new_contents).map_err(io_error)?;
+				let new_note_file = linking_note.file.replace_contents(&new_contents).map_err(io_error)?;
+				self.notes[index].set_file(new_note_file);
+				updated_notes.push(self.notes[index].to_meta());
 			}
 		}
 
@@ -675,16 +1623,154 @@ impl NoteCollection {
 	}
 }
 
-fn literal_to_ci_regex(search: &str) -> Result<Regex, Error> {
-	Regex::new(&format!("(?i){}", &regex::escape(&search)))
+/// A note that has been read and parsed, with the data to put in the cache
+/// if it wasn't found there
+struct LoadedNote {
+	note: Note,
+	parsed: Option<(FileStamp, NoteData)>,
+}
+
+/// Read and parse a note, or take the parsed note from the cache if the file
+/// hasn't changed
+fn load_note(
+	path: &path::Path,
+	parser: &NoteParser,
+	cache: Option<&ParseCache>,
+) -> io::Result<LoadedNote> {
+	let cache = match cache {
+		Some(cache) => cache,
+		None => {
+			let note = Note::new(NoteFile::new(path)?, parser);
+			return Ok(LoadedNote { note, parsed: None });
+		}
+	};
+
+	let stamp = FileStamp::of(path)?;
+	if let Some(data) = cache.get(&path.to_string_lossy(), stamp) {
+		let note = Note::with_data(NoteFile::unread(path, stamp), data, parser);
+		return Ok(LoadedNote { note, parsed: None });
+	}
+	let note_file = NoteFile::new(path)?;
+	let data = parser.parse_file(note_file.content()?, &note_file.extension);
+	let note = Note::with_data(note_file, data.clone(), parser);
+	Ok(LoadedNote {
+		note,
+		parsed: Some((stamp, data)),
+	})
+}
+
+/// Lookup key for an attachment link. Paths are used as they are, but for
+/// wikilinks only the file name is used, since attachments can be anywhere.
+fn get_attachment_key(target: &WikiLink) -> WikiLink {
+	match target {
+		WikiLink::FileName(name) => {
+			WikiLink::FileName(name.rsplit('/').next().unwrap_or_default().to_string())
+		}
+		target => target.clone(),
+	}
+}
+
+/// Links to existing notes are never attachment links. Links to unknown
+/// files are, if they look like file names with another extension than notes.
+/// Tell links to notes and attachments apart, and leave out Markdown links
+/// unless they are followed
+fn split_links(
+	note: &mut Note,
+	lookup: &HashMap<WikiLink, NoteIndex>,
+	attachments: &HashMap<WikiLink, path::PathBuf>,
+	extensions: &[String],
+	markdown_links: bool,
+) {
+	let mut all_links: Vec<Link> = mem::take(&mut note.links);
+	all_links.append(&mut note.attachment_links);
+	all_links.sort_by_key(|link| link.line);
+
+	let (mut links, attachment_links): (Vec<Link>, Vec<Link>) = all_links
+		.into_iter()
+		.partition(|link| !is_attachment_link(link, lookup, attachments, extensions));
+	if !markdown_links {
+		links.retain(|link| !matches!(link.target, WikiLink::Path(_)));
+	}
+	note.links = links;
+	note.attachment_links = attachment_links;
+}
+
+fn is_attachment_link(
+	link: &Link,
+	notes: &HashMap<WikiLink, NoteIndex>,
+	attachments: &HashMap<WikiLink, path::PathBuf>,
+	note_extensions: &[String],
+) -> bool {
+	if notes.contains_key(&link.target) {
+		return false;
+	}
+	let key = get_attachment_key(&link.target);
+	if attachments.contains_key(&key) {
+		return true;
+	}
+	match &key {
+		WikiLink::Id(_) => false,
+		WikiLink::FileName(name) | WikiLink::Path(name) => has_file_extension(name, note_extensions),
+	}
+}
+
+/// Check if the name ends with a known attachment file extension, other
+/// than `note_extensions`, like "image.png", but not "Node.js" or "Dr. No"
+fn has_file_extension(name: &str, note_extensions: &[String]) -> bool {
+	match path::Path::new(name).extension().and_then(|ext| ext.to_str()) {
+		Some(ext) => {
+			ATTACHMENT_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e))
+				&& !note_extensions.iter().any(|e| ext.eq_ignore_ascii_case(e))
+		}
+		None => false,
+	}
+}
+
+/// Make the target of a link found in `file` independent of the file
+fn resolve_link(file: &NoteFile, link: &mut Link) {
+	if let WikiLink::Path(href) = &link.target {
+		// Markdown links are relative to the note's directory
+		link.target = WikiLink::Path(resolve_link_path(&file.path, href));
+	} else if link.anchor.is_some() && link.target == WikiLink::FileName(String::new()) {
+		// Links like "[[#Heading]]" point to a section in the note itself
+		link.target = WikiLink::FileName(file.stem.clone());
+	}
+}
+
+/// Resolve a relative link target against the directory of the linking note,
+/// without touching the file system, since the target might not exist
+fn resolve_link_path(note_path: &str, href: &str) -> String {
+	let dir = path::Path::new(note_path)
+		.parent()
+		.unwrap_or_else(|| path::Path::new(""));
+	let mut resolved = path::PathBuf::new();
+	for component in dir.join(href).components() {
+		match component {
+			path::Component::CurDir => {}
+			path::Component::ParentDir => {
+				resolved.pop();
+			}
+			c => resolved.push(c.as_os_str()),
+		}
+	}
+	resolved.to_string_lossy().to_string()
 }
 
-fn regex_literal_search_replace<'a>(
-	text: &'a str,
-	search: &Regex,
-	replace: &'a str,
-) -> Cow<'a, str> {
-	search.replace_all(&text, |_: &regex::Captures| &replace)
+/// Case-insensitive regex for filename links to `file_stem`, that captures
+/// a possible anchor and label ("#anchor|label") so that they can be kept
+/// when replacing the link
+fn filename_link_to_ci_regex(file_stem: &str) -> Result<Regex, regex::Error> {
+	Regex::new(&format!(
+		r"(?i)\[\[{}((?:#|\\?\|).*?)?\]\]",
+		regex::escape(file_stem)
+	))
+}
+
+fn replace_filename_links<'a>(text: &'a str, search: &Regex, new_file_stem: &str) -> Cow<'a, str> {
+	search.replace_all(text, |capture: &regex::Captures| {
+		let label = capture.get(1).map_or("", |m| m.as_str());
+		format!("[[{}{}]]", new_file_stem, label)
+	})
 }
 
 #[cfg(test)]
@@ -697,10 +1783,10 @@ mod tests {
 
 	#[test]
 	fn title_and_id_parser() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/File Name Title.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
 		assert_eq!(note.file.stem, "File Name Title");
@@ -710,10 +1796,10 @@ mod tests {
 
 	#[test]
 	fn yaml1_title_parser() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/yaml1.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
 		assert_eq!(note.title, "Plain YAML title");
@@ -722,10 +1808,10 @@ mod tests {
 
 	#[test]
 	fn yaml2_title_parser() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/yaml2.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
 		assert_eq!(note.title, "Plein: YAML title");
@@ -734,10 +1820,10 @@ mod tests {
 
 	#[test]
 	fn yaml3_title_parser() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/yaml3.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
 		assert_eq!(note.title, "Das Title");
@@ -746,10 +1832,10 @@ mod tests {
 
 	#[test]
 	fn empty_file_parser() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/Empty File With Name.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
 		assert_eq!(note.file.stem, "Empty File With Name");
@@ -759,10 +1845,10 @@ mod tests {
 
 	#[test]
 	fn title_parser() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/12345678901 Test Note 1.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
 		assert_eq!(note.title, "Test Note 1");
@@ -770,10 +1856,10 @@ mod tests {
 
 	#[test]
 	fn oneliner_parser() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/One-liner.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
 		assert_eq!(note.title, "Just a Heading");
@@ -781,21 +1867,22 @@ mod tests {
 
 	#[test]
 	fn task_parser() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/Tasks.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
-		assert!(note.tasks.contains(&"Don't forget to remember".to_string()));
-		assert!(note.tasks.contains(&"Buy milk!".to_string()));
-		assert!(note.tasks.contains(&"Nested".to_string()));
-		assert!(note.tasks.contains(&"Tabbed with [[link]]".to_string()));
-		assert!(note.tasks.contains(&"Final line".to_string()));
-		assert_eq!(note.tasks.len(), 5);
+		let tasks: Vec<&str> = note.tasks.iter().map(|t| t.text.as_str()).collect();
+		assert!(tasks.contains(&"Don't forget to remember"));
+		assert!(tasks.contains(&"Buy milk!"));
+		assert!(tasks.contains(&"Nested"));
+		assert!(tasks.contains(&"Tabbed with [[link]]"));
+		assert!(tasks.contains(&"Final line"));
+		assert_eq!(tasks.len(), 5);
 
 		assert!(note
-			.links
+			.get_link_targets()
 			.contains(&WikiLink::FileName(String::from("link"))));
 	}
 
@@ -834,10 +1921,10 @@ mod tests {
 
 	#[test]
 	fn backlinks() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/BackLinks.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
 		// All links in this file is in the backlinks section
@@ -846,46 +1933,46 @@ mod tests {
 
 	#[test]
 	fn replace_backlinks() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/BackLinks.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
-		let c1 = note.get_contents_without_backlinks();
+		let c1 = note.get_contents_without_backlinks().unwrap();
 		assert_eq!(
 			c1,
 			"# Backlinks test case\r\n\r\nSome note text\r\n\r\n<!-- Here be dragons -->\r\n"
 		);
 
-		let c2 = note.get_backlinks_section_without_heading().unwrap();
+		let c2 = note.get_backlinks_section_without_heading(&parser.backlinks_heading).unwrap().unwrap();
 		assert_eq!(
 			c2.trim(),
-			"- [[§An outline note]]\r\n- [[20201012145848]] Another note\r\n* Not a link"
+			"- [[§An outline note]]\n- [[20201012145848]] Another note\n* Not a link"
 		);
 
 		let c3 =
-			note.get_contents_with_new_backlinks("## Links to this note", "- [[The one and only]]");
-		assert_eq!(c3, "# Backlinks test case\r\n\r\nSome note text\n\n## Links to this note\n\n- [[The one and only]]\n\n<!-- Here be dragons -->");
+			note.get_contents_with_new_backlinks("## Links to this note", "- [[The one and only]]").unwrap();
+		assert_eq!(c3, "# Backlinks test case\r\n\r\nSome note text\r\n\r\n## Links to this note\r\n\r\n- [[The one and only]]\r\n\r\n<!-- Here be dragons -->\r\n");
 	}
 
 	#[test]
 	fn add_backlinks1() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/One-liner.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
-		let c1 = note.get_contents_without_backlinks();
+		let c1 = note.get_contents_without_backlinks().unwrap();
 		assert_eq!(c1, "# Just a Heading");
 
-		assert!(note.get_backlinks_section_without_heading().is_none());
+		assert!(note.get_backlinks_section_without_heading(&parser.backlinks_heading).unwrap().is_none());
 
 		let c3 = note.get_contents_with_new_backlinks(
 			"## Links to this note",
 			"- [[Link one]]\n- [[Link two]]",
-		);
+		).unwrap();
 		assert_eq!(
 			c3,
 			"# Just a Heading\n\n## Links to this note\n\n- [[Link one]]\n- [[Link two]]"
@@ -894,21 +1981,102 @@ mod tests {
 
 	#[test]
 	fn add_backlinks2() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/12345678901 Test Note 1.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
-		assert!(note.get_backlinks_section_without_heading().is_none());
+		assert!(note.get_backlinks_section_without_heading(&parser.backlinks_heading).unwrap().is_none());
 
 		let c3 = note.get_contents_with_new_backlinks(
 			"## Links to this note",
 			"- [[Link one]]\n- [[Link two]]",
-		);
+		).unwrap();
 		assert_eq!(
 			c3,
-			"This is the ID: 112233445566\n\n## Links to this note\n\n- [[Link one]]\n- [[Link two]]"
+			"This is the ID: 112233445566\n\n## Links to this note\n\n- [[Link one]]\n- [[Link two]]\n"
+		);
+	}
+
+	#[test]
+	fn anchors() {
+		let parser = get_default_parser();
+		let note = Note::new(
+			NoteFile::new(&path::PathBuf::from(r"testdata/Anchors.md")).unwrap(),
+			&parser,
+		);
+
+		assert!(note.has_anchor(&Anchor::Heading("second LEVEL".to_owned())));
+		assert!(note.has_anchor(&Anchor::Heading("Anchors#Third level #3".to_owned())));
+		assert!(note.has_anchor(&Anchor::Block("item2".to_owned())));
+		assert!(!note.has_anchor(&Anchor::Heading("Not a heading ^not-a-block".to_owned())));
+		assert!(!note.has_anchor(&Anchor::Block("not-a-block".to_owned())));
+		assert!(!note.has_anchor(&Anchor::Block("either".to_owned())));
+	}
+
+	#[test]
+	fn file_extensions() {
+		let md = ["md".to_owned()];
+		assert!(has_file_extension("image.png", &md));
+		assert!(has_file_extension("dir/Document.PDF", &md));
+		assert!(has_file_extension("song.mp3", &md));
+		assert!(!has_file_extension("note.MD", &md));
+		assert!(!has_file_extension("Version 1.2", &md));
+		assert!(!has_file_extension("Dr. No", &md));
+		assert!(!has_file_extension("No extension", &md));
+		assert!(!has_file_extension("archive.backup", &md));
+		assert!(!has_file_extension("Node.js", &md));
+		assert!(!has_file_extension("ASP.NET", &md));
+		assert!(!has_file_extension("Vue.app", &md));
+		assert!(!has_file_extension("notes.txt", &["md".to_owned(), "txt".to_owned()]));
+	}
+
+	#[test]
+	fn resolve_link_paths() {
+		assert_eq!(
+			resolve_link_path("/notes/dir/note.md", "other.md"),
+			path::Path::new("/notes/dir/other.md").to_string_lossy()
+		);
+		assert_eq!(
+			resolve_link_path("/notes/dir/note.md", "./../sub/./other.md"),
+			path::Path::new("/notes/sub/other.md").to_string_lossy()
+		);
+		assert_eq!(
+			resolve_link_path("/notes/dir/note.md", "../../../other.md"),
+			path::Path::new("/other.md").to_string_lossy()
+		);
+	}
+
+	#[test]
+	fn aliased_wikilinks() {
+		assert_eq!(
+			Note::get_aliased_wikilink(&Some("20210101120000".to_owned()), "My note", "x"),
+			"[[20210101120000|My note]]"
+		);
+		assert_eq!(
+			Note::get_aliased_wikilink(&None, "My Note", "my note"),
+			"[[my note]]"
+		);
+		assert_eq!(
+			Note::get_aliased_wikilink(&None, "Either | or", "Either or"),
+			"[[Either or]]"
+		);
+	}
+
+	#[test]
+	fn replace_filename_links_keeps_labels() {
+		let search = filename_link_to_ci_regex("Old (name)").unwrap();
+		let text = "[[old (NAME)]], [[Old (name)|a label]], [[Old (name)\\|cell]] and [[Old (name) 2]]";
+		assert_eq!(
+			replace_filename_links(text, &search, "New name"),
+			"[[New name]], [[New name|a label]], [[New name\\|cell]] and [[Old (name) 2]]"
+		);
+
+		let text = "[[Old (name)#Heading]] and [[Old (name)#^block|label]]";
+		assert_eq!(
+			replace_filename_links(text, &search, "New name"),
+			"[[New name#Heading]] and [[New name#^block|label]]"
 		);
 	}
 
@@ -948,33 +2116,173 @@ mod tests {
 
 	#[test]
 	fn file_encodings_utf8_bom() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/BOM.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
-		assert_eq!(note.file.content.chars().next().unwrap(), '\u{feff}');
+		assert_eq!(note.file.content().unwrap().chars().next().unwrap(), '\u{feff}');
 	}
 
 	#[test]
 	fn file_encodings_win1252() {
-		match NoteFile::new(&path::PathBuf::from(r"testdata/Win-1252.md")) {
-			Ok(_) => panic!("Shouldn't be able to read Win-1252 file"),
-			Err(_) => (),
-		};
+		let file = NoteFile::new(&path::PathBuf::from(r"testdata/Win-1252.md")).unwrap();
+		assert!(file.content().unwrap().starts_with("Yxmördaren"));
+		assert_eq!(file.encoding().unwrap().to_string(), "windows-1252");
 	}
 
 	#[test]
 	fn file_without_title() {
-		let parser = Rc::new(get_default_parser());
+		let parser = get_default_parser();
 		let note = Note::new(
 			NoteFile::new(&path::PathBuf::from(r"testdata/20201010101010.md")).unwrap(),
-			Rc::clone(&parser),
+			&parser,
 		);
 
 		assert_eq!(note.title, "".to_owned());
 		assert_eq!(note.id, Some("20201010101010".to_owned()));
 		assert_eq!(note.file.stem, "20201010101010".to_owned());
 	}
+
+	#[test]
+	fn documents_in_memory() {
+		let documents = vec![
+			(path::PathBuf::from("/notes/a.md"), "# A\n\n[[B]] ![[image.png]] [[c]]".to_owned()),
+			(path::PathBuf::from("/notes/b.md"), "# B\n\n[[a]] [[20201010101010]]".to_owned()),
+			(path::PathBuf::from("/notes/20201010101010 c.md"), "# C".to_owned()),
+		];
+		let mut notes = NoteCollection::from_documents(documents, get_default_parser(), "md");
+		assert_eq!(notes.count(), 3);
+
+		let a = path::Path::new("/notes/a.md");
+		let c = notes.get_note_by_id("20201010101010").unwrap();
+		assert_eq!(c.title, "C");
+		assert_eq!(notes.get_note_by_filename("A").unwrap().path, "/notes/a.md");
+		assert!(notes.get_note_by_filename("c").is_none());
+
+		let links = notes.get_links(a);
+		let targets: Vec<&str> = links.iter().map(|l| l.target.value()).collect();
+		assert_eq!(targets, ["B", "c"]);
+		assert_eq!(notes.get_attachment_links(a)[0].target.value(), "image.png");
+		let backlinks: Vec<String> = notes.get_backlinks(a).into_iter().map(|n| n.stem).collect();
+		assert_eq!(backlinks, ["b"]);
+		assert_eq!(notes.get_backlinks(c.path.as_ref()).len(), 1);
+
+		// Nothing is written to disk
+		let b = notes.get_note_by_filename("b").unwrap();
+		assert_eq!(notes.rename_note(&b, "Bee").unwrap().len(), 1);
+		assert_eq!(notes.take_planned_changes().len(), 2);
+		assert!(notes.rename_note(&b, "Bee").is_err());
+	}
+
+	#[test]
+	fn set_note_contents() {
+		let documents = |a: &str| {
+			vec![
+				(path::PathBuf::from("/notes/a.md"), a.to_owned()),
+				(path::PathBuf::from("/notes/b.md"), "# B\n\n[[c]]".to_owned()),
+				(path::PathBuf::from("/notes/c.md"), "# C\n\n[[a]] ![[image.png]]".to_owned()),
+			]
+		};
+		let backlinks = |notes: &NoteCollection| -> Vec<Vec<String>> {
+			["a", "b", "c", "x"]
+				.iter()
+				.map(|stem| {
+					let path = path::PathBuf::from(format!("/notes/{}.md", stem));
+					notes.get_backlinks(&path).into_iter().map(|n| n.stem).collect()
+				})
+				.collect()
+		};
+
+		let mut notes = NoteCollection::from_documents(documents("# A\n\n[[b]] [[c]]"), get_default_parser(), "md");
+		let a = path::Path::new("/notes/a.md");
+		for contents in ["# A\n\n[[c]] [[a]] ![[image.png]]", "# A 20201010101010\n\n[[b]]", "# A"] {
+			notes.set_note_contents(a, contents.to_owned());
+			let reindexed = NoteCollection::from_documents(documents(contents), get_default_parser(), "md");
+			assert_eq!(backlinks(&notes), backlinks(&reindexed));
+			assert_eq!(notes.get_links(a), reindexed.get_links(a));
+			assert_eq!(notes.get_attachment_links(a), reindexed.get_attachment_links(a));
+			assert_eq!(notes.get_note_by_id("20201010101010"), reindexed.get_note_by_id("20201010101010"));
+		}
+
+		// A new note is added
+		notes.set_note_contents(path::Path::new("/notes/x.md"), "# X\n\n[[a]]".to_owned());
+		assert_eq!(notes.count(), 4);
+		assert_eq!(backlinks(&notes)[0], ["c", "x"]);
+	}
+
+	#[test]
+	fn rename_note_with_same_file_name() {
+		let documents = vec![
+			(path::PathBuf::from("/notes/a/same.md"), "# Same A".to_owned()),
+			(path::PathBuf::from("/notes/b/same.md"), "# Same B".to_owned()),
+			(path::PathBuf::from("/notes/c.md"), "# C\n\n[[same]]".to_owned()),
+			(path::PathBuf::from("/notes/d/same.md"), "# Same D".to_owned()),
+		];
+		let mut notes = NoteCollection::from_documents(documents, get_default_parser(), "md");
+
+		// Filename links point to the first note by path
+		let b = notes.get_note(path::Path::new("/notes/b/same.md")).unwrap();
+		assert!(notes.rename_note(&b, "other").unwrap().is_empty());
+		assert_eq!(
+			notes.take_planned_changes(),
+			[Change::Rename {
+				from: "/notes/b/same.md".to_owned(),
+				to: "/notes/b/other.md".to_owned(),
+			}]
+		);
+		assert_eq!(notes.get_note_by_filename("same").unwrap().path, "/notes/a/same.md");
+
+		let a = notes.get_note(path::Path::new("/notes/a/same.md")).unwrap();
+		assert_eq!(notes.rename_note(&a, "first").unwrap()[0].stem, "c");
+		assert_eq!(notes.get_note_by_filename("same").unwrap().path, "/notes/d/same.md");
+	}
+
+	#[test]
+	fn links_to_self() {
+		let documents = vec![
+			(path::PathBuf::from("/notes/a.md"), "# A\n\nSee [[#Part]] and [[b]]\n\n## Part".to_owned()),
+			(path::PathBuf::from("/notes/b.md"), "# B\n\nSee [[#^end]] and [[b]]\n\nEnd ^end".to_owned()),
+			(path::PathBuf::from("/notes/c.md"), "# C\n\nSee [[#Intro]]\n\n## Intro".to_owned()),
+		];
+		let notes = NoteCollection::from_documents(documents, get_default_parser(), "md");
+		let stems = |notes: Vec<NoteMeta>| -> Vec<String> { notes.into_iter().map(|n| n.stem).collect() };
+		assert_eq!(stems(notes.get_sources()), ["a"]);
+		assert_eq!(stems(notes.get_sinks()), ["b"]);
+		assert_eq!(stems(notes.get_isolated()), ["c"]);
+		assert!(notes.get_note_by_filename("a").unwrap().has_links);
+		assert!(!notes.get_note_by_filename("c").unwrap().has_links);
+		assert!(notes.get_broken_anchors().is_empty());
+		assert_eq!(notes.get_graph().edges().count(), 1);
+	}
+
+	#[test]
+	fn link_graph() {
+		let documents = vec![
+			(path::PathBuf::from("/notes/a.md"), "# Alpha\n\n[[b]] [[B]] [[a]] [[x]]\n\n**Links to this note**\n\n- [[c]]".to_owned()),
+			(path::PathBuf::from("/notes/b.md"), "# B\n\n[[20201010101010]] [[x]]".to_owned()),
+			(path::PathBuf::from("/notes/20201010101010 c.md"), "# C\n\n[[a]] [[Unknown]]".to_owned()),
+		];
+		let notes = NoteCollection::from_documents(documents, get_default_parser(), "md");
+		let graph = notes.get_graph();
+		let labels: Vec<&str> = graph.nodes().iter().map(|n| n.label()).collect();
+		assert_eq!(labels, ["C", "Alpha", "B", "Unknown", "x"]);
+		let edges: Vec<(usize, usize)> = graph.edges().collect();
+		assert_eq!(edges, [(0, 1), (0, 3), (1, 2), (1, 4), (2, 0), (2, 4)]);
+
+		assert_eq!(notes.find_note("20201010101010").unwrap().stem, "20201010101010 c");
+		assert_eq!(notes.find_note("B").unwrap().stem, "b");
+		assert_eq!(notes.find_note("alpha").unwrap().stem, "a");
+		assert_eq!(notes.find_note("/notes/a.md").unwrap().title, "Alpha");
+		assert!(notes.find_note("Unknown").is_none());
+
+		// The broken links are left out, so all notes are in one cycle
+		let components = notes.get_components(true);
+		let titles: Vec<Vec<&str>> = components
+			.iter()
+			.map(|notes| notes.iter().map(|n| n.title.as_str()).collect())
+			.collect();
+		assert_eq!(titles, [["Alpha", "B", "C"]]);
+	}
 }
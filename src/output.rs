@@ -0,0 +1,371 @@
+use crate::note::{Anchor, Link, NoteMeta, Task};
+use crate::rank::RankedNote;
+use serde::Serialize;
+use std::error::Error;
+use std::{io, path, str};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+	Markdown,
+	Json,
+	Csv,
+}
+
+impl str::FromStr for OutputFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"markdown" | "md" => Ok(OutputFormat::Markdown),
+			"json" => Ok(OutputFormat::Json),
+			"csv" => Ok(OutputFormat::Csv),
+			_ => Err(format!("Unknown output format \"{}\"", s)),
+		}
+	}
+}
+
+/// A note in a list of notes
+#[derive(Serialize, Default)]
+pub struct NoteRecord {
+	pub path: String,
+	pub stem: String,
+	pub id: Option<String>,
+	pub title: String,
+}
+
+impl From<&NoteMeta> for NoteRecord {
+	fn from(note: &NoteMeta) -> Self {
+		NoteRecord {
+			path: note.path.clone(),
+			stem: note.stem.clone(),
+			id: note.id.clone(),
+			title: note.title.clone(),
+		}
+	}
+}
+
+/// A link, with the note that it's found in
+#[derive(Serialize, Default)]
+pub struct LinkRecord {
+	pub path: String,
+	pub stem: String,
+	pub id: Option<String>,
+	pub title: String,
+	pub line: usize,
+	/// What the link points to: "note", "attachment", "heading" or "block"
+	pub kind: &'static str,
+	/// The link as written in the note
+	pub link: String,
+	pub target: String,
+	pub anchor: Option<String>,
+}
+
+impl LinkRecord {
+	pub fn new(note: &NoteMeta, link: &Link, kind: &'static str) -> Self {
+		LinkRecord {
+			path: note.path.clone(),
+			stem: note.stem.clone(),
+			id: note.id.clone(),
+			title: note.title.clone(),
+			line: link.line,
+			kind,
+			link: link.to_string(),
+			target: link.target.value().to_owned(),
+			anchor: link.anchor.as_ref().map(|anchor| match anchor {
+				Anchor::Heading(heading) => heading.clone(),
+				Anchor::Block(block_id) => block_id.clone(),
+			}),
+		}
+	}
+
+	/// What kind of section an anchor link points to
+	pub fn anchor_kind(link: &Link) -> &'static str {
+		match link.anchor {
+			Some(Anchor::Block(_)) => "block",
+			_ => "heading",
+		}
+	}
+}
+
+/// A task, with the note that it's found in
+#[derive(Serialize, Default)]
+pub struct TaskRecord {
+	pub path: String,
+	pub stem: String,
+	pub id: Option<String>,
+	pub title: String,
+	pub line: usize,
+	pub task: String,
+}
+
+impl TaskRecord {
+	pub fn new(note: &NoteMeta, task: &Task) -> Self {
+		TaskRecord {
+			path: note.path.clone(),
+			stem: note.stem.clone(),
+			id: note.id.clone(),
+			title: note.title.clone(),
+			line: task.line,
+			task: task.text.clone(),
+		}
+	}
+}
+
+/// A file that is not a note
+#[derive(Serialize, Default)]
+pub struct AttachmentRecord {
+	pub path: String,
+}
+
+impl From<&path::PathBuf> for AttachmentRecord {
+	fn from(path: &path::PathBuf) -> Self {
+		AttachmentRecord {
+			path: path.to_string_lossy().to_string(),
+		}
+	}
+}
+
+/// A note in a connected component
+#[derive(Serialize, Default)]
+pub struct ComponentRecord {
+	/// Number of the component, where the largest is 1
+	pub component: usize,
+	pub size: usize,
+	pub path: String,
+	pub stem: String,
+	pub id: Option<String>,
+	pub title: String,
+}
+
+impl ComponentRecord {
+	pub fn new(component: usize, size: usize, note: &NoteMeta) -> Self {
+		ComponentRecord {
+			component,
+			size,
+			path: note.path.clone(),
+			stem: note.stem.clone(),
+			id: note.id.clone(),
+			title: note.title.clone(),
+		}
+	}
+}
+
+/// A note near another note
+#[derive(Serialize, Default)]
+pub struct NeighbourRecord {
+	/// Number of links from the other note
+	pub distance: usize,
+	pub path: String,
+	pub stem: String,
+	pub id: Option<String>,
+	pub title: String,
+}
+
+impl NeighbourRecord {
+	pub fn new(distance: usize, note: &NoteMeta) -> Self {
+		NeighbourRecord {
+			distance,
+			path: note.path.clone(),
+			stem: note.stem.clone(),
+			id: note.id.clone(),
+			title: note.title.clone(),
+		}
+	}
+}
+
+/// A note in a path between two notes
+#[derive(Serialize, Default)]
+pub struct PathRecord {
+	/// Number of the path, where the shortest is 1
+	pub route: usize,
+	/// Number of links from the first note
+	pub step: usize,
+	/// How the link to this note was followed: "forward", "backward", or
+	/// empty for the first note
+	pub direction: &'static str,
+	pub path: String,
+	pub stem: String,
+	pub id: Option<String>,
+	pub title: String,
+}
+
+impl PathRecord {
+	pub fn new(route: usize, step: usize, direction: &'static str, note: &NoteMeta) -> Self {
+		PathRecord {
+			route,
+			step,
+			direction,
+			path: note.path.clone(),
+			stem: note.stem.clone(),
+			id: note.id.clone(),
+			title: note.title.clone(),
+		}
+	}
+}
+
+/// A note in the `rank` list, with all metrics
+#[derive(Serialize, Default)]
+pub struct RankRecord {
+	pub rank: usize,
+	pub path: String,
+	pub stem: String,
+	pub id: Option<String>,
+	pub title: String,
+	pub in_degree: usize,
+	pub out_degree: usize,
+	pub pagerank: Option<f64>,
+	pub betweenness: Option<f64>,
+}
+
+impl RankRecord {
+	pub fn new(rank: usize, ranked: &RankedNote) -> Self {
+		RankRecord {
+			rank,
+			path: ranked.note.path.clone(),
+			stem: ranked.note.stem.clone(),
+			id: ranked.note.id.clone(),
+			title: ranked.note.title.clone(),
+			in_degree: ranked.in_degree,
+			out_degree: ranked.out_degree,
+			pagerank: ranked.pagerank,
+			betweenness: ranked.betweenness,
+		}
+	}
+}
+
+#[derive(Serialize, Default)]
+pub struct StatsRecord {
+	pub notes: usize,
+	pub notes_with_id: usize,
+	pub links: usize,
+	pub attachments: usize,
+}
+
+/// Write records to stdout, as a JSON array or as CSV with a header row,
+/// even if there are no records
+pub fn write_records<T: Serialize + Default>(format: OutputFormat, records: &[T]) -> Result<(), Box<dyn Error>> {
+	write_records_to(io::stdout().lock(), format, records)
+}
+
+fn write_records_to<W: io::Write, T: Serialize + Default>(
+	mut writer: W,
+	format: OutputFormat,
+	records: &[T],
+) -> Result<(), Box<dyn Error>> {
+	match format {
+		OutputFormat::Json => {
+			serde_json::to_writer_pretty(&mut writer, records)?;
+			writeln!(writer)?;
+		}
+		OutputFormat::Csv if records.is_empty() => {
+			// The header is written with the first record, so take it from
+			// a record with default values
+			let mut csv_writer = csv::Writer::from_writer(Vec::new());
+			csv_writer.serialize(T::default())?;
+			let csv = csv_writer.into_inner().map_err(|err| err.into_error())?;
+			let header_end = csv.iter().position(|&b| b == b'\n').map_or(csv.len(), |pos| pos + 1);
+			writer.write_all(&csv[..header_end])?;
+		}
+		OutputFormat::Csv => {
+			let mut csv_writer = csv::Writer::from_writer(writer);
+			for record in records {
+				csv_writer.serialize(record)?;
+			}
+			csv_writer.flush()?;
+		}
+		OutputFormat::Markdown => {
+			return Err("Markdown output is written by each subcommand".into());
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::output::*;
+
+	fn note() -> NoteMeta {
+		NoteMeta {
+			path: "/notes/20210101120000 A note.md".to_owned(),
+			stem: "20210101120000 A note".to_owned(),
+			extension: "md".to_owned(),
+			title: "A note".to_owned(),
+			id: Some("20210101120000".to_owned()),
+			has_links: true,
+		}
+	}
+
+	#[test]
+	fn json_records() {
+		let link = Link {
+			target: crate::note::WikiLink::FileName("Other".to_owned()),
+			anchor: Some(Anchor::Block("abc".to_owned())),
+			label: None,
+			embed: false,
+			line: 3,
+		};
+		let records = [LinkRecord::new(&note(), &link, LinkRecord::anchor_kind(&link))];
+
+		let mut out = Vec::new();
+		write_records_to(&mut out, OutputFormat::Json, &records).unwrap();
+		let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+		assert_eq!(
+			value,
+			serde_json::json!([{
+				"path": "/notes/20210101120000 A note.md",
+				"stem": "20210101120000 A note",
+				"id": "20210101120000",
+				"title": "A note",
+				"line": 3,
+				"kind": "block",
+				"link": "[[Other#^abc]]",
+				"target": "Other",
+				"anchor": "abc",
+			}])
+		);
+	}
+
+	#[test]
+	fn csv_records() {
+		let mut note = note();
+		note.id = None;
+		let task = Task {
+			text: "Buy milk, eggs".to_owned(),
+			line: 12,
+		};
+		let records = [TaskRecord::new(&note, &task)];
+
+		let mut out = Vec::new();
+		write_records_to(&mut out, OutputFormat::Csv, &records).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"path,stem,id,title,line,task\n/notes/20210101120000 A note.md,20210101120000 A note,,A note,12,\"Buy milk, eggs\"\n"
+		);
+	}
+
+	#[test]
+	fn empty_csv_records() {
+		let mut out = Vec::new();
+		write_records_to::<_, AttachmentRecord>(&mut out, OutputFormat::Csv, &[]).unwrap();
+		assert_eq!(String::from_utf8(out).unwrap(), "path\n");
+
+		let mut out = Vec::new();
+		write_records_to::<_, RankRecord>(&mut out, OutputFormat::Csv, &[]).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"rank,path,stem,id,title,in_degree,out_degree,pagerank,betweenness\n"
+		);
+
+		let mut out = Vec::new();
+		write_records_to::<_, AttachmentRecord>(&mut out, OutputFormat::Json, &[]).unwrap();
+		assert_eq!(String::from_utf8(out).unwrap(), "[]\n");
+	}
+
+	#[test]
+	fn parse_format() {
+		assert_eq!("json".parse(), Ok(OutputFormat::Json));
+		assert_eq!("CSV".parse(), Ok(OutputFormat::Csv));
+		assert_eq!("markdown".parse(), Ok(OutputFormat::Markdown));
+		assert!("xml".parse::<OutputFormat>().is_err());
+	}
+}
@@ -0,0 +1,164 @@
+use crate::graph::NoteGraph;
+use crate::note::NoteMeta;
+use std::cmp::Ordering;
+use std::{fmt, str};
+
+/// Measures of how central a note is in the link graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+	InDegree,
+	OutDegree,
+	PageRank,
+	Betweenness,
+}
+
+impl Metric {
+	pub const ALL: [Metric; 4] = [
+		Metric::InDegree,
+		Metric::OutDegree,
+		Metric::PageRank,
+		Metric::Betweenness,
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Metric::InDegree => "in-degree",
+			Metric::OutDegree => "out-degree",
+			Metric::PageRank => "pagerank",
+			Metric::Betweenness => "betweenness",
+		}
+	}
+
+	/// Name for headings
+	pub fn title(&self) -> &'static str {
+		match self {
+			Metric::InDegree => "incoming links",
+			Metric::OutDegree => "outgoing links",
+			Metric::PageRank => "PageRank",
+			Metric::Betweenness => "betweenness",
+		}
+	}
+}
+
+impl fmt::Display for Metric {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+impl str::FromStr for Metric {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Metric::ALL
+			.iter()
+			.find(|metric| metric.name() == s.to_lowercase())
+			.copied()
+			.ok_or_else(|| {
+				let names: Vec<&str> = Metric::ALL.iter().map(|m| m.name()).collect();
+				format!("Unknown metric \"{}\", expected one of {}", s, names.join(", "))
+			})
+	}
+}
+
+/// A note with its metrics. PageRank and betweenness are only computed when
+/// ranking by them.
+#[derive(Debug, Clone)]
+pub struct RankedNote {
+	pub note: NoteMeta,
+	pub in_degree: usize,
+	pub out_degree: usize,
+	pub pagerank: Option<f64>,
+	pub betweenness: Option<f64>,
+}
+
+impl RankedNote {
+	pub fn score(&self, metric: Metric) -> f64 {
+		match metric {
+			Metric::InDegree => self.in_degree as f64,
+			Metric::OutDegree => self.out_degree as f64,
+			Metric::PageRank => self.pagerank.unwrap_or(0.0),
+			Metric::Betweenness => self.betweenness.unwrap_or(0.0),
+		}
+	}
+}
+
+/// Get the notes with the highest score for the metric, at most `top` of
+/// them, or all notes if `top` is 0. Notes with the same score are sorted
+/// by title. Links to unknown notes are left out.
+pub fn rank_notes(graph: &NoteGraph, metric: Metric, top: usize) -> Vec<RankedNote> {
+	let graph = graph.without_missing();
+	let pagerank = (metric == Metric::PageRank).then(|| graph.get_pagerank());
+	let betweenness = (metric == Metric::Betweenness).then(|| graph.get_betweenness());
+
+	let mut ranked: Vec<RankedNote> = graph
+		.nodes()
+		.iter()
+		.enumerate()
+		.filter_map(|(index, node)| {
+			Some(RankedNote {
+				note: node.note()?.clone(),
+				in_degree: graph.incoming(index).len(),
+				out_degree: graph.outgoing(index).len(),
+				pagerank: pagerank.as_ref().map(|scores| scores[index]),
+				betweenness: betweenness.as_ref().map(|scores| scores[index]),
+			})
+		})
+		.collect();
+	ranked.sort_by(|a, b| {
+		b.score(metric)
+			.partial_cmp(&a.score(metric))
+			.unwrap_or(Ordering::Equal)
+			.then_with(|| a.note.title.to_lowercase().cmp(&b.note.title.to_lowercase()))
+	});
+	if top > 0 {
+		ranked.truncate(top);
+	}
+	ranked
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::graph::Node;
+	use crate::rank::*;
+
+	fn graph() -> NoteGraph {
+		let note = |title: &str| {
+			Node::Note(NoteMeta {
+				path: format!("/notes/{}.md", title),
+				stem: title.to_owned(),
+				extension: "md".to_owned(),
+				title: title.to_owned(),
+				id: None,
+				has_links: false,
+			})
+		};
+		// Hub links to A and b, that both link to Center
+		NoteGraph::new(
+			vec![note("Center"), note("Hub"), note("b"), note("A")],
+			vec![vec![], vec![2, 3], vec![0], vec![0]],
+		)
+	}
+
+	#[test]
+	fn rank_by_metric() {
+		let titles = |ranked: Vec<RankedNote>| -> Vec<String> { ranked.into_iter().map(|r| r.note.title).collect() };
+		assert_eq!(titles(rank_notes(&graph(), Metric::InDegree, 0)), ["Center", "A", "b", "Hub"]);
+		assert_eq!(titles(rank_notes(&graph(), Metric::OutDegree, 2)), ["Hub", "A"]);
+		assert_eq!(titles(rank_notes(&graph(), Metric::PageRank, 1)), ["Center"]);
+
+		let ranked = rank_notes(&graph(), Metric::Betweenness, 0);
+		assert_eq!(ranked[0].note.title, "A");
+		assert_eq!(ranked[0].betweenness, Some(0.5 / 6.0));
+		assert_eq!(ranked[0].pagerank, None);
+		assert_eq!(ranked[0].in_degree, 1);
+		assert_eq!(ranked[3].betweenness, Some(0.0));
+	}
+
+	#[test]
+	fn parse_metric() {
+		assert_eq!("PageRank".parse(), Ok(Metric::PageRank));
+		assert_eq!("in-degree".parse(), Ok(Metric::InDegree));
+		assert!("closeness".parse::<Metric>().is_err());
+	}
+}
@@ -0,0 +1,269 @@
+use crate::error::Report;
+use crate::ftree;
+use crate::note::NoteCollection;
+use ansi_term::Colour;
+use chrono::Local;
+use debug_print::debug_eprintln;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::{fs, path};
+
+/// Editors often save files in several steps, so wait for the changes to settle
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(500);
+
+/// Watch the notes directory, and update backlinks sections when notes are
+/// created, changed, renamed or deleted. Runs until the process is stopped.
+pub fn watch(
+	mut notes: NoteCollection,
+	root: &path::Path,
+	filter: &ftree::FileFilter,
+	aliased: bool,
+) -> Result<(), Box<dyn Error>> {
+	let (tx, rx) = mpsc::channel();
+	let mut watcher = notify::watcher(tx, DEBOUNCE_DELAY)?;
+	watcher.watch(root, RecursiveMode::Recursive)?;
+
+	// Contents of the files that we have written ourselves
+	let mut own_writes = HashMap::new();
+	update_backlinks(&mut notes, filter, aliased, &mut own_writes);
+	println!("Watching {} for changes. Press Ctrl-C to stop.", root.display());
+
+	loop {
+		let mut paths = get_event_paths(rx.recv()?, root);
+		// Handle events that arrive together at once
+		while let Ok(event) = rx.try_recv() {
+			paths.extend(get_event_paths(event, root));
+		}
+
+		let mut report = Report::default();
+		let files: Vec<path::PathBuf> = get_affected_files(&notes, root, filter, &paths, &mut report)
+			.into_iter()
+			.filter(|file| !is_own_write(&mut own_writes, file))
+			.collect();
+		report.print();
+		if files.is_empty() {
+			continue;
+		}
+
+		debug_eprintln!("Reloading {:?}", files);
+		notes.reload_files(filter, &files);
+		update_backlinks(&mut notes, filter, aliased, &mut own_writes);
+	}
+}
+
+fn update_backlinks(
+	notes: &mut NoteCollection,
+	filter: &ftree::FileFilter,
+	aliased: bool,
+	own_writes: &mut HashMap<path::PathBuf, Vec<u8>>,
+) {
+	// Each update is a run of its own, that `undo` restores
+	notes.restart_journal();
+	let updated = notes.update_backlinks(aliased);
+	notes.take_report().print();
+	if updated.is_empty() {
+		return;
+	}
+
+	println!(
+		"[{}] Updated backlinks section in {} notes",
+		Local::now().format("%H:%M:%S"),
+		updated.len()
+	);
+	let mut paths = Vec::with_capacity(updated.len());
+	for note in updated {
+		println!("- {}", note.get_wikilink_to());
+		let path = path::PathBuf::from(&note.path);
+		if let Ok(contents) = fs::read(&path) {
+			own_writes.insert(path.clone(), contents);
+		}
+		paths.push(path);
+	}
+
+	// Only the backlinks sections changed, so no links to other notes
+	notes.reload_files(filter, &paths);
+}
+
+/// Check if the file still has the contents that we wrote to it. The
+/// change events for our own writes must not trigger another update.
+fn is_own_write(own_writes: &mut HashMap<path::PathBuf, Vec<u8>>, file: &path::Path) -> bool {
+	match own_writes.remove(file) {
+		Some(contents) => fs::read(file).is_ok_and(|c| c == contents),
+		None => false,
+	}
+}
+
+fn get_event_paths(event: DebouncedEvent, root: &path::Path) -> Vec<path::PathBuf> {
+	match event {
+		DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Remove(path) => {
+			vec![path]
+		}
+		DebouncedEvent::Rename(from, to) => vec![from, to],
+		// Events were lost, so check everything
+		DebouncedEvent::Rescan => vec![root.to_path_buf()],
+		DebouncedEvent::Error(err, path) => {
+			eprintln!(
+				"{} Error while watching {}: {}",
+				Colour::Yellow.paint("Warning:"),
+				path.unwrap_or_default().display(),
+				err
+			);
+			Vec::new()
+		}
+		DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) | DebouncedEvent::Chmod(_) => {
+			Vec::new()
+		}
+	}
+}
+
+/// Get the files that need to be reloaded after changes to `paths`,
+/// including the files in directories that were created, moved or deleted.
+/// When an ignore file has changed, it's read again, and all files in its
+/// directory are affected. Directories that can't be read are added to
+/// `report`.
+pub fn get_affected_files(
+	notes: &NoteCollection,
+	root: &path::Path,
+	filter: &ftree::FileFilter,
+	paths: &[path::PathBuf],
+	report: &mut Report,
+) -> Vec<path::PathBuf> {
+	let known_files = notes.get_paths();
+	let mut files = Vec::new();
+	for path in paths {
+		let ignore_file_dir = path
+			.parent()
+			.filter(|_| ftree::IGNORE_FILE_NAMES.iter().any(|name| path.ends_with(name)));
+		let path = match ignore_file_dir {
+			Some(dir) => {
+				filter.forget_ignore_files(dir);
+				dir
+			}
+			None => path.as_path(),
+		};
+		if path.is_dir() {
+			let (note_paths, attachment_paths) = ftree::get_files(path, filter, report);
+			files.extend(note_paths);
+			files.extend(attachment_paths);
+			files.extend(known_files.iter().filter(|f| f.starts_with(path)).cloned());
+		} else {
+			// The path may also have been a directory that was moved or deleted
+			files.extend(known_files.iter().filter(|f| f.starts_with(path)).cloned());
+			if path.is_file() {
+				files.push(path.to_path_buf());
+			}
+		}
+	}
+
+	// Known files that are now ignored are removed when reloaded
+	files.retain(|file| filter.includes(root, file) || known_files.contains(file));
+	files.sort();
+	files.dedup();
+	files
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mdparse::NoteParser;
+	use crate::watch::*;
+	use std::env::temp_dir;
+
+	#[test]
+	fn affected_files() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-watch");
+		if dir.exists() {
+			fs::remove_dir_all(&dir).unwrap();
+		}
+		fs::create_dir_all(dir.join("old")).unwrap();
+		fs::write(dir.join("a.md"), "# A").unwrap();
+		fs::write(dir.join("old/b.md"), "# B").unwrap();
+		fs::write(dir.join("old/c.png"), "").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &["*.tmp".to_owned()]).unwrap();
+		let notes = NoteCollection::collect_files(
+			&dir,
+			&filter,
+			NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+			None,
+		);
+
+		// A directory is renamed
+		fs::rename(dir.join("old"), dir.join("new")).unwrap();
+		let files = get_affected_files(
+			&notes,
+			&dir,
+			&filter,
+			&[dir.join("old"), dir.join("new"), dir.join("x.tmp"), dir.join(".hidden.md")],
+			&mut Report::default(),
+		);
+		assert_eq!(
+			files,
+			[
+				dir.join("new/b.md"),
+				dir.join("new/c.png"),
+				dir.join("old/b.md"),
+				dir.join("old/c.png"),
+			]
+		);
+	}
+
+	#[test]
+	fn changed_ignore_files() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-watchignore");
+		if dir.exists() {
+			fs::remove_dir_all(&dir).unwrap();
+		}
+		fs::create_dir_all(dir.join("sub")).unwrap();
+		fs::write(dir.join("a.md"), "# A").unwrap();
+		fs::write(dir.join("sub/b.md"), "# B").unwrap();
+		fs::write(dir.join("sub/c.md"), "# C").unwrap();
+
+		let filter = ftree::FileFilter::new("md", &[]).unwrap();
+		let mut notes = NoteCollection::collect_files(
+			&dir,
+			&filter,
+			NoteParser::new(r"\d{14}", "## Backlinks").unwrap(),
+			None,
+		);
+		assert_eq!(notes.count(), 3);
+
+		let ignore_file = dir.join("sub/.noteexplorerignore");
+		fs::write(&ignore_file, "b.md\n").unwrap();
+		let files = get_affected_files(&notes, &dir, &filter, std::slice::from_ref(&ignore_file), &mut Report::default());
+		assert_eq!(files, [dir.join("sub/b.md"), dir.join("sub/c.md")]);
+		notes.reload_files(&filter, &files);
+		assert_eq!(notes.count(), 2);
+		assert!(notes.get_note(&dir.join("sub/b.md")).is_none());
+
+		fs::remove_file(&ignore_file).unwrap();
+		let files = get_affected_files(&notes, &dir, &filter, &[ignore_file], &mut Report::default());
+		notes.reload_files(&filter, &files);
+		assert_eq!(notes.count(), 3);
+	}
+
+	#[test]
+	fn own_writes() {
+		let mut dir = temp_dir();
+		dir.push("noteexplorer-test-ownwrites");
+		fs::create_dir_all(&dir).unwrap();
+		let file = dir.join("a.md");
+		fs::write(&file, "# A").unwrap();
+
+		let mut own_writes = HashMap::new();
+		assert!(!is_own_write(&mut own_writes, &file));
+
+		own_writes.insert(file.clone(), b"# A".to_vec());
+		assert!(is_own_write(&mut own_writes, &file));
+		// Only the first event after the write is ignored
+		assert!(!is_own_write(&mut own_writes, &file));
+
+		own_writes.insert(file.clone(), b"# A".to_vec());
+		fs::write(&file, "# A, edited").unwrap();
+		assert!(!is_own_write(&mut own_writes, &file));
+	}
+}